@@ -1,5 +1,5 @@
 use datafusion::prelude::SessionContext;
-use datafusion_loki::LokiLogTable;
+use datafusion_loki::{LokiLogTable, register_loki_udfs};
 use std::sync::Arc;
 
 #[tokio::main]
@@ -7,7 +7,8 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let loki_table = LokiLogTable::try_new("http://localhost:33100")?;
     // let loki_table = LokiLogTable::try_new("http://192.168.0.159:42705")?;
 
-    let ctx = SessionContext::new();
+    let mut ctx = SessionContext::new();
+    register_loki_udfs(&mut ctx)?;
     ctx.register_table("loki", Arc::new(loki_table))?;
 
     ctx.sql(
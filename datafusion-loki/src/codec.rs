@@ -1,12 +1,22 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use datafusion_common::{internal_datafusion_err, internal_err, not_impl_err};
+use arrow::datatypes::{SchemaRef, TimeUnit};
+use datafusion_catalog::TableProvider;
+use datafusion_common::{TableReference, internal_datafusion_err, internal_err, not_impl_err};
 use datafusion_execution::TaskContext;
+use datafusion_expr::{Extension, LogicalPlan, ScalarUDF};
 use datafusion_physical_plan::ExecutionPlan;
-use datafusion_proto::physical_plan::PhysicalExtensionCodec;
+use datafusion_proto::{
+    logical_plan::LogicalExtensionCodec, physical_plan::PhysicalExtensionCodec,
+};
 use prost::Message;
 
-use crate::{DFResult, LokiLogInsertExec, LokiLogScanExec, protobuf};
+use crate::{
+    DEFAULT_BATCH_SIZE, DEFAULT_MAX_PUSH_CONCURRENCY, DEFAULT_PUSH_CONTENT_TYPE, DFResult,
+    DuplicateLabelPolicy, LokiBasicAuth, LokiDeleteRequest, LokiDirection, LokiHttpOptions,
+    LokiLogInsertExec, LokiLogScanExec, LokiLogTable, LokiLogTailExec, LokiMetricScanExec,
+    PartitionErrorPolicy, loki_udf, protobuf,
+};
 
 #[derive(Debug, Clone)]
 pub struct LokiPhysicalCodec;
@@ -30,14 +40,44 @@ impl PhysicalExtensionCodec for LokiPhysicalCodec {
         match loki_plan {
             protobuf::loki_physical_plan_node::LokiPhysicalPlanType::Scan(proto) => {
                 let projection = parse_projection(proto.projection.as_ref());
+                let partition_error_policy =
+                    parse_partition_error_policy(proto.partition_error_policy());
+                let direction = parse_direction(proto.direction());
                 let exec = LokiLogScanExec::try_new(
                     proto.endpoint,
                     proto.log_query,
                     proto.start,
                     proto.end,
-                    projection,
+                    None,
                     proto.limit.map(|l| l as usize),
                 )?;
+                let exec = match proto.schema.as_ref() {
+                    Some(schema) => exec.with_schema(Arc::new(schema.try_into()?))?,
+                    None => exec,
+                };
+                let exec = exec
+                    .with_query_tags(proto.query_tags)
+                    .with_structured_metadata(proto.structured_metadata)?
+                    .with_age_reference(proto.age_reference)?
+                    .with_source_column(proto.source_column.map(|c| (c.name, c.value)))?
+                    .with_truncation_flag(proto.truncation_flag)?
+                    .with_partition_error_policy(partition_error_policy)
+                    .with_http_options(parse_http_options(proto.http_options))
+                    .with_target_partitions(proto.target_partitions as usize)?
+                    .with_max_entries_limit(proto.max_entries_limit.map(|l| l as usize))
+                    .with_direction(direction)
+                    .with_batch_size(match proto.batch_size {
+                        // Absent from plans serialized before the field existed
+                        0 => DEFAULT_BATCH_SIZE,
+                        batch_size => batch_size as usize,
+                    })
+                    .with_fail_on_empty(proto.fail_on_empty)
+                    .with_step(proto.step)
+                    .with_timestamp_range(proto.timestamp_range.map(|r| (r.min, r.max)))
+                    // Absent from plans serialized before the field existed
+                    .with_response_compression(proto.response_compression.unwrap_or(false))
+                    .with_max_labels_per_stream(proto.max_labels_per_stream.map(|m| m as usize))
+                    .with_projection(projection)?;
                 Ok(Arc::new(exec))
             }
             protobuf::loki_physical_plan_node::LokiPhysicalPlanType::Insert(proto) => {
@@ -46,9 +86,70 @@ impl PhysicalExtensionCodec for LokiPhysicalCodec {
                 }
 
                 let input = inputs[0].clone();
-                let exec = LokiLogInsertExec::try_new(input, proto.endpoint)?;
+                let duplicate_label_policy =
+                    parse_duplicate_label_policy(proto.duplicate_label_policy());
+                let exec = LokiLogInsertExec::try_new(input, proto.endpoint)?
+                    .with_http_options(parse_http_options(proto.http_options))
+                    .with_duplicate_label_policy(duplicate_label_policy)
+                    .with_clock_skew_tolerance(
+                        proto.clock_skew_tolerance_ms.map(Duration::from_millis),
+                    )
+                    .with_push_content_type(
+                        proto
+                            .push_content_type
+                            .unwrap_or_else(|| DEFAULT_PUSH_CONTENT_TYPE.to_string()),
+                    )
+                    .with_max_push_bytes(proto.max_push_bytes.map(|n| n as usize))
+                    .with_max_push_lines(proto.max_push_lines.map(|n| n as usize))
+                    .with_push_compression(proto.push_compression)
+                    .with_max_push_concurrency(
+                        proto
+                            .max_push_concurrency
+                            .map_or(DEFAULT_MAX_PUSH_CONCURRENCY, |n| n as usize),
+                    )?
+                    .with_detailed_result(proto.detailed_result)
+                    .with_overwrite_delete(proto.overwrite_delete.map(|request| {
+                        LokiDeleteRequest {
+                            query: request.query,
+                            start: request.start,
+                            end: request.end,
+                            timeout: Duration::from_millis(request.timeout_ms),
+                        }
+                    }));
                 Ok(Arc::new(exec))
             }
+            protobuf::loki_physical_plan_node::LokiPhysicalPlanType::MetricScan(proto) => {
+                let Some(schema) = proto.schema.as_ref() else {
+                    return internal_err!("LokiMetricScanExec schema is missing");
+                };
+                let exec = LokiMetricScanExec::try_new(
+                    proto.endpoint,
+                    proto.log_query,
+                    proto.start,
+                    proto.end,
+                    proto.group_labels,
+                    Arc::new(schema.try_into()?),
+                )?
+                .with_query_tags(proto.query_tags)
+                .with_http_options(parse_http_options(proto.http_options));
+                Ok(Arc::new(exec))
+            }
+            protobuf::loki_physical_plan_node::LokiPhysicalPlanType::Tail(proto) => {
+                let exec = LokiLogTailExec::try_new(proto.endpoint, proto.log_query)?
+                    .with_start(proto.start)
+                    .with_max_duration(proto.max_duration_ms.map(Duration::from_millis))
+                    .with_max_lines(proto.max_lines.map(|n| n as usize))
+                    .with_http_options(parse_http_options(proto.http_options));
+                Ok(Arc::new(exec))
+            }
+        }
+    }
+
+    fn try_decode_udf(&self, name: &str, _buf: &[u8]) -> DFResult<Arc<ScalarUDF>> {
+        // Reached for functions the decoding node didn't register
+        match loki_udf(name) {
+            Some(udf) => Ok(udf),
+            None => not_impl_err!("LokiPhysicalCodec does not support decoding udf {name}"),
         }
     }
 
@@ -66,6 +167,33 @@ impl PhysicalExtensionCodec for LokiPhysicalCodec {
                             end: exec.end,
                             projection,
                             limit: exec.limit.map(|l| l as i32),
+                            schema: Some(exec.schema.as_ref().try_into()?),
+                            query_tags: exec.query_tags.clone(),
+                            age_reference: exec.age_reference,
+                            structured_metadata: exec.structured_metadata,
+                            partition_error_policy: serialize_partition_error_policy(
+                                exec.partition_error_policy,
+                            )
+                            .into(),
+                            http_options: Some(serialize_http_options(&exec.http_options)),
+                            target_partitions: exec.target_partitions as u32,
+                            max_entries_limit: exec.max_entries_limit.map(|l| l as u64),
+                            direction: serialize_direction(exec.direction).into(),
+                            batch_size: exec.batch_size as u64,
+                            fail_on_empty: exec.fail_on_empty,
+                            max_labels_per_stream: exec.max_labels_per_stream.map(|m| m as u64),
+                            truncation_flag: exec.truncation_flag,
+                            step: exec.step.clone(),
+                            response_compression: Some(exec.response_compression),
+                            timestamp_range: exec
+                                .timestamp_range
+                                .map(|(min, max)| protobuf::TimestampRange { min, max }),
+                            source_column: exec.source_column.as_ref().map(|(name, value)| {
+                                protobuf::SourceColumn {
+                                    name: name.clone(),
+                                    value: value.clone(),
+                                }
+                            }),
                         },
                     ),
                 ),
@@ -81,13 +209,77 @@ impl PhysicalExtensionCodec for LokiPhysicalCodec {
                     protobuf::loki_physical_plan_node::LokiPhysicalPlanType::Insert(
                         protobuf::LokiLogInsertExec {
                             endpoint: exec.endpoint.clone(),
+                            http_options: Some(serialize_http_options(&exec.http_options)),
+                            duplicate_label_policy: serialize_duplicate_label_policy(
+                                exec.duplicate_label_policy,
+                            )
+                            .into(),
+                            clock_skew_tolerance_ms: exec
+                                .clock_skew_tolerance
+                                .map(|t| t.as_millis() as u64),
+                            push_content_type: Some(exec.push_content_type.clone()),
+                            max_push_bytes: exec.max_push_bytes.map(|n| n as u64),
+                            max_push_lines: exec.max_push_lines.map(|n| n as u64),
+                            push_compression: exec.push_compression,
+                            max_push_concurrency: Some(exec.max_push_concurrency as u64),
+                            detailed_result: exec.detailed_result,
+                            overwrite_delete: exec.overwrite_delete.as_ref().map(|request| {
+                                protobuf::LokiDeleteRequest {
+                                    query: request.query.clone(),
+                                    start: request.start,
+                                    end: request.end,
+                                    timeout_ms: request.timeout.as_millis() as u64,
+                                }
+                            }),
                         },
                     ),
                 ),
             };
 
             proto.encode(buf).map_err(|e| {
-                internal_datafusion_err!("Failed to encode loki log scan exec plan: {e:?}")
+                internal_datafusion_err!("Failed to encode loki log insert exec plan: {e:?}")
+            })?;
+            Ok(())
+        } else if let Some(exec) = node.as_any().downcast_ref::<LokiMetricScanExec>() {
+            let proto = protobuf::LokiPhysicalPlanNode {
+                loki_physical_plan_type: Some(
+                    protobuf::loki_physical_plan_node::LokiPhysicalPlanType::MetricScan(
+                        protobuf::LokiMetricScanExec {
+                            endpoint: exec.endpoint.clone(),
+                            log_query: exec.log_query.clone(),
+                            start: exec.start,
+                            end: exec.end,
+                            group_labels: exec.group_labels.clone(),
+                            schema: Some(exec.schema.as_ref().try_into()?),
+                            query_tags: exec.query_tags.clone(),
+                            http_options: Some(serialize_http_options(&exec.http_options)),
+                        },
+                    ),
+                ),
+            };
+
+            proto.encode(buf).map_err(|e| {
+                internal_datafusion_err!("Failed to encode loki metric scan exec plan: {e:?}")
+            })?;
+            Ok(())
+        } else if let Some(exec) = node.as_any().downcast_ref::<LokiLogTailExec>() {
+            let proto = protobuf::LokiPhysicalPlanNode {
+                loki_physical_plan_type: Some(
+                    protobuf::loki_physical_plan_node::LokiPhysicalPlanType::Tail(
+                        protobuf::LokiLogTailExec {
+                            endpoint: exec.endpoint.clone(),
+                            log_query: exec.log_query.clone(),
+                            start: exec.start,
+                            http_options: Some(serialize_http_options(&exec.http_options)),
+                            max_duration_ms: exec.max_duration.map(|d| d.as_millis() as u64),
+                            max_lines: exec.max_lines.map(|n| n as u64),
+                        },
+                    ),
+                ),
+            };
+
+            proto.encode(buf).map_err(|e| {
+                internal_datafusion_err!("Failed to encode loki log tail exec plan: {e:?}")
             })?;
             Ok(())
         } else {
@@ -99,6 +291,160 @@ impl PhysicalExtensionCodec for LokiPhysicalCodec {
     }
 }
 
+/// Serializes [`LokiLogTable`]s referenced by logical plans, e.g. shipped to remote
+/// executors by distributed engines.
+///
+/// The request middleware of a table isn't serialized, and decoded tables build their
+/// own http client.
+#[derive(Debug, Clone)]
+pub struct LokiLogicalCodec;
+
+impl LogicalExtensionCodec for LokiLogicalCodec {
+    fn try_decode(
+        &self,
+        _buf: &[u8],
+        _inputs: &[LogicalPlan],
+        _ctx: &TaskContext,
+    ) -> DFResult<Extension> {
+        not_impl_err!("LokiLogicalCodec does not support decoding extension nodes")
+    }
+
+    fn try_encode(&self, _node: &Extension, _buf: &mut Vec<u8>) -> DFResult<()> {
+        not_impl_err!("LokiLogicalCodec does not support encoding extension nodes")
+    }
+
+    fn try_decode_udf(&self, name: &str, _buf: &[u8]) -> DFResult<Arc<ScalarUDF>> {
+        match loki_udf(name) {
+            Some(udf) => Ok(udf),
+            None => not_impl_err!("LokiLogicalCodec does not support decoding udf {name}"),
+        }
+    }
+
+    fn try_decode_table_provider(
+        &self,
+        buf: &[u8],
+        _table_ref: &TableReference,
+        _schema: SchemaRef,
+        _ctx: &TaskContext,
+    ) -> DFResult<Arc<dyn TableProvider>> {
+        let proto = protobuf::LokiLogTable::decode(buf)
+            .map_err(|e| internal_datafusion_err!("Failed to decode loki log table: {e:?}"))?;
+        let duplicate_label_policy = parse_duplicate_label_policy(proto.duplicate_label_policy());
+        let timestamp_unit = parse_timestamp_unit(proto.timestamp_unit());
+        let partition_error_policy = parse_partition_error_policy(proto.partition_error_policy());
+
+        let mut table = LokiLogTable::try_new(proto.endpoint)?
+            .with_insert_default_labels(proto.insert_default_labels)?;
+        if let Some(schema) = proto.schema.as_ref() {
+            table.schema = Arc::new(schema.try_into()?);
+        }
+        table.default_label = proto.default_label;
+        table.mandatory_selector = proto.mandatory_selector;
+        table.query_tags = proto.query_tags;
+        table.int64_timestamp_as_nanos = proto.int64_timestamp_as_nanos;
+        table.age_column = proto.age_column;
+        table.default_start = proto.default_start;
+        table.default_end = proto.default_end;
+        table.default_lookback = Duration::from_millis(proto.default_lookback_ms);
+        table.structured_metadata_column = proto.structured_metadata_column;
+        table.source_column = proto.source_column.map(|c| (c.name, c.value));
+        table.duplicate_label_policy = duplicate_label_policy;
+        table.clock_skew_tolerance = proto.clock_skew_tolerance_ms.map(Duration::from_millis);
+        table.push_content_type = proto.push_content_type;
+        table.max_push_bytes = proto.max_push_bytes.map(|n| n as usize);
+        table.max_push_lines = proto.max_push_lines.map(|n| n as usize);
+        table.push_compression = proto.push_compression;
+        table.max_push_concurrency = proto.max_push_concurrency as usize;
+        table.detailed_insert_result = proto.detailed_insert_result;
+        table.pushdown_verification = proto.pushdown_verification;
+        table.dictionary_labels = proto.dictionary_labels;
+        table.timestamp_unit = timestamp_unit;
+        table.sorted_labels = proto.sorted_labels;
+        table.partition_error_policy = partition_error_policy;
+        table.http_options = parse_http_options(proto.http_options);
+        table.target_partitions = proto.target_partitions.map(|n| n as usize);
+        table.scan_batch_size = proto.scan_batch_size.map(|n| n as usize);
+        table.max_entries_limit = proto.max_entries_limit.map(|n| n as usize);
+        table.fail_on_empty = proto.fail_on_empty;
+        table.max_labels_per_stream = proto.max_labels_per_stream.map(|n| n as usize);
+        table.truncation_flag = proto.truncation_flag;
+        table.response_compression = proto.response_compression;
+        table.overwrite_delete_timeout =
+            proto.overwrite_delete_timeout_ms.map(Duration::from_millis);
+        table.timestamp_statistics = proto.timestamp_statistics;
+        table.tail_max_duration = proto.tail_max_duration_ms.map(Duration::from_millis);
+        table.tail_max_lines = proto.tail_max_lines.map(|n| n as usize);
+        Ok(Arc::new(table))
+    }
+
+    fn try_encode_table_provider(
+        &self,
+        table_ref: &TableReference,
+        node: Arc<dyn TableProvider>,
+        buf: &mut Vec<u8>,
+    ) -> DFResult<()> {
+        let Some(table) = node.as_any().downcast_ref::<LokiLogTable>() else {
+            return not_impl_err!(
+                "LokiLogicalCodec does not support encoding table provider of {table_ref}"
+            );
+        };
+        let proto = protobuf::LokiLogTable {
+            endpoint: table.endpoint.clone(),
+            default_label: table.default_label.clone(),
+            mandatory_selector: table.mandatory_selector.clone(),
+            schema: Some(table.schema.as_ref().try_into()?),
+            query_tags: table.query_tags.clone(),
+            int64_timestamp_as_nanos: table.int64_timestamp_as_nanos,
+            age_column: table.age_column,
+            default_start: table.default_start,
+            default_end: table.default_end,
+            default_lookback_ms: table.default_lookback.as_millis() as u64,
+            structured_metadata_column: table.structured_metadata_column,
+            source_column: table.source_column.as_ref().map(|(name, value)| {
+                protobuf::SourceColumn {
+                    name: name.clone(),
+                    value: value.clone(),
+                }
+            }),
+            insert_default_labels: table.insert_default_labels.clone(),
+            duplicate_label_policy: serialize_duplicate_label_policy(table.duplicate_label_policy)
+                .into(),
+            clock_skew_tolerance_ms: table.clock_skew_tolerance.map(|t| t.as_millis() as u64),
+            push_content_type: table.push_content_type.clone(),
+            max_push_bytes: table.max_push_bytes.map(|n| n as u64),
+            max_push_lines: table.max_push_lines.map(|n| n as u64),
+            push_compression: table.push_compression,
+            max_push_concurrency: table.max_push_concurrency as u64,
+            detailed_insert_result: table.detailed_insert_result,
+            pushdown_verification: table.pushdown_verification,
+            dictionary_labels: table.dictionary_labels,
+            timestamp_unit: serialize_timestamp_unit(table.timestamp_unit).into(),
+            sorted_labels: table.sorted_labels,
+            partition_error_policy: serialize_partition_error_policy(table.partition_error_policy)
+                .into(),
+            http_options: Some(serialize_http_options(&table.http_options)),
+            target_partitions: table.target_partitions.map(|n| n as u64),
+            scan_batch_size: table.scan_batch_size.map(|n| n as u64),
+            max_entries_limit: table.max_entries_limit.map(|n| n as u64),
+            fail_on_empty: table.fail_on_empty,
+            max_labels_per_stream: table.max_labels_per_stream.map(|n| n as u64),
+            truncation_flag: table.truncation_flag,
+            response_compression: table.response_compression,
+            overwrite_delete_timeout_ms: table
+                .overwrite_delete_timeout
+                .map(|t| t.as_millis() as u64),
+            timestamp_statistics: table.timestamp_statistics,
+            tail_max_duration_ms: table.tail_max_duration.map(|d| d.as_millis() as u64),
+            tail_max_lines: table.tail_max_lines.map(|n| n as u64),
+        };
+
+        proto
+            .encode(buf)
+            .map_err(|e| internal_datafusion_err!("Failed to encode loki log table: {e:?}"))?;
+        Ok(())
+    }
+}
+
 fn serialize_projection(projection: Option<&Vec<usize>>) -> Option<protobuf::Projection> {
     projection.map(|p| protobuf::Projection {
         projection: p.iter().map(|n| *n as u32).collect(),
@@ -108,3 +454,117 @@ fn serialize_projection(projection: Option<&Vec<usize>>) -> Option<protobuf::Pro
 fn parse_projection(projection: Option<&protobuf::Projection>) -> Option<Vec<usize>> {
     projection.map(|p| p.projection.iter().map(|n| *n as usize).collect())
 }
+
+fn serialize_partition_error_policy(
+    policy: PartitionErrorPolicy,
+) -> protobuf::PartitionErrorPolicy {
+    match policy {
+        PartitionErrorPolicy::FailFast => protobuf::PartitionErrorPolicy::FailFast,
+        PartitionErrorPolicy::SkipAndWarn => protobuf::PartitionErrorPolicy::SkipAndWarn,
+    }
+}
+
+fn parse_partition_error_policy(policy: protobuf::PartitionErrorPolicy) -> PartitionErrorPolicy {
+    match policy {
+        protobuf::PartitionErrorPolicy::FailFast => PartitionErrorPolicy::FailFast,
+        protobuf::PartitionErrorPolicy::SkipAndWarn => PartitionErrorPolicy::SkipAndWarn,
+    }
+}
+
+fn serialize_direction(direction: LokiDirection) -> protobuf::LokiDirection {
+    match direction {
+        LokiDirection::Forward => protobuf::LokiDirection::Forward,
+        LokiDirection::Backward => protobuf::LokiDirection::Backward,
+    }
+}
+
+fn parse_direction(direction: protobuf::LokiDirection) -> LokiDirection {
+    match direction {
+        protobuf::LokiDirection::Forward => LokiDirection::Forward,
+        protobuf::LokiDirection::Backward => LokiDirection::Backward,
+    }
+}
+
+fn serialize_duplicate_label_policy(
+    policy: DuplicateLabelPolicy,
+) -> protobuf::DuplicateLabelPolicy {
+    match policy {
+        DuplicateLabelPolicy::KeepFirst => protobuf::DuplicateLabelPolicy::KeepFirst,
+        DuplicateLabelPolicy::KeepLast => protobuf::DuplicateLabelPolicy::KeepLast,
+        DuplicateLabelPolicy::Error => protobuf::DuplicateLabelPolicy::Error,
+    }
+}
+
+fn parse_duplicate_label_policy(policy: protobuf::DuplicateLabelPolicy) -> DuplicateLabelPolicy {
+    match policy {
+        protobuf::DuplicateLabelPolicy::KeepFirst => DuplicateLabelPolicy::KeepFirst,
+        protobuf::DuplicateLabelPolicy::KeepLast => DuplicateLabelPolicy::KeepLast,
+        protobuf::DuplicateLabelPolicy::Error => DuplicateLabelPolicy::Error,
+    }
+}
+
+fn serialize_timestamp_unit(unit: TimeUnit) -> protobuf::TimestampUnit {
+    match unit {
+        TimeUnit::Nanosecond => protobuf::TimestampUnit::Nanosecond,
+        TimeUnit::Microsecond => protobuf::TimestampUnit::Microsecond,
+        TimeUnit::Millisecond => protobuf::TimestampUnit::Millisecond,
+        TimeUnit::Second => protobuf::TimestampUnit::Second,
+    }
+}
+
+fn parse_timestamp_unit(unit: protobuf::TimestampUnit) -> TimeUnit {
+    match unit {
+        protobuf::TimestampUnit::Nanosecond => TimeUnit::Nanosecond,
+        protobuf::TimestampUnit::Microsecond => TimeUnit::Microsecond,
+        protobuf::TimestampUnit::Millisecond => TimeUnit::Millisecond,
+        protobuf::TimestampUnit::Second => TimeUnit::Second,
+    }
+}
+
+fn serialize_http_options(http_options: &LokiHttpOptions) -> protobuf::LokiHttpOptions {
+    // Sorted for a deterministic encoding
+    let mut headers = http_options
+        .headers
+        .iter()
+        .map(|(name, value)| protobuf::LokiHttpHeader {
+            name: name.clone(),
+            value: value.clone(),
+        })
+        .collect::<Vec<_>>();
+    headers.sort_by(|a, b| a.name.cmp(&b.name));
+
+    protobuf::LokiHttpOptions {
+        bearer_token: http_options.bearer_token.clone(),
+        basic_auth: http_options
+            .basic_auth
+            .as_ref()
+            .map(|basic_auth| protobuf::LokiBasicAuth {
+                username: basic_auth.username.clone(),
+                password: basic_auth.password.clone(),
+            }),
+        tenant: http_options.tenant.clone(),
+        headers,
+        timeout_ms: http_options.timeout.map(|t| t.as_millis() as u64),
+    }
+}
+
+fn parse_http_options(http_options: Option<protobuf::LokiHttpOptions>) -> LokiHttpOptions {
+    let Some(http_options) = http_options else {
+        return LokiHttpOptions::default();
+    };
+    LokiHttpOptions {
+        bearer_token: http_options.bearer_token,
+        basic_auth: http_options.basic_auth.map(|basic_auth| LokiBasicAuth {
+            username: basic_auth.username,
+            password: basic_auth.password,
+        }),
+        tenant: http_options.tenant,
+        headers: http_options
+            .headers
+            .into_iter()
+            .map(|header| (header.name, header.value))
+            .collect(),
+        timeout: http_options.timeout_ms.map(Duration::from_millis),
+        middleware: None,
+    }
+}
@@ -1,48 +1,212 @@
 use std::sync::LazyLock;
 
+use arrow::datatypes::DataType;
 use datafusion_common::ScalarValue;
-use datafusion_expr::{BinaryExpr, Expr, Like, Operator, ScalarUDFImpl, expr::ScalarFunction};
+use datafusion_expr::{
+    Between, BinaryExpr, Case, Cast, Expr, Like, Operator, ScalarUDFImpl,
+    expr::{InList, ScalarFunction},
+};
 use datafusion_functions::core::getfield::GetFieldFunc;
 
-use crate::{LABELS_FIELD_REF, LINE_FIELD_REF, TIMESTAMP_FIELD_REF};
+use crate::{
+    AGE_FIELD_REF, LABELS_FIELD_REF, LINE_FIELD_REF, STRUCTURED_METADATA_FIELD_REF,
+    TIMESTAMP_FIELD_REF,
+};
 
 static GET_FIELD_FUNC: LazyLock<GetFieldFunc> = LazyLock::new(GetFieldFunc::new);
 
 pub fn expr_to_label_filter(expr: &Expr) -> Option<String> {
-    if let Expr::BinaryExpr(BinaryExpr { left, op, right }) = expr {
-        let Expr::ScalarFunction(ScalarFunction { func, args }) = left.as_ref() else {
-            return None;
-        };
-        if func.name() != GET_FIELD_FUNC.name() {
-            return None;
+    if let Expr::InList(in_list) = expr {
+        return in_list_to_label_filter(in_list);
+    }
+    if let Some((label, contained)) = parse_label_presence(expr) {
+        let matcher = if contained { "=~\".+\"" } else { "=\"\"" };
+        return Some(format!("{label}{matcher}"));
+    }
+    let (label, op, value) =
+        parse_label_matcher(expr).or_else(|| parse_coalesced_label_matcher(expr))?;
+    Some(format!("{label}{op}\"{}\"", escape_string(value)))
+}
+
+/// Parses `coalesce(labels['app'], 'none') <op> 'value'`, which DataFusion simplifies to a
+/// `CASE WHEN labels['app'] IS NOT NULL ...`, into a matcher on the label.
+///
+/// Only `=` and `!=` against a value other than the fallback are supported, where logs missing
+/// the label are filtered exactly like loki does: `app="x"` never matches them, `app!="x"` always.
+fn parse_coalesced_label_matcher(expr: &Expr) -> Option<(&str, &'static str, &str)> {
+    let Expr::BinaryExpr(BinaryExpr { left, op, right }) = expr else {
+        return None;
+    };
+    let (label, fallback) = parse_coalesced_map_entry(left, LABELS_FIELD_REF.name())?;
+    let Expr::Literal(ScalarValue::Utf8(Some(value)), _) = right.as_ref() else {
+        return None;
+    };
+    if value == fallback {
+        return None;
+    }
+    let op = match op {
+        Operator::Eq => "=",
+        Operator::NotEq => "!=",
+        _ => return None,
+    };
+    Some((label, op, value))
+}
+
+/// Parses `coalesce(column[key], 'fallback')` into the key and fallback.
+fn parse_coalesced_map_entry<'a>(expr: &'a Expr, column: &str) -> Option<(&'a str, &'a str)> {
+    let (entry, fallback) = match expr {
+        Expr::ScalarFunction(ScalarFunction { func, args }) if func.name() == "coalesce" => {
+            match args.as_slice() {
+                [entry, fallback] => (entry, fallback),
+                _ => return None,
+            }
         }
-        if args.len() != 2 {
-            return None;
+        Expr::Case(Case {
+            expr: None,
+            when_then_expr,
+            else_expr: Some(fallback),
+        }) => match when_then_expr.as_slice() {
+            [(when, then)] if matches!(when.as_ref(), Expr::IsNotNull(e) if e == then) => {
+                (then.as_ref(), fallback.as_ref())
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let key = parse_map_entry_key(entry, column)?;
+    let Expr::Literal(ScalarValue::Utf8(Some(fallback)), _) = fallback else {
+        return None;
+    };
+    Some((key, fallback))
+}
+
+/// Parses `map_contains_key(labels, 'app')`, optionally negated or compared to a boolean,
+/// into the label and whether it should be present.
+fn parse_label_presence(expr: &Expr) -> Option<(&str, bool)> {
+    match expr {
+        Expr::Not(expr) => parse_label_presence(expr).map(|(label, present)| (label, !present)),
+        Expr::BinaryExpr(BinaryExpr { left, op, right }) => {
+            let Expr::Literal(ScalarValue::Boolean(Some(value)), _) = right.as_ref() else {
+                return None;
+            };
+            let (label, present) = parse_label_presence(left)?;
+            match op {
+                Operator::Eq | Operator::IsNotDistinctFrom => Some((label, present == *value)),
+                Operator::NotEq | Operator::IsDistinctFrom => Some((label, present != *value)),
+                _ => None,
+            }
         }
-        let label = match (&args[0], &args[1]) {
-            (Expr::Column(col), Expr::Literal(ScalarValue::Utf8(value), _))
-                if col.name() == LABELS_FIELD_REF.name() =>
-            {
-                value.as_ref()
+        Expr::ScalarFunction(ScalarFunction { func, args })
+            if func.name() == "map_contains_key" =>
+        {
+            match args.as_slice() {
+                [
+                    Expr::Column(col),
+                    Expr::Literal(ScalarValue::Utf8(Some(label)), _),
+                ] if col.name() == LABELS_FIELD_REF.name() => Some((label.as_str(), true)),
+                _ => None,
             }
-            _ => None,
-        }?;
+        }
+        _ => None,
+    }
+}
 
-        let Expr::Literal(ScalarValue::Utf8(value), _) = right.as_ref() else {
-            return None;
-        };
-        let empty_string = String::new();
-        let value = value.as_ref().unwrap_or(&empty_string);
+/// Parses `labels['app'] <op> 'value'` into the label, LogQL matcher operator and value.
+pub fn parse_label_matcher(expr: &Expr) -> Option<(&str, &'static str, &str)> {
+    parse_map_entry_matcher(expr, LABELS_FIELD_REF.name())
+}
 
-        match op {
-            Operator::Eq => Some(format!("{label}=\"{value}\"")),
-            Operator::NotEq => Some(format!("{label}!=\"{value}\"")),
-            Operator::RegexMatch => Some(format!("{label}=~\"{value}\"")),
-            Operator::RegexNotMatch => Some(format!("{label}!~\"{value}\"")),
+/// Converts `labels['app'] in ('api', 'web')` to the regex matcher `app=~"api|web"`,
+/// loki anchoring regex matchers to the whole value.
+fn in_list_to_label_filter(in_list: &InList) -> Option<String> {
+    let label = parse_map_entry_key(&in_list.expr, LABELS_FIELD_REF.name())?;
+    if in_list.list.is_empty() {
+        return None;
+    }
+    let values = in_list
+        .list
+        .iter()
+        .map(|value| match value {
+            Expr::Literal(ScalarValue::Utf8(Some(value)), _) => Some(escape_regex(value)),
             _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let op = if in_list.negated { "!~" } else { "=~" };
+    Some(format!(
+        "{label}{op}\"{}\"",
+        escape_string(&values.join("|"))
+    ))
+}
+
+/// Converts the values a label must not equal to a single matcher, a negative regex
+/// `app!~"api|web"` for several values rather than one `!=` matcher per value.
+pub fn negated_label_filter(label: &str, values: &[&str]) -> String {
+    let mut distinct = Vec::with_capacity(values.len());
+    for value in values {
+        if !distinct.contains(value) {
+            distinct.push(*value);
+        }
+    }
+    match distinct.as_slice() {
+        [value] => format!("{label}!=\"{}\"", escape_string(value)),
+        _ => {
+            let values = distinct.iter().map(|v| escape_regex(v)).collect::<Vec<_>>();
+            format!("{label}!~\"{}\"", escape_string(&values.join("|")))
         }
-    } else {
-        None
+    }
+}
+
+/// Converts a predicate on a structured metadata entry, e.g.
+/// `structured_metadata['trace_id'] = 'abc'`, to a label filter stage `| trace_id="abc"`.
+///
+/// The stage also matches stream labels of the same name, so the predicate should be
+/// re-verified on the returned logs.
+pub fn expr_to_structured_metadata_filter(expr: &Expr) -> Option<String> {
+    let (key, op, value) = parse_map_entry_matcher(expr, STRUCTURED_METADATA_FIELD_REF.name())?;
+    Some(format!("| {key}{op}\"{value}\""))
+}
+
+/// Parses `column[key] <op> value` into the key, LogQL matcher operator and value.
+fn parse_map_entry_matcher<'a>(
+    expr: &'a Expr,
+    column: &str,
+) -> Option<(&'a str, &'static str, &'a str)> {
+    let Expr::BinaryExpr(BinaryExpr { left, op, right }) = expr else {
+        return None;
+    };
+    let key = parse_map_entry_key(left, column)?;
+
+    let Expr::Literal(ScalarValue::Utf8(value), _) = right.as_ref() else {
+        return None;
+    };
+    let value = value.as_deref().unwrap_or_default();
+
+    let op = match op {
+        Operator::Eq => "=",
+        Operator::NotEq => "!=",
+        Operator::RegexMatch => "=~",
+        Operator::RegexNotMatch => "!~",
+        _ => return None,
+    };
+    Some((key, op, value))
+}
+
+/// Parses `column[key]` or `map_get(column, key)` into the key.
+fn parse_map_entry_key<'a>(expr: &'a Expr, column: &str) -> Option<&'a str> {
+    let Expr::ScalarFunction(ScalarFunction { func, args }) = expr else {
+        return None;
+    };
+    if func.name() != GET_FIELD_FUNC.name() && func.name() != "map_get" {
+        return None;
+    }
+    if args.len() != 2 {
+        return None;
+    }
+    match (&args[0], &args[1]) {
+        (Expr::Column(col), Expr::Literal(ScalarValue::Utf8(key), _)) if col.name() == column => {
+            key.as_deref()
+        }
+        _ => None,
     }
 }
 
@@ -61,23 +225,41 @@ pub fn expr_to_line_filter(expr: &Expr) -> Option<String> {
     if let Expr::BinaryExpr(BinaryExpr { left, op, right }) = expr {
         match op {
             Operator::RegexMatch => match (left.as_ref(), right.as_ref()) {
-                (Expr::Column(_), Expr::Literal(ScalarValue::Utf8(value), _)) => {
-                    Some(format!("|~`{}`", value.as_ref().unwrap_or(&empty_string)))
-                }
-                (Expr::Literal(ScalarValue::Utf8(value), _), Expr::Column(_)) => {
-                    Some(format!("|~`{}`", value.as_ref().unwrap_or(&empty_string)))
-                }
+                (Expr::Column(_), Expr::Literal(ScalarValue::Utf8(value), _)) => Some(format!(
+                    "|~{}",
+                    quote_string(value.as_ref().unwrap_or(&empty_string))
+                )),
+                (Expr::Literal(ScalarValue::Utf8(value), _), Expr::Column(_)) => Some(format!(
+                    "|~{}",
+                    quote_string(value.as_ref().unwrap_or(&empty_string))
+                )),
                 _ => None,
             },
             Operator::RegexNotMatch => match (left.as_ref(), right.as_ref()) {
-                (Expr::Column(_), Expr::Literal(ScalarValue::Utf8(value), _)) => {
-                    Some(format!("!~`{}`", value.as_ref().unwrap_or(&empty_string)))
-                }
-                (Expr::Literal(ScalarValue::Utf8(value), _), Expr::Column(_)) => {
-                    Some(format!("!~`{}`", value.as_ref().unwrap_or(&empty_string)))
-                }
+                (Expr::Column(_), Expr::Literal(ScalarValue::Utf8(value), _)) => Some(format!(
+                    "!~{}",
+                    quote_string(value.as_ref().unwrap_or(&empty_string))
+                )),
+                (Expr::Literal(ScalarValue::Utf8(value), _), Expr::Column(_)) => Some(format!(
+                    "!~{}",
+                    quote_string(value.as_ref().unwrap_or(&empty_string))
+                )),
                 _ => None,
             },
+            // The `~~` operator family, e.g. `line ~~* '%foo%'` for `ILIKE`
+            Operator::LikeMatch
+            | Operator::ILikeMatch
+            | Operator::NotLikeMatch
+            | Operator::NotILikeMatch => {
+                let (Expr::Column(_), Expr::Literal(ScalarValue::Utf8(Some(pattern)), _)) =
+                    (left.as_ref(), right.as_ref())
+                else {
+                    return None;
+                };
+                let negated = matches!(op, Operator::NotLikeMatch | Operator::NotILikeMatch);
+                let case_insensitive = matches!(op, Operator::ILikeMatch | Operator::NotILikeMatch);
+                like_to_line_filter(pattern, negated, case_insensitive)
+            }
             _ => None,
         }
     } else if let Expr::Like(Like {
@@ -95,26 +277,173 @@ pub fn expr_to_line_filter(expr: &Expr) -> Option<String> {
             return None;
         };
         let value = value.as_ref().unwrap_or(&empty_string);
-        if value.starts_with("%") && value.ends_with("%") && !value.contains("_") {
-            let value = value.trim_start_matches('%').trim_end_matches('%');
-            match (negated, case_insensitive) {
-                (true, true) => Some(format!("!~ `(?i){}`", value)),
-                (true, false) => Some(format!("!= `{}`", value)),
-                (false, true) => Some(format!("|~ `(?i){}`", value)),
-                (false, false) => Some(format!("|= `{}`", value)),
+        like_to_line_filter(value, *negated, *case_insensitive)
+    } else if let Expr::Not(expr) = expr {
+        string_function_to_line_filter(expr, true)
+    } else {
+        string_function_to_line_filter(expr, false)
+    }
+}
+
+/// Converts `contains(line, 'x')` to ``|= `x` ``, and `starts_with`/`ends_with` to
+/// anchored regexes of the escaped literal, e.g. ``|~ `^x` ``.
+fn string_function_to_line_filter(expr: &Expr, negated: bool) -> Option<String> {
+    let Expr::ScalarFunction(ScalarFunction { func, args }) = expr else {
+        return None;
+    };
+    let [Expr::Column(col), Expr::Literal(value, _)] = args.as_slice() else {
+        return None;
+    };
+    if col.name() != LINE_FIELD_REF.name() {
+        return None;
+    }
+    let (anchor_start, anchor_end) = match func.name() {
+        "contains" => (false, false),
+        "starts_with" => (true, false),
+        "ends_with" => (false, true),
+        _ => return None,
+    };
+    Some(match_to_line_filter(
+        value.try_as_str()??,
+        anchor_start,
+        anchor_end,
+        negated,
+        false,
+    ))
+}
+
+/// Converts a LIKE pattern without inner wildcards to a line filter. `%term%` becomes a
+/// substring match, while `term%`, `%term` and case-insensitive patterns become anchored and/or
+/// `(?i)` regexes of the escaped term.
+fn like_to_line_filter(pattern: &str, negated: bool, case_insensitive: bool) -> Option<String> {
+    let (anchor_start, rest) = match pattern.strip_prefix('%') {
+        Some(rest) => (false, rest),
+        None => (true, pattern),
+    };
+    let (anchor_end, term) = match rest.strip_suffix('%') {
+        Some(term) => (false, term),
+        None => (true, rest),
+    };
+    if term.contains(['%', '_']) {
+        return None;
+    }
+    Some(match_to_line_filter(
+        term,
+        anchor_start,
+        anchor_end,
+        negated,
+        case_insensitive,
+    ))
+}
+
+/// Builds a line filter matching `term` literally, optionally anchored to the start and/or end
+/// of the line.
+fn match_to_line_filter(
+    term: &str,
+    anchor_start: bool,
+    anchor_end: bool,
+    negated: bool,
+    case_insensitive: bool,
+) -> String {
+    let prefix = if negated { "!" } else { "|" };
+    if !(anchor_start || anchor_end || case_insensitive) {
+        return format!("{prefix}= {}", quote_string(term));
+    }
+    let regex = format!(
+        "{}{}{}{}",
+        if case_insensitive { "(?i)" } else { "" },
+        if anchor_start { "^" } else { "" },
+        escape_regex(term),
+        if anchor_end { "$" } else { "" },
+    );
+    format!("{prefix}~ {}", quote_string(&regex))
+}
+
+/// Converts positional predicates on the line to line filters, e.g. `position('x' in line) > 0`
+/// to ``|= `x` `` and `substr(line, 1, n) = 'p'` to ``|~ `^p` ``.
+///
+/// Unlike [`expr_to_line_filter`], the LogQL filter may not exactly match the predicate's
+/// semantics, so it should be re-verified on the returned logs.
+pub fn expr_to_inexact_line_filter(expr: &Expr) -> Option<String> {
+    let Expr::BinaryExpr(BinaryExpr { left, op, right }) = expr else {
+        return None;
+    };
+    let Expr::ScalarFunction(ScalarFunction { func, args }) = left.as_ref() else {
+        return None;
+    };
+    match (func.name(), args.as_slice(), op, right.as_ref()) {
+        (
+            "strpos",
+            [Expr::Column(col), Expr::Literal(value, _)],
+            Operator::Gt,
+            Expr::Literal(zero, _),
+        ) if col.name() == LINE_FIELD_REF.name() && literal_to_i64(zero) == Some(0) => {
+            Some(format!("|= {}", quote_string(value.try_as_str()??)))
+        }
+        (
+            "substr",
+            [
+                Expr::Column(col),
+                Expr::Literal(start, _),
+                Expr::Literal(len, _),
+            ],
+            Operator::Eq,
+            Expr::Literal(prefix, _),
+        ) if col.name() == LINE_FIELD_REF.name() && literal_to_i64(start) == Some(1) => {
+            // A longer length would require the whole line to equal the literal
+            let prefix = prefix.try_as_str()??;
+            if literal_to_i64(len) != Some(prefix.chars().count() as i64) {
+                return None;
             }
-        } else {
-            None
+            Some(format!(
+                "|~ {}",
+                quote_string(&format!("^{}", escape_regex(prefix)))
+            ))
         }
+        _ => None,
+    }
+}
+
+fn literal_to_i64(value: &ScalarValue) -> Option<i64> {
+    match value.cast_to(&DataType::Int64).ok()? {
+        ScalarValue::Int64(v) => v,
+        _ => None,
+    }
+}
+
+fn escape_regex(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Escapes `value` for a double-quoted LogQL string.
+fn escape_string(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Quotes `value` as a LogQL string, raw in backticks unless it contains a backtick.
+fn quote_string(value: &str) -> String {
+    if value.contains('`') {
+        format!("\"{}\"", escape_string(value))
     } else {
-        None
+        format!("`{value}`")
     }
 }
 
+/// Bounds of loki's `[start, end)` query range, i.e. `start` is inclusive and `end` exclusive.
 pub enum TimestampBound {
     Start(Option<i64>),
     End(Option<i64>),
+    /// Both bounds, e.g. of `timestamp between x and y`.
+    Range(Option<i64>, Option<i64>),
 }
+
 pub fn parse_timestamp_bound(expr: &Expr) -> Option<TimestampBound> {
     let cols = expr.column_refs();
     if cols.len() != 1 {
@@ -124,29 +453,113 @@ pub fn parse_timestamp_bound(expr: &Expr) -> Option<TimestampBound> {
     if col.name() != TIMESTAMP_FIELD_REF.name() {
         return None;
     }
-    if let Expr::BinaryExpr(BinaryExpr { left, op, right }) = expr {
-        match op {
-            Operator::Lt | Operator::LtEq => match (left.as_ref(), right.as_ref()) {
-                (Expr::Column(_), Expr::Literal(ScalarValue::TimestampNanosecond(value, _), _)) => {
-                    Some(TimestampBound::End(*value))
-                }
-                (Expr::Literal(ScalarValue::TimestampNanosecond(value, _), _), Expr::Column(_)) => {
-                    Some(TimestampBound::Start(*value))
-                }
-                _ => None,
-            },
-            Operator::Gt | Operator::GtEq => match (left.as_ref(), right.as_ref()) {
-                (Expr::Column(_), Expr::Literal(ScalarValue::TimestampNanosecond(value, _), _)) => {
-                    Some(TimestampBound::Start(*value))
-                }
-                (Expr::Literal(ScalarValue::TimestampNanosecond(value, _), _), Expr::Column(_)) => {
-                    Some(TimestampBound::End(*value))
-                }
-                _ => None,
-            },
-            _ => None,
+    if let Expr::Between(between) = expr {
+        return between_bound(between);
+    }
+    let Expr::BinaryExpr(BinaryExpr { left, op, right }) = expr else {
+        return None;
+    };
+    let ((value, unit_ns), op) = match (left.as_ref(), right.as_ref()) {
+        (Expr::Column(_), Expr::Literal(value, _)) => (timestamp_literal(value)?, *op),
+        (Expr::Literal(value, _), Expr::Column(_)) => (timestamp_literal(value)?, op.swap()?),
+        _ => return None,
+    };
+    // A column of a coarser unit holds truncated timestamps, so e.g. `timestamp > v` in
+    // micros matches nanosecond timestamps from `(v + 1) * 1000` on.
+    let (op, value) = match op {
+        Operator::Gt => (Operator::GtEq, value.map(|v| v.saturating_add(1))),
+        Operator::LtEq => (Operator::Lt, value.map(|v| v.saturating_add(1))),
+        op => (op, value),
+    };
+    range_bound(op, value.map(|v| v.saturating_mul(unit_ns)))
+}
+
+/// Splits a timestamp literal into its value and the nanoseconds per unit of its type.
+///
+/// Arrow timestamps with a time zone count from the UTC epoch, the zone only affecting
+/// display, so the value is loki's UTC timestamp as is. Offsets of string literals, e.g.
+/// `'2025-01-01T02:00:00+02:00'`, are applied when coerced to the column type, and
+/// timestamps without a time zone are taken as UTC.
+fn timestamp_literal(value: &ScalarValue) -> Option<(Option<i64>, i64)> {
+    match value {
+        ScalarValue::TimestampNanosecond(value, _) => Some((*value, 1)),
+        ScalarValue::TimestampMicrosecond(value, _) => Some((*value, 1_000)),
+        ScalarValue::TimestampMillisecond(value, _) => Some((*value, 1_000_000)),
+        ScalarValue::TimestampSecond(value, _) => Some((*value, 1_000_000_000)),
+        _ => None,
+    }
+}
+
+/// Converts `timestamp between low and high` into both bounds, `not between` spanning two
+/// ranges loki can't query at once.
+fn between_bound(between: &Between) -> Option<TimestampBound> {
+    let Between {
+        expr,
+        negated,
+        low,
+        high,
+    } = between;
+    if *negated || !matches!(expr.as_ref(), Expr::Column(_)) {
+        return None;
+    }
+    let (Expr::Literal(low, _), Expr::Literal(high, _)) = (low.as_ref(), high.as_ref()) else {
+        return None;
+    };
+    let (low, low_unit_ns) = timestamp_literal(low)?;
+    let (high, high_unit_ns) = timestamp_literal(high)?;
+    Some(TimestampBound::Range(
+        low.map(|v| v.saturating_mul(low_unit_ns)),
+        high.map(|v| v.saturating_add(1).saturating_mul(high_unit_ns)),
+    ))
+}
+
+/// Converts `timestamp <op> value` into a bound of loki's `[start, end)` query range.
+fn range_bound(op: Operator, value: Option<i64>) -> Option<TimestampBound> {
+    match op {
+        Operator::Lt => Some(TimestampBound::End(value)),
+        Operator::LtEq => Some(TimestampBound::End(value.map(|v| v.saturating_add(1)))),
+        Operator::Gt => Some(TimestampBound::Start(value.map(|v| v.saturating_add(1)))),
+        Operator::GtEq => Some(TimestampBound::Start(value)),
+        _ => None,
+    }
+}
+
+/// Parses a bound on the synthetic age column into a timestamp bound, ages being
+/// measured from `now` (ns). Intervals with months are not supported as their length varies.
+pub fn parse_age_bound(expr: &Expr, now: i64) -> Option<TimestampBound> {
+    let Expr::BinaryExpr(BinaryExpr { left, op, right }) = expr else {
+        return None;
+    };
+    let (age, op) = match (left.as_ref(), right.as_ref()) {
+        (col, Expr::Literal(value, _)) if is_age_column(col) => (interval_to_ns(value)?, *op),
+        (Expr::Literal(value, _), col) if is_age_column(col) => {
+            (interval_to_ns(value)?, op.swap()?)
         }
-    } else {
-        None
+        _ => return None,
+    };
+    // `age <op> x` is `timestamp <swapped op> now - x`
+    range_bound(op.swap()?, Some(now.saturating_sub(age)))
+}
+
+fn is_age_column(expr: &Expr) -> bool {
+    match expr {
+        Expr::Column(col) => col.name() == AGE_FIELD_REF.name(),
+        Expr::Cast(Cast { expr, .. }) => is_age_column(expr),
+        _ => false,
+    }
+}
+
+/// Length of an interval in nanoseconds, `None` for intervals too long for `i64`.
+fn interval_to_ns(value: &ScalarValue) -> Option<i64> {
+    const NANOS_PER_DAY: i64 = 24 * 60 * 60 * 1_000_000_000;
+    match value {
+        ScalarValue::IntervalMonthDayNano(Some(v)) if v.months == 0 => (v.days as i64)
+            .checked_mul(NANOS_PER_DAY)?
+            .checked_add(v.nanoseconds),
+        ScalarValue::IntervalDayTime(Some(v)) => (v.days as i64)
+            .checked_mul(NANOS_PER_DAY)?
+            .checked_add(v.milliseconds as i64 * 1_000_000),
+        ScalarValue::DurationNanosecond(Some(v)) => Some(*v),
+        _ => None,
     }
 }
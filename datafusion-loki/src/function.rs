@@ -0,0 +1,495 @@
+use std::{any::Any, sync::Arc};
+
+use arrow::{
+    array::{Array, ArrayRef, AsArray, BooleanArray, MapArray, StringArray, UInt32Array},
+    compute::{cast, kernels::zip::zip, take},
+    datatypes::DataType,
+};
+use datafusion_common::{ScalarValue, exec_err, plan_err};
+use datafusion_expr::{
+    ColumnarValue, ScalarFunctionArgs, ScalarUDF, ScalarUDFImpl, Signature, TypeSignature,
+    Volatility, registry::FunctionRegistry,
+};
+
+use crate::{DFResult, has_sorted_keys};
+
+/// `map_contains_key(map, key)`, whether the map has an entry for the key, null for
+/// null maps or keys.
+///
+/// On the labels column, e.g. `map_contains_key(labels, 'app')`, it's pushed down to loki
+/// as the `app=~".+"` label matcher.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct MapContainsKey {
+    signature: Signature,
+}
+
+impl Default for MapContainsKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MapContainsKey {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::any(2, Volatility::Immutable),
+        }
+    }
+}
+
+impl ScalarUDFImpl for MapContainsKey {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "map_contains_key"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> DFResult<DataType> {
+        match arg_types {
+            [DataType::Map(_, _), key] if is_string_type(key) => Ok(DataType::Boolean),
+            _ => plan_err!("map_contains_key expects a map and a string key, got {arg_types:?}"),
+        }
+    }
+
+    fn invoke_with_args(&self, args: ScalarFunctionArgs) -> DFResult<ColumnarValue> {
+        let [map, key] = args.args.as_slice() else {
+            return exec_err!("map_contains_key expects 2 arguments");
+        };
+        let is_scalar = matches!(
+            (map, key),
+            (ColumnarValue::Scalar(_), ColumnarValue::Scalar(_))
+        );
+        let map = map.to_array(args.number_rows)?;
+        let map = map.as_map();
+        let key = cast(&key.to_array(args.number_rows)?, &DataType::Utf8)?;
+        let key = key.as_string::<i32>();
+        let map_keys = cast(map.keys(), &DataType::Utf8)?;
+        let map_keys = map_keys.as_string::<i32>();
+
+        let result = (0..map.len())
+            .map(|row| {
+                if map.is_null(row) || key.is_null(row) {
+                    return None;
+                }
+                let offsets = &map.value_offsets()[row..row + 2];
+                let key = key.value(row);
+                Some(
+                    (offsets[0] as usize..offsets[1] as usize)
+                        .any(|i| map_keys.is_valid(i) && map_keys.value(i) == key),
+                )
+            })
+            .collect::<BooleanArray>();
+        if is_scalar {
+            Ok(ColumnarValue::Scalar(ScalarValue::try_from_array(
+                &result, 0,
+            )?))
+        } else {
+            Ok(ColumnarValue::Array(Arc::new(result)))
+        }
+    }
+}
+
+/// `map_get(map, key[, default])`, the value of the key's entry in the map, or `default`
+/// (null if omitted) if the map has no such entry. Null for null maps or keys.
+///
+/// Maps holding the key more than once, e.g. labels decoded from a misbehaving backend,
+/// don't fail the query: the first entry for the key wins.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct MapGet {
+    signature: Signature,
+}
+
+impl Default for MapGet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MapGet {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::one_of(
+                vec![TypeSignature::UserDefined, TypeSignature::Any(2)],
+                Volatility::Immutable,
+            ),
+        }
+    }
+}
+
+impl ScalarUDFImpl for MapGet {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "map_get"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn coerce_types(&self, arg_types: &[DataType]) -> DFResult<Vec<DataType>> {
+        match arg_types {
+            [DataType::Map(field, _), key, _] if is_string_type(key) => {
+                let DataType::Struct(fields) = field.data_type() else {
+                    return plan_err!("map_get expects map entries to be a struct");
+                };
+                Ok(vec![
+                    arg_types[0].clone(),
+                    key.clone(),
+                    fields[1].data_type().clone(),
+                ])
+            }
+            _ => plan_err!(
+                "map_get expects a map, a string key and a default value, got {arg_types:?}"
+            ),
+        }
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> DFResult<DataType> {
+        match arg_types {
+            [DataType::Map(field, _), key] | [DataType::Map(field, _), key, _]
+                if is_string_type(key) =>
+            {
+                let DataType::Struct(fields) = field.data_type() else {
+                    return plan_err!("map_get expects map entries to be a struct");
+                };
+                Ok(fields[1].data_type().clone())
+            }
+            _ => plan_err!("map_get expects a map and a string key, got {arg_types:?}"),
+        }
+    }
+
+    fn invoke_with_args(&self, args: ScalarFunctionArgs) -> DFResult<ColumnarValue> {
+        let (map, key, default) = match args.args.as_slice() {
+            [map, key] => (map, key, None),
+            [map, key, default] => (map, key, Some(default)),
+            _ => return exec_err!("map_get expects 2 or 3 arguments"),
+        };
+        let is_scalar = args
+            .args
+            .iter()
+            .all(|arg| matches!(arg, ColumnarValue::Scalar(_)));
+        let map = map.to_array(args.number_rows)?;
+        let key = cast(&key.to_array(args.number_rows)?, &DataType::Utf8)?;
+        let default = default
+            .map(|default| default.to_array(args.number_rows))
+            .transpose()?;
+
+        let sorted = args
+            .arg_fields
+            .first()
+            .is_some_and(|field| has_sorted_keys(field));
+        let result = map_get_inner(map.as_map(), &key, default.as_ref(), sorted)?;
+        if is_scalar {
+            Ok(ColumnarValue::Scalar(ScalarValue::try_from_array(
+                &result, 0,
+            )?))
+        } else {
+            Ok(ColumnarValue::Array(result))
+        }
+    }
+}
+
+/// `json_get(line, key)`, the top-level field `key` of each line parsed as a JSON object,
+/// e.g. `json_get(line, 'user_id')` on JSON-formatted logs.
+///
+/// String fields are returned as is and other fields as their JSON text. Null for lines
+/// that aren't JSON objects, missing or null fields, and null arguments.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct JsonGet {
+    signature: Signature,
+}
+
+impl Default for JsonGet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JsonGet {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::any(2, Volatility::Immutable),
+        }
+    }
+}
+
+impl ScalarUDFImpl for JsonGet {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "json_get"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> DFResult<DataType> {
+        match arg_types {
+            [line, key] if is_string_type(line) && is_string_type(key) => Ok(DataType::Utf8),
+            _ => plan_err!("json_get expects a string line and a string key, got {arg_types:?}"),
+        }
+    }
+
+    fn invoke_with_args(&self, args: ScalarFunctionArgs) -> DFResult<ColumnarValue> {
+        let [line, key] = args.args.as_slice() else {
+            return exec_err!("json_get expects 2 arguments");
+        };
+        let is_scalar = matches!(
+            (line, key),
+            (ColumnarValue::Scalar(_), ColumnarValue::Scalar(_))
+        );
+        let line = cast(&line.to_array(args.number_rows)?, &DataType::Utf8)?;
+        let line = line.as_string::<i32>();
+        let key = cast(&key.to_array(args.number_rows)?, &DataType::Utf8)?;
+        let key = key.as_string::<i32>();
+
+        let result = line
+            .iter()
+            .zip(key.iter())
+            .map(|(line, key)| {
+                let mut object =
+                    serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(line?)
+                        .ok()?;
+                match object.remove(key?)? {
+                    serde_json::Value::Null => None,
+                    serde_json::Value::String(value) => Some(value),
+                    value => Some(value.to_string()),
+                }
+            })
+            .collect::<StringArray>();
+        if is_scalar {
+            Ok(ColumnarValue::Scalar(ScalarValue::try_from_array(
+                &result, 0,
+            )?))
+        } else {
+            Ok(ColumnarValue::Array(Arc::new(result)))
+        }
+    }
+}
+
+/// `logfmt_get(line, key)`, the value of `key` in each line parsed as logfmt, e.g.
+/// `logfmt_get(line, 'msg')` on `level=error msg="boom" dur=12ms` is `boom`.
+///
+/// Quoted values are unescaped, keys without a value are empty strings and the first of
+/// repeated keys wins. Null for missing keys and null arguments.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct LogfmtGet {
+    signature: Signature,
+}
+
+impl Default for LogfmtGet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogfmtGet {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::any(2, Volatility::Immutable),
+        }
+    }
+}
+
+impl ScalarUDFImpl for LogfmtGet {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "logfmt_get"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> DFResult<DataType> {
+        match arg_types {
+            [line, key] if is_string_type(line) && is_string_type(key) => Ok(DataType::Utf8),
+            _ => plan_err!("logfmt_get expects a string line and a string key, got {arg_types:?}"),
+        }
+    }
+
+    fn invoke_with_args(&self, args: ScalarFunctionArgs) -> DFResult<ColumnarValue> {
+        let [line, key] = args.args.as_slice() else {
+            return exec_err!("logfmt_get expects 2 arguments");
+        };
+        let is_scalar = matches!(
+            (line, key),
+            (ColumnarValue::Scalar(_), ColumnarValue::Scalar(_))
+        );
+        let line = cast(&line.to_array(args.number_rows)?, &DataType::Utf8)?;
+        let line = line.as_string::<i32>();
+        let key = cast(&key.to_array(args.number_rows)?, &DataType::Utf8)?;
+        let key = key.as_string::<i32>();
+
+        let result = line
+            .iter()
+            .zip(key.iter())
+            .map(|(line, key)| logfmt_value(line?, key?))
+            .collect::<StringArray>();
+        if is_scalar {
+            Ok(ColumnarValue::Scalar(ScalarValue::try_from_array(
+                &result, 0,
+            )?))
+        } else {
+            Ok(ColumnarValue::Array(Arc::new(result)))
+        }
+    }
+}
+
+/// Scans a logfmt line for the first `key=value` pair of `key`.
+fn logfmt_value(line: &str, key: &str) -> Option<String> {
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        chars.peek()?;
+        let mut pair_key = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '=') {
+            pair_key.push(c);
+        }
+        let mut value = String::new();
+        if chars.next_if_eq(&'=').is_some() {
+            if chars.next_if_eq(&'"').is_some() {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some('n') => value.push('\n'),
+                            Some('t') => value.push('\t'),
+                            Some('r') => value.push('\r'),
+                            Some(c) => value.push(c),
+                            None => break,
+                        },
+                        c => value.push(c),
+                    }
+                }
+            } else {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    value.push(c);
+                }
+            }
+        }
+        if pair_key == key {
+            return Some(value);
+        }
+    }
+}
+
+/// Looks `key` up in `map` row by row, taking the first entry of duplicate keys and
+/// falling back to `default`, or null without one.
+///
+/// Only resolves the index of each row's entry, the values being gathered by the `take`
+/// and `zip` kernels at once. Entries `sorted` by key are binary searched.
+fn map_get_inner(
+    map: &MapArray,
+    key: &ArrayRef,
+    default: Option<&ArrayRef>,
+    sorted: bool,
+) -> DFResult<ArrayRef> {
+    let key = key.as_string::<i32>();
+    let map_keys = match map.keys().as_string_opt::<i32>() {
+        Some(_) => map.keys().clone(),
+        None => cast(map.keys(), &DataType::Utf8)?,
+    };
+    let map_keys = map_keys.as_string::<i32>();
+    let (key_offsets, key_bytes) = (map_keys.value_offsets(), map_keys.value_data());
+    let has_null_keys = map_keys.null_count() > 0;
+    let offsets = map.value_offsets();
+
+    let mut indices = Vec::with_capacity(map.len());
+    let mut missing = Vec::with_capacity(map.len());
+    for row in 0..map.len() {
+        if map.is_null(row) || key.is_null(row) {
+            indices.push(None);
+            missing.push(false);
+            continue;
+        }
+        let key = key.value(row).as_bytes();
+        let map_key = |i: usize| &key_bytes[key_offsets[i] as usize..key_offsets[i + 1] as usize];
+        let (start, end) = (offsets[row] as usize, offsets[row + 1] as usize);
+        let found = if sorted && !has_null_keys {
+            // The first of duplicate keys, as sorting kept their order
+            let (mut low, mut high) = (start, end);
+            while low < high {
+                let mid = low + (high - low) / 2;
+                if map_key(mid) < key {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+            (low < end && map_key(low) == key).then_some(low)
+        } else {
+            (start..end).find(|&i| (!has_null_keys || map_keys.is_valid(i)) && map_key(i) == key)
+        };
+        indices.push(found.map(|i| i as u32));
+        missing.push(found.is_none());
+    }
+
+    let values = take(map.values(), &UInt32Array::from(indices), None)?;
+    match default {
+        Some(default) => Ok(zip(&BooleanArray::from(missing), default, &values)?),
+        None => Ok(values),
+    }
+}
+
+/// The scalar functions of this crate: [`MapContainsKey`], [`MapGet`], [`JsonGet`] and
+/// [`LogfmtGet`].
+pub fn loki_udfs() -> Vec<Arc<ScalarUDF>> {
+    vec![
+        Arc::new(ScalarUDF::from(MapContainsKey::new())),
+        Arc::new(ScalarUDF::from(MapGet::new())),
+        Arc::new(ScalarUDF::from(JsonGet::new())),
+        Arc::new(ScalarUDF::from(LogfmtGet::new())),
+    ]
+}
+
+/// Registers [`loki_udfs`], e.g. `register_loki_udfs(&mut ctx)` of a `SessionContext`.
+pub fn register_loki_udfs(registry: &mut dyn FunctionRegistry) -> DFResult<()> {
+    for udf in loki_udfs() {
+        registry.register_udf(udf)?;
+    }
+    Ok(())
+}
+
+/// The function of [`loki_udfs`] named `name`, resolving functions of decoded plans.
+pub(crate) fn loki_udf(name: &str) -> Option<Arc<ScalarUDF>> {
+    loki_udfs().into_iter().find(|udf| udf.name() == name)
+}
+
+pub(crate) fn is_string_type(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View | DataType::Null => true,
+        DataType::Dictionary(_, value_type) => is_string_type(value_type),
+        _ => false,
+    }
+}
+
+/// Whether `data_type` is a map of string keys to string values, in any string encoding.
+pub(crate) fn is_string_map_type(data_type: &DataType) -> bool {
+    let DataType::Map(entries, _) = data_type else {
+        return false;
+    };
+    match entries.data_type() {
+        DataType::Struct(fields) => {
+            fields.len() == 2
+                && is_string_type(fields[0].data_type())
+                && is_string_type(fields[1].data_type())
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use datafusion_common::DataFusionError;
+use reqwest::{
+    Client, Request, RequestBuilder, Response, StatusCode,
+    header::{HeaderMap, HeaderName, HeaderValue},
+};
+
+/// Characters of a non-JSON error body kept in errors, e.g. of a proxy's html error page.
+const MAX_ERROR_TEXT_LEN: usize = 1024;
+
+pub(crate) fn build_client() -> Result<Client, DataFusionError> {
+    Client::builder()
+        .build()
+        .map_err(|e| DataFusionError::Execution(format!("Failed to build http client: {e}")))
+}
+
+/// The http client of a plan, usually the one of its table, so repeated queries reuse
+/// its pooled connections. Plans without one, e.g. decoded by the codec, build their own
+/// on first use, shared with their clones.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SharedClient(Arc<OnceLock<Client>>);
+
+impl SharedClient {
+    pub(crate) fn new(client: Client) -> Self {
+        Self(Arc::new(OnceLock::from(client)))
+    }
+
+    pub(crate) fn get(&self) -> Result<Client, DataFusionError> {
+        if let Some(client) = self.0.get() {
+            return Ok(client.clone());
+        }
+        let client = build_client()?;
+        Ok(self.0.get_or_init(|| client).clone())
+    }
+}
+
+/// Options applied to every http request sent to loki.
+#[derive(Debug, Clone, Default)]
+pub struct LokiHttpOptions {
+    /// Token sent in the `Authorization: Bearer` header.
+    pub bearer_token: Option<String>,
+    pub basic_auth: Option<LokiBasicAuth>,
+    /// Tenant sent in the `X-Scope-OrgID` header of multi-tenant loki.
+    pub tenant: Option<String>,
+    /// Custom headers, replacing any auth or tenant header of the same name.
+    pub headers: HashMap<String, String>,
+    /// Timeout of each request, from sending until the response body is read.
+    pub timeout: Option<Duration>,
+    /// Hook run on every request just before sending. Not serialized by the codec.
+    pub middleware: Option<LokiRequestMiddleware>,
+}
+
+/// Hook modifying requests before they are sent, e.g. to add a computed signature header.
+#[derive(Clone)]
+pub struct LokiRequestMiddleware(pub Arc<dyn Fn(&mut Request) + Send + Sync>);
+
+impl std::fmt::Debug for LokiRequestMiddleware {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LokiRequestMiddleware")
+    }
+}
+
+/// Credentials for http basic auth, e.g. for loki behind an nginx proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LokiBasicAuth {
+    pub username: String,
+    pub password: Option<String>,
+}
+
+impl LokiHttpOptions {
+    pub(crate) fn apply(&self, mut req_builder: RequestBuilder) -> RequestBuilder {
+        if let Some(basic_auth) = &self.basic_auth {
+            req_builder =
+                req_builder.basic_auth(&basic_auth.username, basic_auth.password.as_ref());
+        }
+        if let Some(token) = &self.bearer_token {
+            req_builder = req_builder.bearer_auth(token);
+        }
+        if let Some(tenant) = &self.tenant {
+            req_builder = req_builder.header("X-Scope-OrgID", tenant);
+        }
+        if let Some(timeout) = self.timeout {
+            req_builder = req_builder.timeout(timeout);
+        }
+        let mut headers = HeaderMap::with_capacity(self.headers.len());
+        for (name, value) in &self.headers {
+            match (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                (Ok(name), Ok(value)) => {
+                    headers.insert(name, value);
+                }
+                // Lets the request builder report the invalid header when sending
+                _ => req_builder = req_builder.header(name, value),
+            }
+        }
+        // Unlike `header`, `headers` replaces existing values
+        req_builder.headers(headers)
+    }
+
+    /// Converts a request error into an execution error, reporting timeouts with the
+    /// configured timeout instead of a generic reqwest error.
+    pub(crate) fn map_request_error(&self, e: reqwest::Error, context: &str) -> DataFusionError {
+        match self.timeout {
+            Some(timeout) if e.is_timeout() => DataFusionError::Execution(format!(
+                "{context}: request to loki timed out after {timeout:?}"
+            )),
+            _ => DataFusionError::Execution(format!("{context}: {e}")),
+        }
+    }
+
+    /// Sends the request, running the middleware first.
+    pub(crate) async fn send(&self, req_builder: RequestBuilder) -> reqwest::Result<Response> {
+        let Some(middleware) = &self.middleware else {
+            return req_builder.send().await;
+        };
+        let (client, request) = req_builder.build_split();
+        let mut request = request?;
+        (middleware.0)(&mut request);
+        client.execute(request).await
+    }
+}
+
+/// Reads the reason of a failed loki response, the `error` or `message` of JSON bodies and
+/// otherwise the body itself, truncated to [`MAX_ERROR_TEXT_LEN`] characters.
+pub(crate) async fn read_error_text(resp: Response) -> Option<String> {
+    let text = resp.text().await.ok()?;
+    if let Ok(serde_json::Value::Object(body)) = serde_json::from_str(&text) {
+        let reason = ["error", "message"]
+            .iter()
+            .find_map(|key| body.get(*key).and_then(|v| v.as_str()));
+        if let Some(reason) = reason {
+            return Some(reason.to_string());
+        }
+    }
+    let text = text.trim();
+    match text.char_indices().nth(MAX_ERROR_TEXT_LEN) {
+        Some((end, _)) => Some(format!("{}...", &text[..end])),
+        None => Some(text.to_string()),
+    }
+}
+
+/// Wraps the error of a failed loki response into the error variant matching its status.
+pub(crate) fn status_error(status: StatusCode, message: String) -> DataFusionError {
+    match status {
+        // Loki rejected the query itself, e.g. a LogQL parse error
+        StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => {
+            DataFusionError::Plan(message)
+        }
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => DataFusionError::Configuration(message),
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+            DataFusionError::ResourcesExhausted(message)
+        }
+        _ => DataFusionError::Execution(message),
+    }
+}
+
+/// Displays the options for plans, redacting credentials.
+impl Display for LokiHttpOptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(tenant) = &self.tenant {
+            write!(f, ", tenant={tenant}")?;
+        }
+        if let Some(timeout) = self.timeout {
+            write!(f, ", timeout={timeout:?}")?;
+        }
+        if self.basic_auth.is_some() {
+            write!(f, ", basic_auth=***")?;
+        }
+        if self.bearer_token.is_some() {
+            write!(f, ", bearer_token=***")?;
+        }
+        Ok(())
+    }
+}
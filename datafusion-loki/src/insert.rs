@@ -1,6 +1,8 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, btree_map::Entry},
+    io::Write,
     sync::{Arc, LazyLock},
+    time::{Duration, Instant},
 };
 
 use arrow::{
@@ -8,20 +10,46 @@ use arrow::{
         Array, ArrayRef, Int64Array, MapArray, RecordBatch, StringArray, StructArray,
         TimestampNanosecondArray,
     },
-    datatypes::{DataType, Field, Schema, SchemaRef},
+    compute::cast,
+    datatypes::{DataType, Field, FieldRef, Schema, SchemaRef, TimeUnit},
+    temporal_conversions::timestamp_ns_to_datetime,
 };
-use datafusion_common::{DataFusionError, plan_err, stats::Precision};
+use datafusion_common::{DataFusionError, exec_err, internal_err, plan_err, stats::Precision};
 use datafusion_execution::{SendableRecordBatchStream, TaskContext};
-use datafusion_physical_expr::EquivalenceProperties;
+use datafusion_physical_expr::{
+    EquivalenceProperties, PhysicalExpr,
+    expressions::{CastExpr, Column},
+};
 use datafusion_physical_plan::{
     DisplayAs, DisplayFormatType, ExecutionPlan, ExecutionPlanProperties, Partitioning,
-    PlanProperties, stream::RecordBatchStreamAdapter,
+    PlanProperties,
+    metrics::{Count, ExecutionPlanMetricsSet, MetricBuilder, MetricsSet, Time},
+    projection::ProjectionExec,
+    stream::RecordBatchStreamAdapter,
+};
+use flate2::{Compression, write::GzEncoder};
+use futures::{StreamExt, TryStreamExt};
+use reqwest::{
+    Client,
+    header::{CONTENT_ENCODING, CONTENT_TYPE},
 };
-use futures::StreamExt;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use tracing::{Instrument, Span, debug, debug_span, field::Empty, warn};
+
+use crate::{
+    DFResult, DICTIONARY_LABELS_FIELD_REF, LABELS_FIELD_REF, LOG_TABLE_SCHEMA, LokiHttpOptions,
+    STRUCTURED_METADATA_FIELD_REF, SharedClient, TIMESTAMP_FIELD_REF, current_timestamp_ns,
+    is_string_map_type, read_error_text, status_error,
+};
+
+/// Content type of push requests, unless set otherwise.
+pub const DEFAULT_PUSH_CONTENT_TYPE: &str = "application/json";
+
+/// Input partitions pushed concurrently, unless set otherwise.
+pub const DEFAULT_MAX_PUSH_CONCURRENCY: usize = 4;
 
-use crate::{DFResult, LOG_TABLE_SCHEMA};
+/// Interval between checks whether loki processed the delete of an overwrite.
+const DELETE_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 pub static COUNT_SCHEMA: LazyLock<SchemaRef> = LazyLock::new(|| {
     Arc::new(Schema::new(vec![Field::new(
@@ -31,38 +59,173 @@ pub static COUNT_SCHEMA: LazyLock<SchemaRef> = LazyLock::new(|| {
     )]))
 });
 
+/// Result schema of inserts with [`LokiLogInsertExec::detailed_result`]: the logs pushed
+/// and those of failed push requests, and the streams of successful push requests,
+/// counted once per request they were pushed in.
+pub static DETAILED_INSERT_RESULT_SCHEMA: LazyLock<SchemaRef> = LazyLock::new(|| {
+    Arc::new(Schema::new(vec![
+        Field::new("pushed", DataType::Int64, false),
+        Field::new("failed", DataType::Int64, false),
+        Field::new("streams", DataType::Int64, false),
+    ]))
+});
+
 #[derive(Debug)]
 pub struct LokiLogInsertExec {
     pub input: Arc<dyn ExecutionPlan>,
     pub endpoint: String,
-    client: Client,
+    pub http_options: LokiHttpOptions,
+    pub duplicate_label_policy: DuplicateLabelPolicy,
+    /// Timestamps more than this in the future are clamped to now plus this.
+    pub clock_skew_tolerance: Option<Duration>,
+    /// `Content-Type` header of push requests, whose body is always JSON.
+    pub push_content_type: String,
+    /// Splits pushes whose serialized payload would exceed this many bytes.
+    pub max_push_bytes: Option<usize>,
+    /// Splits pushes of more than this many logs.
+    pub max_push_lines: Option<usize>,
+    /// Gzip-compresses push request bodies.
+    pub push_compression: bool,
+    /// Input partitions pushed concurrently, each pushing its batches one after another.
+    pub max_push_concurrency: usize,
+    /// Returns [`DETAILED_INSERT_RESULT_SCHEMA`] instead of [`COUNT_SCHEMA`], counting
+    /// the logs of failed push requests instead of failing the insert.
+    pub detailed_result: bool,
+    /// Logs deleted before pushing, implementing `insert overwrite`.
+    pub overwrite_delete: Option<LokiDeleteRequest>,
+    metrics: ExecutionPlanMetricsSet,
+    pub(crate) client: SharedClient,
     plan_properties: Arc<PlanProperties>,
 }
 
 impl LokiLogInsertExec {
     pub fn try_new(input: Arc<dyn ExecutionPlan>, endpoint: String) -> DFResult<Self> {
-        if input.schema() != LOG_TABLE_SCHEMA.clone() {
-            return plan_err!("input exec schema not matched: {:?}", input.schema());
-        }
-
-        let plan_properties = PlanProperties::new(
-            EquivalenceProperties::new(COUNT_SCHEMA.clone()),
-            Partitioning::UnknownPartitioning(1),
-            input.pipeline_behavior(),
-            input.boundedness(),
-        );
+        check_input_schema(&input.schema())?;
 
-        let client = Client::builder()
-            .build()
-            .map_err(|e| DataFusionError::Plan(format!("Failed to build http client: {e}")))?;
+        let plan_properties = make_plan_properties(COUNT_SCHEMA.clone(), &input);
 
         Ok(Self {
             input,
             endpoint,
-            client,
+            http_options: LokiHttpOptions::default(),
+            duplicate_label_policy: DuplicateLabelPolicy::default(),
+            clock_skew_tolerance: None,
+            push_content_type: DEFAULT_PUSH_CONTENT_TYPE.to_string(),
+            max_push_bytes: None,
+            max_push_lines: None,
+            push_compression: false,
+            max_push_concurrency: DEFAULT_MAX_PUSH_CONCURRENCY,
+            detailed_result: false,
+            overwrite_delete: None,
+            metrics: ExecutionPlanMetricsSet::new(),
+            client: SharedClient::default(),
             plan_properties: Arc::new(plan_properties),
         })
     }
+
+    /// Sends requests with `client`, e.g. shared with other plans to reuse its connections.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = SharedClient::new(client);
+        self
+    }
+
+    pub fn with_http_options(mut self, http_options: LokiHttpOptions) -> Self {
+        self.http_options = http_options;
+        self
+    }
+
+    pub fn with_duplicate_label_policy(
+        mut self,
+        duplicate_label_policy: DuplicateLabelPolicy,
+    ) -> Self {
+        self.duplicate_label_policy = duplicate_label_policy;
+        self
+    }
+
+    pub fn with_clock_skew_tolerance(mut self, clock_skew_tolerance: Option<Duration>) -> Self {
+        self.clock_skew_tolerance = clock_skew_tolerance;
+        self
+    }
+
+    pub fn with_push_content_type(mut self, push_content_type: String) -> Self {
+        self.push_content_type = push_content_type;
+        self
+    }
+
+    pub fn with_max_push_bytes(mut self, max_push_bytes: Option<usize>) -> Self {
+        self.max_push_bytes = max_push_bytes;
+        self
+    }
+
+    pub fn with_max_push_lines(mut self, max_push_lines: Option<usize>) -> Self {
+        self.max_push_lines = max_push_lines;
+        self
+    }
+
+    pub fn with_push_compression(mut self, push_compression: bool) -> Self {
+        self.push_compression = push_compression;
+        self
+    }
+
+    /// Partitions pushed concurrently interleave their requests, so a stream's logs
+    /// only reach loki in partition order with a concurrency of 1.
+    pub fn with_max_push_concurrency(mut self, max_push_concurrency: usize) -> DFResult<Self> {
+        if max_push_concurrency == 0 {
+            return plan_err!("max_push_concurrency must be greater than 0");
+        }
+        self.max_push_concurrency = max_push_concurrency;
+        Ok(self)
+    }
+
+    pub fn with_detailed_result(mut self, detailed_result: bool) -> Self {
+        let schema = if detailed_result {
+            DETAILED_INSERT_RESULT_SCHEMA.clone()
+        } else {
+            COUNT_SCHEMA.clone()
+        };
+        self.plan_properties = Arc::new(make_plan_properties(schema, &self.input));
+        self.detailed_result = detailed_result;
+        self
+    }
+
+    /// Deletes the logs of `overwrite_delete` and waits for loki to process the delete
+    /// before pushing any logs.
+    pub fn with_overwrite_delete(mut self, overwrite_delete: Option<LokiDeleteRequest>) -> Self {
+        self.overwrite_delete = overwrite_delete;
+        self
+    }
+}
+
+/// A request of loki's delete API, removing the logs of `query` within `[start, end]` (ns).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LokiDeleteRequest {
+    /// Stream selector of the deleted logs, e.g. `{app="foo"}`.
+    pub query: String,
+    pub start: i64,
+    pub end: i64,
+    /// How long to wait for loki to process the delete, which its compactor only does
+    /// after its `delete_request_cancel_period`.
+    pub timeout: Duration,
+}
+
+fn make_plan_properties(schema: SchemaRef, input: &Arc<dyn ExecutionPlan>) -> PlanProperties {
+    PlanProperties::new(
+        EquivalenceProperties::new(schema),
+        Partitioning::UnknownPartitioning(1),
+        input.pipeline_behavior(),
+        input.boundedness(),
+    )
+}
+
+/// How an insert handles a labels map containing the same key more than once, which
+/// hand-built arrow maps may do.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateLabelPolicy {
+    KeepFirst,
+    #[default]
+    KeepLast,
+    /// Fails the insert, e.g. to catch data bugs.
+    Error,
 }
 
 impl ExecutionPlan for LokiLogInsertExec {
@@ -87,41 +250,107 @@ impl ExecutionPlan for LokiLogInsertExec {
         children: Vec<Arc<dyn ExecutionPlan>>,
     ) -> DFResult<Arc<dyn ExecutionPlan>> {
         let input = children[0].clone();
-        let exec = Self::try_new(input, self.endpoint.clone())?;
+        let exec = Self::try_new(input, self.endpoint.clone())?
+            .with_http_options(self.http_options.clone())
+            .with_duplicate_label_policy(self.duplicate_label_policy)
+            .with_clock_skew_tolerance(self.clock_skew_tolerance)
+            .with_push_content_type(self.push_content_type.clone())
+            .with_max_push_bytes(self.max_push_bytes)
+            .with_max_push_lines(self.max_push_lines)
+            .with_push_compression(self.push_compression)
+            .with_max_push_concurrency(self.max_push_concurrency)?
+            .with_detailed_result(self.detailed_result)
+            .with_overwrite_delete(self.overwrite_delete.clone());
+        let exec = Self {
+            client: self.client.clone(),
+            ..exec
+        };
         Ok(Arc::new(exec))
     }
 
+    /// Pushes all input partitions, up to `max_push_concurrency` at a time, into the
+    /// single output partition.
     fn execute(
         &self,
         partition: usize,
         context: Arc<TaskContext>,
     ) -> DFResult<SendableRecordBatchStream> {
-        let mut input_stream = self.input.execute(partition, context)?;
-
-        let endpoint = self.endpoint.clone();
-        let client = self.client.clone();
+        if partition != 0 {
+            return internal_err!(
+                "LokiLogInsertExec has a single partition, cannot execute partition {partition}"
+            );
+        }
+        let input_partitions = self.input.output_partitioning().partition_count();
+        let partition_pushes = (0..input_partitions)
+            .map(|input_partition| {
+                let input_stream = self.input.execute(input_partition, context.clone())?;
+                let pusher = LogPusher {
+                    endpoint: self.endpoint.clone(),
+                    client: self.client.get()?,
+                    http_options: self.http_options.clone(),
+                    duplicate_label_policy: self.duplicate_label_policy,
+                    clock_skew_tolerance: self.clock_skew_tolerance,
+                    content_type: self.push_content_type.clone(),
+                    max_push_bytes: self.max_push_bytes,
+                    max_push_lines: self.max_push_lines,
+                    compression: self.push_compression,
+                    tolerate_failures: self.detailed_result,
+                    metrics: PushMetrics::new(&self.metrics, input_partition),
+                };
+                let rows_pushed =
+                    MetricBuilder::new(&self.metrics).counter("rows_pushed", input_partition);
+                Ok(push_partition(input_stream, pusher, rows_pushed))
+            })
+            .collect::<DFResult<Vec<_>>>()?;
 
+        let max_push_concurrency = self.max_push_concurrency;
+        let detailed_result = self.detailed_result;
+        let overwrite_delete = match &self.overwrite_delete {
+            Some(request) => Some(LogDeleter {
+                endpoint: self.endpoint.clone(),
+                client: self.client.get()?,
+                http_options: self.http_options.clone(),
+                request: request.clone(),
+            }),
+            None => None,
+        };
         let stream = futures::stream::once(async move {
-            let mut count = 0;
-            while let Some(batch) = input_stream.next().await {
-                let batch = batch?;
-                push_logs(&endpoint, &client, &batch).await?;
-                count += batch.num_rows();
+            if let Some(deleter) = overwrite_delete {
+                deleter.delete().await?;
             }
-            make_result_batch(count as i64)
+            let counts = futures::stream::iter(partition_pushes)
+                .buffer_unordered(max_push_concurrency)
+                .try_fold(
+                    PushCounts::default(),
+                    |counts, partition_counts| async move { Ok(counts.merge(partition_counts)) },
+                )
+                .await?;
+            make_result_batch(counts, detailed_result)
         })
         .boxed();
 
         Ok(Box::pin(RecordBatchStreamAdapter::new(
-            COUNT_SCHEMA.clone(),
+            self.schema(),
             stream,
         )))
     }
+
+    fn metrics(&self) -> Option<MetricsSet> {
+        Some(self.metrics.clone_inner())
+    }
 }
 
 impl DisplayAs for LokiLogInsertExec {
     fn fmt_as(&self, _t: DisplayFormatType, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "LokiLogInsertExec: endpoint={}", self.endpoint)?;
+        write!(f, "{}", self.http_options)?;
+        if let Some(request) = &self.overwrite_delete {
+            write!(
+                f,
+                ", overwrite_delete={} [{}, {}]",
+                request.query, request.start, request.end
+            )?;
+        }
         if let Ok(stats) = self.input.partition_statistics(None) {
             match stats.num_rows {
                 Precision::Exact(rows) => write!(f, ", rows={rows}")?,
@@ -133,38 +362,516 @@ impl DisplayAs for LokiLogInsertExec {
     }
 }
 
-fn make_result_batch(count: i64) -> DFResult<RecordBatch> {
-    let array = Arc::new(Int64Array::from(vec![count])) as ArrayRef;
-    let batch = RecordBatch::try_new(COUNT_SCHEMA.clone(), vec![array])?;
+/// Checks the input columns match [`LOG_TABLE_SCHEMA`] by position and type, so e.g.
+/// `ts`, `labels`, `msg` columns of a `SELECT` are accepted. Nullability is ignored as
+/// columns omitted from an insert column list are planned as nullable, and labels of any
+/// string encoding are accepted, e.g. `LargeUtf8` or dictionary-encoded keys and values.
+///
+/// The input may have a trailing structured metadata column, of any string map type,
+/// whose entries are attached to the logs.
+fn check_input_schema(schema: &SchemaRef) -> DFResult<()> {
+    let num_fields = LOG_TABLE_SCHEMA.fields().len();
+    let with_metadata = schema.fields().len() == num_fields + 1
+        && is_string_map_type(schema.field(num_fields).data_type());
+    let matched = (schema.fields().len() == num_fields || with_metadata)
+        && schema
+            .fields()
+            .iter()
+            .zip(LOG_TABLE_SCHEMA.fields())
+            .all(|(field, expected)| {
+                field.data_type() == expected.data_type()
+                    || (expected.name() == LABELS_FIELD_REF.name()
+                        && is_string_map_type(field.data_type()))
+            });
+    if !matched {
+        let format_fields = |fields: &[FieldRef]| {
+            fields
+                .iter()
+                .map(|field| format!("{} {}", field.name(), field.data_type()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        return plan_err!(
+            "Insert input columns ({}) don't match the expected columns ({}), optionally followed by {} {}",
+            format_fields(schema.fields()),
+            format_fields(LOG_TABLE_SCHEMA.fields()),
+            STRUCTURED_METADATA_FIELD_REF.name(),
+            STRUCTURED_METADATA_FIELD_REF.data_type()
+        );
+    }
+    Ok(())
+}
+
+/// Interprets an `Int64` timestamp column of `input` as epoch nanoseconds by casting it
+/// to the timestamp type of [`LOG_TABLE_SCHEMA`].
+pub(crate) fn cast_int64_timestamp_to_nanos(
+    input: Arc<dyn ExecutionPlan>,
+) -> DFResult<Arc<dyn ExecutionPlan>> {
+    let schema = input.schema();
+    if schema.fields().is_empty() || schema.field(0).data_type() != &DataType::Int64 {
+        return Ok(input);
+    }
+    cast_column(input, &TIMESTAMP_FIELD_REF, 0)
+}
+
+/// Casts a timestamp column of `input` in a unit other than nanoseconds to the timestamp
+/// type of [`LOG_TABLE_SCHEMA`].
+pub(crate) fn cast_timestamp_to_nanos(
+    input: Arc<dyn ExecutionPlan>,
+) -> DFResult<Arc<dyn ExecutionPlan>> {
+    let schema = input.schema();
+    if schema.fields().is_empty()
+        || !matches!(
+            schema.field(0).data_type(),
+            DataType::Timestamp(unit, _) if *unit != TimeUnit::Nanosecond
+        )
+    {
+        return Ok(input);
+    }
+    cast_column(input, &TIMESTAMP_FIELD_REF, 0)
+}
+
+/// Casts a dictionary-encoded labels column of `input` back to the labels type of
+/// [`LOG_TABLE_SCHEMA`].
+pub(crate) fn cast_dictionary_labels(
+    input: Arc<dyn ExecutionPlan>,
+) -> DFResult<Arc<dyn ExecutionPlan>> {
+    let schema = input.schema();
+    if schema.fields().len() < 2
+        || schema.field(1).data_type() != DICTIONARY_LABELS_FIELD_REF.data_type()
+    {
+        return Ok(input);
+    }
+    cast_column(input, &LABELS_FIELD_REF, 1)
+}
+
+/// Projects `input` with the column at `index` cast into `field`.
+fn cast_column(
+    input: Arc<dyn ExecutionPlan>,
+    field: &FieldRef,
+    index: usize,
+) -> DFResult<Arc<dyn ExecutionPlan>> {
+    let exprs = input
+        .schema()
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(i, input_field)| {
+            let column = Arc::new(Column::new(input_field.name(), i)) as Arc<dyn PhysicalExpr>;
+            if i == index {
+                let cast = CastExpr::new(column, field.data_type().clone(), None);
+                (
+                    Arc::new(cast) as Arc<dyn PhysicalExpr>,
+                    field.name().clone(),
+                )
+            } else {
+                (column, input_field.name().clone())
+            }
+        })
+        .collect::<Vec<_>>();
+    Ok(Arc::new(ProjectionExec::try_new(exprs, input)?))
+}
+
+/// Pushes the batches of an input partition.
+async fn push_partition(
+    mut input_stream: SendableRecordBatchStream,
+    pusher: LogPusher,
+    rows_pushed: Count,
+) -> DFResult<PushCounts> {
+    let mut counts = PushCounts::default();
+    while let Some(batch) = input_stream.next().await {
+        let batch = batch?;
+        let batch_counts = pusher.push(&batch).await?;
+        rows_pushed.add(batch_counts.pushed);
+        counts = counts.merge(batch_counts);
+    }
+    Ok(counts)
+}
+
+/// What an insert pushed, see [`DETAILED_INSERT_RESULT_SCHEMA`].
+#[derive(Debug, Default)]
+struct PushCounts {
+    pushed: usize,
+    failed: usize,
+    streams: usize,
+}
+
+impl PushCounts {
+    fn merge(self, other: PushCounts) -> Self {
+        Self {
+            pushed: self.pushed + other.pushed,
+            failed: self.failed + other.failed,
+            streams: self.streams + other.streams,
+        }
+    }
+}
+
+fn make_result_batch(counts: PushCounts, detailed_result: bool) -> DFResult<RecordBatch> {
+    let count_array = |count: usize| Arc::new(Int64Array::from(vec![count as i64])) as ArrayRef;
+    let batch = if detailed_result {
+        RecordBatch::try_new(
+            DETAILED_INSERT_RESULT_SCHEMA.clone(),
+            vec![
+                count_array(counts.pushed),
+                count_array(counts.failed),
+                count_array(counts.streams),
+            ],
+        )?
+    } else {
+        RecordBatch::try_new(COUNT_SCHEMA.clone(), vec![count_array(counts.pushed)])?
+    };
     Ok(batch)
 }
 
-async fn push_logs(endpoint: &str, client: &Client, batch: &RecordBatch) -> DFResult<()> {
-    let log_streams = build_log_streams(batch)?;
-    let resp = client
-        .post(format!("{endpoint}/loki/api/v1/push"))
-        .json(&log_streams)
-        .send()
-        .await
-        .map_err(|e| {
-            DataFusionError::Execution(format!("Failed to send push request to loki: {e:?}"))
-        })?;
-    let status = resp.status();
-    if !status.is_success() {
-        let text = resp.text().await.ok();
-        let with_text = if let Some(t) = text {
-            format!(", text: {t}")
+/// What's needed to push logs to loki, shared by the batches of a partition.
+struct LogPusher {
+    endpoint: String,
+    client: Client,
+    http_options: LokiHttpOptions,
+    duplicate_label_policy: DuplicateLabelPolicy,
+    clock_skew_tolerance: Option<Duration>,
+    content_type: String,
+    max_push_bytes: Option<usize>,
+    max_push_lines: Option<usize>,
+    compression: bool,
+    /// Counts the logs of failed push requests instead of failing.
+    tolerate_failures: bool,
+    metrics: PushMetrics,
+}
+
+/// Metrics of the push requests of an insert partition.
+struct PushMetrics {
+    push_requests: Count,
+    /// Size of the push request bodies, after compression.
+    push_bytes: Count,
+    /// Time spent on push requests, until loki responded.
+    push_time: Time,
+}
+
+impl PushMetrics {
+    fn new(metrics: &ExecutionPlanMetricsSet, partition: usize) -> Self {
+        Self {
+            push_requests: MetricBuilder::new(metrics).counter("push_requests", partition),
+            push_bytes: MetricBuilder::new(metrics).counter("push_bytes", partition),
+            push_time: MetricBuilder::new(metrics).subset_time("push_time", partition),
+        }
+    }
+}
+
+impl LogPusher {
+    /// Pushes the logs of `batch`, split into as many requests as the push limits require.
+    ///
+    /// Requests are sent one after another, so when one fails, those before it were
+    /// already stored by loki.
+    async fn push(&self, batch: &RecordBatch) -> DFResult<PushCounts> {
+        let max_timestamp = self.clock_skew_tolerance.map(|tolerance| {
+            current_timestamp_ns().saturating_add(tolerance.as_nanos().min(i64::MAX as u128) as i64)
+        });
+        let log_streams = build_log_streams(batch, self.duplicate_label_policy, max_timestamp)?;
+        let chunks = match (self.max_push_bytes, self.max_push_lines) {
+            (None, None) => vec![log_streams],
+            (max_bytes, max_lines) => chunk_log_streams(log_streams, max_bytes, max_lines)?,
+        };
+        let num_chunks = chunks.len();
+        let mut counts = PushCounts::default();
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let lines = chunk.streams.iter().map(|s| s.values.len()).sum::<usize>();
+            let streams = chunk.streams.len();
+            let body = serialize_json(&chunk)?;
+            if num_chunks > 1 {
+                debug!(
+                    "[datafusion-loki] pushing chunk {} of {num_chunks} with {lines} logs, {} bytes",
+                    i + 1,
+                    body.len()
+                );
+            }
+            let span = debug_span!(
+                "loki_push",
+                endpoint = %self.endpoint,
+                lines,
+                streams,
+                status = Empty,
+                bytes = Empty,
+            );
+            match self.send(body).instrument(span).await {
+                Ok(()) => {
+                    counts.pushed += lines;
+                    counts.streams += streams;
+                }
+                Err(e) if self.tolerate_failures => {
+                    warn!("[datafusion-loki] failed to push {lines} logs: {e}");
+                    counts.failed += lines;
+                }
+                Err(e) if num_chunks > 1 => {
+                    return Err(e.context(format!(
+                        "Failed to push chunk {} of {num_chunks}, {} logs of the batch already pushed",
+                        i + 1,
+                        counts.pushed
+                    )));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Sends a single push request, gzip-compressing the JSON body if enabled.
+    ///
+    /// Records the sent body size and the response status on the current span.
+    async fn send(&self, body: Vec<u8>) -> DFResult<()> {
+        let json_len = body.len();
+        let body = if self.compression {
+            let body = gzip(&body)?;
+            debug!(
+                "[datafusion-loki] compressed push request body from {json_len} to {} bytes",
+                body.len()
+            );
+            body
         } else {
-            "".to_string()
+            body
         };
-        return Err(DataFusionError::Execution(format!(
-            "Failed to send push request to loki with status {status}{with_text}",
-        )));
+        let span = Span::current();
+        span.record("bytes", body.len());
+        self.metrics.push_requests.add(1);
+        self.metrics.push_bytes.add(body.len());
+        let _timer = self.metrics.push_time.timer();
+        let mut req_builder = self
+            .http_options
+            .apply(
+                self.client
+                    .post(format!("{}/loki/api/v1/push", self.endpoint)),
+            )
+            .header(CONTENT_TYPE, self.content_type.as_str());
+        if self.compression {
+            req_builder = req_builder.header(CONTENT_ENCODING, "gzip");
+        }
+        let req_builder = req_builder.body(body);
+        let resp = self.http_options.send(req_builder).await.map_err(|e| {
+            self.http_options
+                .map_request_error(e, "Failed to send push request to loki")
+        })?;
+        let status = resp.status();
+        span.record("status", status.as_u16());
+        if !status.is_success() {
+            let with_text = read_error_text(resp)
+                .await
+                .map(|text| format!(", text: {text}"))
+                .unwrap_or_default();
+            return Err(status_error(
+                status,
+                format!("Failed to send push request to loki with status {status}{with_text}"),
+            ));
+        }
+        Ok(())
     }
-    Ok(())
 }
 
-fn build_log_streams(batch: &RecordBatch) -> DFResult<LogStreams> {
+/// Sends the delete of an overwrite and waits until loki processed it.
+struct LogDeleter {
+    endpoint: String,
+    client: Client,
+    http_options: LokiHttpOptions,
+    request: LokiDeleteRequest,
+}
+
+/// An entry of loki's delete request list.
+#[derive(Debug, Deserialize)]
+struct DeleteRequestEntry {
+    query: String,
+    /// Epoch seconds with millisecond precision.
+    start_time: f64,
+    end_time: f64,
+    status: String,
+    #[serde(default)]
+    created_at: f64,
+}
+
+impl LogDeleter {
+    async fn delete(&self) -> DFResult<()> {
+        let request = &self.request;
+        debug!(
+            "[datafusion-loki] deleting logs of {} in [{}, {}] before overwriting",
+            request.query, request.start, request.end
+        );
+        let (Some(start), Some(end)) = (
+            timestamp_ns_to_datetime(request.start),
+            timestamp_ns_to_datetime(request.end),
+        ) else {
+            return exec_err!(
+                "Delete range [{}, {}] is out of the supported timestamp range",
+                request.start,
+                request.end
+            );
+        };
+        let req_builder = self
+            .http_options
+            .apply(
+                self.client
+                    .post(format!("{}/loki/api/v1/delete", self.endpoint)),
+            )
+            .query(&[
+                ("query", request.query.clone()),
+                ("start", start.and_utc().to_rfc3339()),
+                ("end", end.and_utc().to_rfc3339()),
+            ]);
+        let resp = self.http_options.send(req_builder).await.map_err(|e| {
+            self.http_options
+                .map_request_error(e, "Failed to send delete request to loki")
+        })?;
+        let status = resp.status();
+        if !status.is_success() {
+            let with_text = read_error_text(resp)
+                .await
+                .map(|text| format!(", text: {text}"))
+                .unwrap_or_default();
+            return Err(status_error(
+                status,
+                format!(
+                    "Failed to send delete request to loki with status {status}, query: {}{with_text}",
+                    request.query
+                ),
+            ));
+        }
+
+        let deadline = Instant::now() + request.timeout;
+        loop {
+            if self.is_processed().await? {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return exec_err!(
+                    "Loki didn't process the delete of {} within {:?}, no logs were pushed",
+                    request.query,
+                    request.timeout
+                );
+            }
+            tokio::time::sleep(DELETE_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Whether the latest delete request of loki matching ours is processed.
+    async fn is_processed(&self) -> DFResult<bool> {
+        let req_builder = self.http_options.apply(
+            self.client
+                .get(format!("{}/loki/api/v1/delete", self.endpoint)),
+        );
+        let resp = self.http_options.send(req_builder).await.map_err(|e| {
+            self.http_options
+                .map_request_error(e, "Failed to list delete requests of loki")
+        })?;
+        let status = resp.status();
+        if !status.is_success() {
+            let with_text = read_error_text(resp)
+                .await
+                .map(|text| format!(", text: {text}"))
+                .unwrap_or_default();
+            return Err(status_error(
+                status,
+                format!("Failed to list delete requests of loki with status {status}{with_text}"),
+            ));
+        }
+        let entries = resp.json::<Vec<DeleteRequestEntry>>().await.map_err(|e| {
+            DataFusionError::Execution(format!("Failed to parse delete requests of loki: {e}"))
+        })?;
+        // Loki keeps milliseconds of the requested range
+        let start_ms = self.request.start.div_euclid(1_000_000);
+        let end_ms = self.request.end.div_euclid(1_000_000);
+        let latest = entries
+            .into_iter()
+            .filter(|entry| {
+                entry.query == self.request.query
+                    && ((entry.start_time * 1000.0).round() as i64 - start_ms).abs() <= 1
+                    && ((entry.end_time * 1000.0).round() as i64 - end_ms).abs() <= 1
+            })
+            .max_by(|a, b| a.created_at.total_cmp(&b.created_at));
+        Ok(latest.is_some_and(|entry| entry.status == "processed"))
+    }
+}
+
+fn gzip(data: &[u8]) -> DFResult<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .map_err(|e| DataFusionError::Execution(format!("Failed to compress push request: {e}")))
+}
+
+fn serialize_json<T: Serialize>(value: &T) -> DFResult<Vec<u8>> {
+    serde_json::to_vec(value)
+        .map_err(|e| DataFusionError::Execution(format!("Failed to serialize push request: {e}")))
+}
+
+/// Serialized size of a push payload without streams.
+const PAYLOAD_OVERHEAD_BYTES: usize = r#"{"streams":[]}"#.len();
+/// Serialized size of a stream without its labels and values.
+const STREAM_OVERHEAD_BYTES: usize = r#"{"stream":,"values":[]}"#.len();
+
+/// Splits `log_streams` into payloads of at most `max_bytes` serialized bytes and
+/// `max_lines` logs each, splitting streams across payloads where needed.
+///
+/// A single log exceeding `max_bytes` still gets a payload of its own, for loki to reject.
+fn chunk_log_streams(
+    log_streams: LogStreams,
+    max_bytes: Option<usize>,
+    max_lines: Option<usize>,
+) -> DFResult<Vec<LogStreams>> {
+    let max_bytes = max_bytes.unwrap_or(usize::MAX);
+    let max_lines = max_lines.unwrap_or(usize::MAX);
+    let mut chunks = Vec::new();
+    let mut current: Vec<LogStream> = Vec::new();
+    let mut bytes = PAYLOAD_OVERHEAD_BYTES;
+    let mut lines = 0;
+    for stream in log_streams.streams {
+        let stream_bytes = STREAM_OVERHEAD_BYTES + serialize_json(&stream.stream)?.len();
+        // Whether the last stream of the current chunk is this one
+        let mut open = false;
+        for value in stream.values {
+            let value_bytes = serialize_json(&value)?.len();
+            // Values and streams after the first are separated by a comma
+            let mut added = if open {
+                value_bytes + 1
+            } else {
+                stream_bytes + value_bytes + usize::from(!current.is_empty())
+            };
+            if !current.is_empty() && (bytes + added > max_bytes || lines >= max_lines) {
+                chunks.push(LogStreams {
+                    streams: std::mem::take(&mut current),
+                });
+                bytes = PAYLOAD_OVERHEAD_BYTES;
+                lines = 0;
+                open = false;
+                added = stream_bytes + value_bytes;
+            }
+            if !open {
+                current.push(LogStream {
+                    stream: stream.stream.clone(),
+                    values: Vec::new(),
+                });
+                open = true;
+            }
+            if let Some(last) = current.last_mut() {
+                last.values.push(value);
+            }
+            bytes += added;
+            lines += 1;
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(LogStreams { streams: current });
+    }
+    Ok(chunks)
+}
+
+/// Builds the push payload of `batch`, clamping timestamps to `max_timestamp` if given.
+///
+/// Rows with the same labels are pushed as a single stream, in order of their first row,
+/// with its values sorted by timestamp as loki requires. Non-empty structured metadata of
+/// a trailing metadata column is attached to its log.
+fn build_log_streams(
+    batch: &RecordBatch,
+    duplicate_label_policy: DuplicateLabelPolicy,
+    max_timestamp: Option<i64>,
+) -> DFResult<LogStreams> {
     let timestamp_arr = batch.column(0);
     let timestamp_arr = timestamp_arr
         .as_any()
@@ -182,43 +889,101 @@ fn build_log_streams(batch: &RecordBatch) -> DFResult<LogStreams> {
         .as_any()
         .downcast_ref::<StringArray>()
         .ok_or_else(|| DataFusionError::Execution("Failed to downcast line array".to_string()))?;
+    let metadata_arr = batch
+        .columns()
+        .get(LOG_TABLE_SCHEMA.fields().len())
+        .map(|arr| {
+            arr.as_any().downcast_ref::<MapArray>().ok_or_else(|| {
+                DataFusionError::Execution(
+                    "Failed to downcast structured metadata array".to_string(),
+                )
+            })
+        })
+        .transpose()?;
 
-    let streams = timestamp_arr
+    let mut streams = Vec::new();
+    let mut stream_indices = HashMap::new();
+    for (row, ((timestamp, labels), line)) in timestamp_arr
         .iter()
         .zip(labels_arr.iter())
         .zip(line_arr.iter())
-        .map(|((timestamp, labels), line)| {
-            let timestamp = timestamp.ok_or_else(|| {
-                DataFusionError::Execution("timestamp should not be null".to_string())
-            })?;
-            let label_map = if let Some(labels) = labels {
-                struct_arr_to_map(&labels)?
-            } else {
-                HashMap::new()
-            };
-            let line = line.map(|s| s.to_string()).unwrap_or_default();
-            Ok::<_, DataFusionError>(LogStream {
+        .enumerate()
+    {
+        let timestamp = timestamp.ok_or_else(|| {
+            DataFusionError::Execution("timestamp should not be null".to_string())
+        })?;
+        let timestamp = match max_timestamp {
+            Some(max_timestamp) if timestamp > max_timestamp => {
+                debug!(
+                    "[datafusion-loki] clamping future timestamp {timestamp} to {max_timestamp}"
+                );
+                max_timestamp
+            }
+            _ => timestamp,
+        };
+        let label_map = if let Some(labels) = labels {
+            struct_arr_to_map(&labels, duplicate_label_policy)?
+        } else {
+            BTreeMap::new()
+        };
+        let line = line.map(|s| s.to_string()).unwrap_or_default();
+        let metadata = match metadata_arr {
+            Some(arr) if arr.is_valid(row) => {
+                Some(struct_arr_to_map(&arr.value(row), duplicate_label_policy)?)
+                    .filter(|metadata| !metadata.is_empty())
+            }
+            _ => None,
+        };
+        let index = match stream_indices.get(&label_map) {
+            Some(index) => *index,
+            None => {
+                stream_indices.insert(label_map.clone(), streams.len());
+                streams.push((label_map, Vec::new()));
+                streams.len() - 1
+            }
+        };
+        streams[index].1.push((timestamp, line, metadata));
+    }
+
+    let streams = streams
+        .into_iter()
+        .map(|(label_map, mut values)| {
+            // Stable, so logs at the same timestamp keep their order
+            values.sort_by_key(|(timestamp, _, _)| *timestamp);
+            LogStream {
                 stream: label_map,
-                values: vec![[timestamp.to_string(), line.to_string()]],
-            })
+                values: values
+                    .into_iter()
+                    .map(|(timestamp, line, metadata)| match metadata {
+                        Some(metadata) => {
+                            LogValue::WithMetadata(timestamp.to_string(), line, metadata)
+                        }
+                        None => LogValue::Line(timestamp.to_string(), line),
+                    })
+                    .collect(),
+            }
         })
-        .collect::<Result<Vec<_>, _>>()?;
+        .collect();
     Ok(LogStreams { streams })
 }
 
-fn struct_arr_to_map(arr: &StructArray) -> DFResult<HashMap<String, String>> {
-    let keys_arr = arr.column(0);
+fn struct_arr_to_map(
+    arr: &StructArray,
+    duplicate_label_policy: DuplicateLabelPolicy,
+) -> DFResult<BTreeMap<String, String>> {
+    // Normalizes `LargeUtf8`, `Utf8View` and dictionary-encoded labels
+    let keys_arr = cast(arr.column(0), &DataType::Utf8)?;
     let keys_arr = keys_arr
         .as_any()
         .downcast_ref::<StringArray>()
         .ok_or_else(|| DataFusionError::Execution("Failed to downcast keys array".to_string()))?;
-    let values_arr = arr.column(1);
+    let values_arr = cast(arr.column(1), &DataType::Utf8)?;
     let values_arr = values_arr
         .as_any()
         .downcast_ref::<StringArray>()
         .ok_or_else(|| DataFusionError::Execution("Failed to downcast values array".to_string()))?;
 
-    let mut map = HashMap::new();
+    let mut map = BTreeMap::new();
     keys_arr
         .iter()
         .zip(values_arr.iter())
@@ -227,7 +992,22 @@ fn struct_arr_to_map(arr: &StructArray) -> DFResult<HashMap<String, String>> {
                 DataFusionError::Execution("label key should not be null".to_string())
             })?;
             let value = value.map(|v| v.to_string()).unwrap_or_default();
-            map.insert(key.to_string(), value);
+            match map.entry(key.to_string()) {
+                Entry::Vacant(entry) => {
+                    entry.insert(value);
+                }
+                Entry::Occupied(mut entry) => match duplicate_label_policy {
+                    DuplicateLabelPolicy::KeepFirst => {}
+                    DuplicateLabelPolicy::KeepLast => {
+                        entry.insert(value);
+                    }
+                    DuplicateLabelPolicy::Error => {
+                        return Err(DataFusionError::Execution(format!(
+                            "Duplicate label key {key} in labels map"
+                        )));
+                    }
+                },
+            }
             Ok::<_, DataFusionError>(())
         })
         .collect::<Result<Vec<_>, _>>()?;
@@ -242,6 +1022,15 @@ struct LogStreams {
 
 #[derive(Debug, Serialize, Deserialize)]
 struct LogStream {
-    stream: HashMap<String, String>,
-    values: Vec<[String; 2]>,
+    stream: BTreeMap<String, String>,
+    values: Vec<LogValue>,
+}
+
+/// A log of a [`LogStream`], serialized as `[timestamp, line]`, or with structured
+/// metadata as `[timestamp, line, {metadata}]`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum LogValue {
+    Line(String, String),
+    WithMetadata(String, String, BTreeMap<String, String>),
 }
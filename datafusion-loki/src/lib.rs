@@ -1,16 +1,25 @@
 mod codec;
 mod expr;
+mod function;
+mod http;
 mod insert;
+mod metric;
+#[allow(clippy::large_enum_variant)]
 pub(crate) mod protobuf;
 mod scan;
 mod table;
+mod tail;
 mod utils;
 
 pub use codec::*;
 pub use expr::*;
+pub use function::*;
+pub use http::*;
 pub use insert::*;
+pub use metric::*;
 pub use scan::*;
 pub use table::*;
+pub use tail::*;
 pub use utils::*;
 
 pub type DFResult<T> = Result<T, datafusion_common::DataFusionError>;
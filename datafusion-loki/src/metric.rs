@@ -0,0 +1,456 @@
+use std::{any::Any, collections::HashMap, sync::Arc};
+
+use arrow::{
+    array::{ArrayRef, Int64Array, RecordBatch, StringArray},
+    compute::cast,
+    datatypes::SchemaRef,
+};
+use datafusion_common::{
+    DataFusionError, ScalarValue,
+    config::ConfigOptions,
+    exec_err, internal_err,
+    tree_node::{Transformed, TreeNode, TreeNodeRecursion},
+};
+use datafusion_execution::{SendableRecordBatchStream, TaskContext};
+use datafusion_physical_expr::{
+    EquivalenceProperties, ScalarFunctionExpr,
+    expressions::{Column, Literal},
+};
+use datafusion_physical_optimizer::PhysicalOptimizerRule;
+use datafusion_physical_plan::{
+    DisplayAs, DisplayFormatType, ExecutionPlan, ExecutionPlanProperties, Partitioning,
+    PlanProperties,
+    aggregates::{AggregateExec, AggregateMode},
+    coalesce_partitions::CoalescePartitionsExec,
+    coop::CooperativeExec,
+    execution_plan::{Boundedness, EmissionType},
+    repartition::RepartitionExec,
+    stream::RecordBatchStreamAdapter,
+};
+use reqwest::Client;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{
+    DFResult, LokiHttpOptions, LokiLogScanExec, MapGet, SharedClient, current_timestamp_ns,
+    format_query_tags, read_error_text, status_error, thirty_days_before_now_timestamp_ns,
+};
+
+/// Evaluates a LogQL metric query over `[start, end)` with a single `query_range` step,
+/// returning a row per series with its `group_labels` values and its count.
+///
+/// Built by [`LokiMetricPushdown`] in place of counting scanned logs locally.
+#[derive(Debug, Clone)]
+pub struct LokiMetricScanExec {
+    pub endpoint: String,
+    /// Log query the counted logs match, e.g. `{app="my-app"} |= "error"`.
+    pub log_query: String,
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+    /// Labels the counts are grouped by, the first columns of `schema`.
+    pub group_labels: Vec<String>,
+    /// The group columns followed by the `Int64` count column.
+    pub schema: SchemaRef,
+    /// Tags sent in the `X-Query-Tags` header.
+    pub query_tags: HashMap<String, String>,
+    pub http_options: LokiHttpOptions,
+    pub(crate) client: SharedClient,
+    plan_properties: Arc<PlanProperties>,
+}
+
+impl LokiMetricScanExec {
+    pub fn try_new(
+        endpoint: String,
+        log_query: String,
+        start: Option<i64>,
+        end: Option<i64>,
+        group_labels: Vec<String>,
+        schema: SchemaRef,
+    ) -> DFResult<Self> {
+        if schema.fields().len() != group_labels.len() + 1 {
+            return internal_err!(
+                "LokiMetricScanExec schema should have {} fields, got {}",
+                group_labels.len() + 1,
+                schema.fields().len()
+            );
+        }
+        let plan_properties = PlanProperties::new(
+            EquivalenceProperties::new(schema.clone()),
+            Partitioning::UnknownPartitioning(1),
+            EmissionType::Final,
+            Boundedness::Bounded,
+        );
+        Ok(LokiMetricScanExec {
+            endpoint,
+            log_query,
+            start,
+            end,
+            group_labels,
+            schema,
+            query_tags: HashMap::new(),
+            http_options: LokiHttpOptions::default(),
+            client: SharedClient::default(),
+            plan_properties: Arc::new(plan_properties),
+        })
+    }
+
+    /// Sends requests with `client`, e.g. shared with other plans to reuse its connections.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = SharedClient::new(client);
+        self
+    }
+
+    pub fn with_query_tags(mut self, query_tags: HashMap<String, String>) -> Self {
+        self.query_tags = query_tags;
+        self
+    }
+
+    pub fn with_http_options(mut self, http_options: LokiHttpOptions) -> Self {
+        self.http_options = http_options;
+        self
+    }
+
+    /// The LogQL metric query counting the logs of `range` nanoseconds.
+    pub fn metric_query(&self, range: i64) -> String {
+        let count = format!("count_over_time({} [{range}ns])", self.log_query.trim_end());
+        if self.group_labels.is_empty() {
+            format!("sum({count})")
+        } else {
+            format!("sum by ({}) ({count})", self.group_labels.join(", "))
+        }
+    }
+}
+
+impl ExecutionPlan for LokiMetricScanExec {
+    fn name(&self) -> &str {
+        "LokiMetricScanExec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &Arc<PlanProperties> {
+        &self.plan_properties
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        _children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DFResult<Arc<dyn ExecutionPlan>> {
+        Ok(self)
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        _context: Arc<TaskContext>,
+    ) -> DFResult<SendableRecordBatchStream> {
+        if partition != 0 {
+            return exec_err!(
+                "LokiMetricScanExec has 1 partition, cannot execute partition {partition}"
+            );
+        }
+        let start = self.start.unwrap_or(thirty_days_before_now_timestamp_ns());
+        let end = self.end.unwrap_or(current_timestamp_ns());
+        // Range vectors select `(t - range, t]`, so evaluating at `end - 1` covers `[start, end)`
+        let time = end.saturating_sub(1);
+        let query = self.metric_query(end.saturating_sub(start).max(1));
+        debug!("[datafusion-loki] starting to query metric: {query}, time: {time}");
+
+        let mut req_builder = self
+            .http_options
+            .apply(
+                self.client
+                    .get()?
+                    .get(format!("{}/loki/api/v1/query_range", self.endpoint)),
+            )
+            .query(&[
+                ("query", query),
+                ("start", time.to_string()),
+                ("end", time.to_string()),
+                ("step", "1".to_string()),
+            ]);
+        if !self.query_tags.is_empty() {
+            req_builder = req_builder.header("X-Query-Tags", format_query_tags(&self.query_tags));
+        }
+
+        let http_options = self.http_options.clone();
+        let group_labels = self.group_labels.clone();
+        let schema = self.schema.clone();
+        let stream = futures::stream::once(async move {
+            let resp = http_options
+                .send(req_builder)
+                .await
+                .map_err(|e| http_options.map_request_error(e, "Failed to send request to loki"))?;
+            let status = resp.status();
+            if !status.is_success() {
+                let text = read_error_text(resp).await.unwrap_or_default();
+                return Err(status_error(
+                    status,
+                    format!("Metric query to loki failed with status {status}: {text}"),
+                ));
+            }
+            let resp: MetricResponse = resp.json().await.map_err(|e| {
+                DataFusionError::Execution(format!("Failed to decode loki metric response: {e}"))
+            })?;
+            metric_batch(resp.data.result, &group_labels, &schema)
+        });
+        Ok(Box::pin(RecordBatchStreamAdapter::new(
+            self.schema.clone(),
+            stream,
+        )))
+    }
+}
+
+impl DisplayAs for LokiMetricScanExec {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "LokiMetricScanExec: endpoint={}, query={}",
+            self.endpoint, self.log_query
+        )?;
+        write!(f, "{}", self.http_options)?;
+        if let Some(start) = self.start {
+            write!(f, ", start={}", start)?;
+        }
+        if let Some(end) = self.end {
+            write!(f, ", end={}", end)?;
+        }
+        write!(f, ", group_labels=[{}]", self.group_labels.join(", "))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MetricResponse {
+    data: MetricData,
+}
+
+#[derive(Debug, Deserialize)]
+struct MetricData {
+    #[serde(default)]
+    result: Vec<MetricSeries>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MetricSeries {
+    #[serde(default)]
+    metric: HashMap<String, String>,
+    /// `[<seconds>, "<value>"]` samples
+    #[serde(default)]
+    values: Vec<(serde_json::Value, String)>,
+}
+
+/// Converts the series of a metric response into a row per series.
+fn metric_batch(
+    series: Vec<MetricSeries>,
+    group_labels: &[String],
+    schema: &SchemaRef,
+) -> DFResult<RecordBatch> {
+    let mut counts = Vec::with_capacity(series.len());
+    for s in &series {
+        let mut count = 0;
+        for (_, value) in &s.values {
+            let Ok(value) = value.parse::<f64>() else {
+                return exec_err!("Failed to parse loki metric value {value}");
+            };
+            count += value as i64;
+        }
+        counts.push(count);
+    }
+    // An ungrouped count has a row even when loki has no series, i.e. no logs
+    if group_labels.is_empty() && series.is_empty() {
+        counts.push(0);
+    }
+
+    let mut columns = Vec::with_capacity(group_labels.len() + 1);
+    for (label, field) in group_labels.iter().zip(schema.fields()) {
+        let values = series
+            .iter()
+            .map(|s| s.metric.get(label).map(String::as_str))
+            .collect::<StringArray>();
+        columns.push(cast(&values, field.data_type())?);
+    }
+    columns.push(Arc::new(Int64Array::from(counts)) as ArrayRef);
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+/// Physical optimizer rule answering `count(*)` of loki logs, optionally grouped by
+/// `map_get(labels, '<label>')`, with a LogQL metric query instead of scanning all logs,
+/// e.g. for
+///
+/// ```sql
+/// select map_get(labels, 'app') as app, count(*) from loki where ... group by app
+/// ```
+///
+/// it queries `sum by (app) (count_over_time({...} [<range>]))`. Only scans whose
+/// filters were all pushed into their log query are rewritten. Register it with
+/// `SessionStateBuilder::with_physical_optimizer_rule`.
+///
+/// Loki groups by any label of the counted logs, so a grouping label that is also a
+/// structured metadata key may be counted by its metadata value.
+#[derive(Debug, Default)]
+pub struct LokiMetricPushdown;
+
+impl LokiMetricPushdown {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl PhysicalOptimizerRule for LokiMetricPushdown {
+    fn optimize(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        _config: &ConfigOptions,
+    ) -> DFResult<Arc<dyn ExecutionPlan>> {
+        plan.transform_down(|plan| {
+            let Some(exec) = pushdown_count(&plan)? else {
+                return Ok(Transformed::no(plan));
+            };
+            debug!(
+                "[datafusion-loki] pushed down count grouped by [{}] into a metric query",
+                exec.group_labels.join(", ")
+            );
+            let exec: Arc<dyn ExecutionPlan> = Arc::new(exec);
+            // Keeps the partitioning parents were planned against
+            let exec = match plan.output_partitioning() {
+                partitioning @ Partitioning::Hash(_, _) => {
+                    Arc::new(RepartitionExec::try_new(exec, partitioning.clone())?)
+                }
+                _ => exec,
+            };
+            Ok(Transformed::new(exec, true, TreeNodeRecursion::Jump))
+        })
+        .map(|t| t.data)
+    }
+
+    fn name(&self) -> &str {
+        "loki_metric_pushdown"
+    }
+
+    fn schema_check(&self) -> bool {
+        true
+    }
+}
+
+/// Builds the metric scan answering `plan` if it's a final count over a loki scan.
+fn pushdown_count(plan: &Arc<dyn ExecutionPlan>) -> DFResult<Option<LokiMetricScanExec>> {
+    let Some(aggregate) = plan.as_any().downcast_ref::<AggregateExec>() else {
+        return Ok(None);
+    };
+    // The aggregate that evaluates the count over the scanned rows
+    let raw = match aggregate.mode() {
+        AggregateMode::Single | AggregateMode::SinglePartitioned => aggregate,
+        AggregateMode::Final | AggregateMode::FinalPartitioned => {
+            match skip_data_movement(aggregate.input())
+                .as_any()
+                .downcast_ref::<AggregateExec>()
+            {
+                Some(partial) if *partial.mode() == AggregateMode::Partial => partial,
+                _ => return Ok(None),
+            }
+        }
+        _ => return Ok(None),
+    };
+    let Some(scan) = skip_data_movement(raw.input())
+        .as_any()
+        .downcast_ref::<LokiLogScanExec>()
+    else {
+        return Ok(None);
+    };
+    // Limits and truncated labels change which logs are counted
+    if scan.limit.is_some() || scan.max_labels_per_stream.is_some() || scan.fail_on_empty {
+        return Ok(None);
+    }
+    if !is_count_star(raw) {
+        return Ok(None);
+    }
+    let group_by = raw.group_expr();
+    if group_by.has_grouping_set() {
+        return Ok(None);
+    }
+    let mut group_labels = Vec::with_capacity(group_by.expr().len());
+    for (expr, _) in group_by.expr() {
+        match map_get_label(expr.as_any().downcast_ref::<ScalarFunctionExpr>(), scan) {
+            Some(label) => group_labels.push(label),
+            None => return Ok(None),
+        }
+    }
+    let exec = LokiMetricScanExec::try_new(
+        scan.endpoint.clone(),
+        scan.log_query.clone(),
+        scan.start,
+        scan.end,
+        group_labels,
+        aggregate.schema(),
+    )?
+    .with_query_tags(scan.query_tags.clone())
+    .with_http_options(scan.http_options.clone());
+    let exec = LokiMetricScanExec {
+        client: scan.client.clone(),
+        ..exec
+    };
+    Ok(Some(exec))
+}
+
+/// Skips the operators between aggregates and their scans that only move rows around.
+fn skip_data_movement(mut plan: &Arc<dyn ExecutionPlan>) -> &Arc<dyn ExecutionPlan> {
+    while plan.as_any().is::<RepartitionExec>()
+        || plan.as_any().is::<CoalescePartitionsExec>()
+        || plan.as_any().is::<CooperativeExec>()
+    {
+        plan = plan.children()[0];
+    }
+    plan
+}
+
+/// Whether the aggregate only computes an unfiltered `count(*)`.
+fn is_count_star(aggregate: &AggregateExec) -> bool {
+    let [count] = aggregate.aggr_expr() else {
+        return false;
+    };
+    count.fun().name() == "count"
+        && !count.is_distinct()
+        && aggregate.filter_expr().iter().all(Option::is_none)
+        && count.expressions().iter().all(|expr| {
+            expr.as_any()
+                .downcast_ref::<Literal>()
+                .is_some_and(|l| !l.value().is_null())
+        })
+}
+
+/// The label of a `map_get(labels, '<label>')` on the scan's labels column.
+fn map_get_label(expr: Option<&ScalarFunctionExpr>, scan: &LokiLogScanExec) -> Option<String> {
+    let expr = expr?;
+    if !expr.fun().inner().as_any().is::<MapGet>() {
+        return None;
+    }
+    let [map, key] = expr.args() else {
+        return None;
+    };
+    let column = map.as_any().downcast_ref::<Column>()?;
+    if !scan.is_labels_column(column.index()) {
+        return None;
+    }
+    let label = match key.as_any().downcast_ref::<Literal>()?.value() {
+        ScalarValue::Utf8(Some(label))
+        | ScalarValue::LargeUtf8(Some(label))
+        | ScalarValue::Utf8View(Some(label)) => label,
+        _ => return None,
+    };
+    is_label_name(label).then(|| label.clone())
+}
+
+/// Whether `name` is a valid label name, usable as is in LogQL, e.g. grouping clauses.
+pub(crate) fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
@@ -1,7 +1,10 @@
 // This file is @generated by prost-build.
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct LokiPhysicalPlanNode {
-    #[prost(oneof = "loki_physical_plan_node::LokiPhysicalPlanType", tags = "1, 2")]
+    #[prost(
+        oneof = "loki_physical_plan_node::LokiPhysicalPlanType",
+        tags = "1, 2, 3, 4"
+    )]
     pub loki_physical_plan_type:
         ::core::option::Option<loki_physical_plan_node::LokiPhysicalPlanType>,
 }
@@ -13,6 +16,10 @@ pub mod loki_physical_plan_node {
         Scan(super::LokiLogScanExec),
         #[prost(message, tag = "2")]
         Insert(super::LokiLogInsertExec),
+        #[prost(message, tag = "3")]
+        MetricScan(super::LokiMetricScanExec),
+        #[prost(message, tag = "4")]
+        Tail(super::LokiLogTailExec),
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -29,14 +36,351 @@ pub struct LokiLogScanExec {
     pub projection: ::core::option::Option<Projection>,
     #[prost(int32, optional, tag = "6")]
     pub limit: ::core::option::Option<i32>,
+    #[prost(message, optional, tag = "7")]
+    pub schema: ::core::option::Option<::datafusion_proto::protobuf::Schema>,
+    #[prost(map = "string, string", tag = "8")]
+    pub query_tags:
+        ::std::collections::HashMap<::prost::alloc::string::String, ::prost::alloc::string::String>,
+    #[prost(int64, optional, tag = "9")]
+    pub age_reference: ::core::option::Option<i64>,
+    #[prost(enumeration = "PartitionErrorPolicy", tag = "10")]
+    pub partition_error_policy: i32,
+    #[prost(message, optional, tag = "11")]
+    pub http_options: ::core::option::Option<LokiHttpOptions>,
+    #[prost(bool, tag = "12")]
+    pub structured_metadata: bool,
+    #[prost(uint32, tag = "13")]
+    pub target_partitions: u32,
+    #[prost(uint64, optional, tag = "14")]
+    pub max_entries_limit: ::core::option::Option<u64>,
+    #[prost(enumeration = "LokiDirection", tag = "15")]
+    pub direction: i32,
+    #[prost(uint64, tag = "16")]
+    pub batch_size: u64,
+    #[prost(bool, tag = "17")]
+    pub fail_on_empty: bool,
+    #[prost(message, optional, tag = "18")]
+    pub source_column: ::core::option::Option<SourceColumn>,
+    #[prost(uint64, optional, tag = "19")]
+    pub max_labels_per_stream: ::core::option::Option<u64>,
+    #[prost(bool, tag = "20")]
+    pub truncation_flag: bool,
+    #[prost(string, optional, tag = "21")]
+    pub step: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(bool, optional, tag = "22")]
+    pub response_compression: ::core::option::Option<bool>,
+    #[prost(message, optional, tag = "23")]
+    pub timestamp_range: ::core::option::Option<TimestampRange>,
+}
+#[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct TimestampRange {
+    #[prost(int64, tag = "1")]
+    pub min: i64,
+    #[prost(int64, tag = "2")]
+    pub max: i64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LokiMetricScanExec {
+    #[prost(string, tag = "1")]
+    pub endpoint: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub log_query: ::prost::alloc::string::String,
+    #[prost(int64, optional, tag = "3")]
+    pub start: ::core::option::Option<i64>,
+    #[prost(int64, optional, tag = "4")]
+    pub end: ::core::option::Option<i64>,
+    #[prost(string, repeated, tag = "5")]
+    pub group_labels: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(message, optional, tag = "6")]
+    pub schema: ::core::option::Option<::datafusion_proto::protobuf::Schema>,
+    #[prost(map = "string, string", tag = "7")]
+    pub query_tags:
+        ::std::collections::HashMap<::prost::alloc::string::String, ::prost::alloc::string::String>,
+    #[prost(message, optional, tag = "8")]
+    pub http_options: ::core::option::Option<LokiHttpOptions>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LokiLogTailExec {
+    #[prost(string, tag = "1")]
+    pub endpoint: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub log_query: ::prost::alloc::string::String,
+    #[prost(int64, optional, tag = "3")]
+    pub start: ::core::option::Option<i64>,
+    #[prost(message, optional, tag = "4")]
+    pub http_options: ::core::option::Option<LokiHttpOptions>,
+    #[prost(uint64, optional, tag = "5")]
+    pub max_duration_ms: ::core::option::Option<u64>,
+    #[prost(uint64, optional, tag = "6")]
+    pub max_lines: ::core::option::Option<u64>,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct SourceColumn {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub value: ::prost::alloc::string::String,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct LokiLogInsertExec {
     #[prost(string, tag = "1")]
     pub endpoint: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub http_options: ::core::option::Option<LokiHttpOptions>,
+    #[prost(enumeration = "DuplicateLabelPolicy", tag = "3")]
+    pub duplicate_label_policy: i32,
+    #[prost(uint64, optional, tag = "4")]
+    pub clock_skew_tolerance_ms: ::core::option::Option<u64>,
+    #[prost(string, optional, tag = "5")]
+    pub push_content_type: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(uint64, optional, tag = "6")]
+    pub max_push_bytes: ::core::option::Option<u64>,
+    #[prost(uint64, optional, tag = "7")]
+    pub max_push_lines: ::core::option::Option<u64>,
+    #[prost(bool, tag = "8")]
+    pub push_compression: bool,
+    #[prost(uint64, optional, tag = "9")]
+    pub max_push_concurrency: ::core::option::Option<u64>,
+    #[prost(bool, tag = "10")]
+    pub detailed_result: bool,
+    #[prost(message, optional, tag = "11")]
+    pub overwrite_delete: ::core::option::Option<LokiDeleteRequest>,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct LokiDeleteRequest {
+    #[prost(string, tag = "1")]
+    pub query: ::prost::alloc::string::String,
+    #[prost(int64, tag = "2")]
+    pub start: i64,
+    #[prost(int64, tag = "3")]
+    pub end: i64,
+    #[prost(uint64, tag = "4")]
+    pub timeout_ms: u64,
+}
+/// A `LokiLogTable` referenced by a logical plan.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LokiLogTable {
+    #[prost(string, tag = "1")]
+    pub endpoint: ::prost::alloc::string::String,
+    #[prost(string, optional, tag = "2")]
+    pub default_label: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(string, optional, tag = "3")]
+    pub mandatory_selector: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(message, optional, tag = "4")]
+    pub schema: ::core::option::Option<::datafusion_proto::protobuf::Schema>,
+    #[prost(map = "string, string", tag = "5")]
+    pub query_tags:
+        ::std::collections::HashMap<::prost::alloc::string::String, ::prost::alloc::string::String>,
+    #[prost(bool, tag = "6")]
+    pub int64_timestamp_as_nanos: bool,
+    #[prost(bool, tag = "7")]
+    pub age_column: bool,
+    #[prost(int64, optional, tag = "8")]
+    pub default_start: ::core::option::Option<i64>,
+    #[prost(int64, optional, tag = "9")]
+    pub default_end: ::core::option::Option<i64>,
+    #[prost(uint64, tag = "10")]
+    pub default_lookback_ms: u64,
+    #[prost(bool, tag = "11")]
+    pub structured_metadata_column: bool,
+    #[prost(message, optional, tag = "12")]
+    pub source_column: ::core::option::Option<SourceColumn>,
+    #[prost(map = "string, string", tag = "13")]
+    pub insert_default_labels:
+        ::std::collections::HashMap<::prost::alloc::string::String, ::prost::alloc::string::String>,
+    #[prost(enumeration = "DuplicateLabelPolicy", tag = "14")]
+    pub duplicate_label_policy: i32,
+    #[prost(uint64, optional, tag = "15")]
+    pub clock_skew_tolerance_ms: ::core::option::Option<u64>,
+    #[prost(string, tag = "16")]
+    pub push_content_type: ::prost::alloc::string::String,
+    #[prost(uint64, optional, tag = "17")]
+    pub max_push_bytes: ::core::option::Option<u64>,
+    #[prost(uint64, optional, tag = "18")]
+    pub max_push_lines: ::core::option::Option<u64>,
+    #[prost(bool, tag = "19")]
+    pub push_compression: bool,
+    #[prost(uint64, tag = "20")]
+    pub max_push_concurrency: u64,
+    #[prost(bool, tag = "21")]
+    pub detailed_insert_result: bool,
+    #[prost(bool, tag = "22")]
+    pub pushdown_verification: bool,
+    #[prost(bool, tag = "23")]
+    pub dictionary_labels: bool,
+    #[prost(enumeration = "TimestampUnit", tag = "24")]
+    pub timestamp_unit: i32,
+    #[prost(bool, tag = "25")]
+    pub sorted_labels: bool,
+    #[prost(enumeration = "PartitionErrorPolicy", tag = "26")]
+    pub partition_error_policy: i32,
+    #[prost(message, optional, tag = "27")]
+    pub http_options: ::core::option::Option<LokiHttpOptions>,
+    #[prost(uint64, optional, tag = "28")]
+    pub target_partitions: ::core::option::Option<u64>,
+    #[prost(uint64, optional, tag = "29")]
+    pub max_entries_limit: ::core::option::Option<u64>,
+    #[prost(bool, tag = "30")]
+    pub fail_on_empty: bool,
+    #[prost(uint64, optional, tag = "31")]
+    pub max_labels_per_stream: ::core::option::Option<u64>,
+    #[prost(bool, tag = "32")]
+    pub truncation_flag: bool,
+    #[prost(uint64, optional, tag = "33")]
+    pub scan_batch_size: ::core::option::Option<u64>,
+    #[prost(bool, tag = "34")]
+    pub response_compression: bool,
+    #[prost(uint64, optional, tag = "35")]
+    pub overwrite_delete_timeout_ms: ::core::option::Option<u64>,
+    #[prost(bool, tag = "36")]
+    pub timestamp_statistics: bool,
+    #[prost(uint64, optional, tag = "37")]
+    pub tail_max_duration_ms: ::core::option::Option<u64>,
+    #[prost(uint64, optional, tag = "38")]
+    pub tail_max_lines: ::core::option::Option<u64>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LokiHttpOptions {
+    #[prost(string, optional, tag = "1")]
+    pub bearer_token: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(message, optional, tag = "2")]
+    pub basic_auth: ::core::option::Option<LokiBasicAuth>,
+    #[prost(string, optional, tag = "3")]
+    pub tenant: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(message, repeated, tag = "4")]
+    pub headers: ::prost::alloc::vec::Vec<LokiHttpHeader>,
+    #[prost(uint64, optional, tag = "5")]
+    pub timeout_ms: ::core::option::Option<u64>,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct LokiHttpHeader {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub value: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct LokiBasicAuth {
+    #[prost(string, tag = "1")]
+    pub username: ::prost::alloc::string::String,
+    #[prost(string, optional, tag = "2")]
+    pub password: ::core::option::Option<::prost::alloc::string::String>,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct Projection {
     #[prost(uint32, repeated, tag = "1")]
     pub projection: ::prost::alloc::vec::Vec<u32>,
 }
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum LokiDirection {
+    Backward = 0,
+    Forward = 1,
+}
+impl LokiDirection {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::Backward => "BACKWARD",
+            Self::Forward => "FORWARD",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "BACKWARD" => Some(Self::Backward),
+            "FORWARD" => Some(Self::Forward),
+            _ => None,
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum PartitionErrorPolicy {
+    FailFast = 0,
+    SkipAndWarn = 1,
+}
+impl PartitionErrorPolicy {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::FailFast => "FAIL_FAST",
+            Self::SkipAndWarn => "SKIP_AND_WARN",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "FAIL_FAST" => Some(Self::FailFast),
+            "SKIP_AND_WARN" => Some(Self::SkipAndWarn),
+            _ => None,
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum DuplicateLabelPolicy {
+    KeepLast = 0,
+    KeepFirst = 1,
+    Error = 2,
+}
+impl DuplicateLabelPolicy {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::KeepLast => "KEEP_LAST",
+            Self::KeepFirst => "KEEP_FIRST",
+            Self::Error => "ERROR",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "KEEP_LAST" => Some(Self::KeepLast),
+            "KEEP_FIRST" => Some(Self::KeepFirst),
+            "ERROR" => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum TimestampUnit {
+    Nanosecond = 0,
+    Microsecond = 1,
+    Millisecond = 2,
+    Second = 3,
+}
+impl TimestampUnit {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::Nanosecond => "NANOSECOND",
+            Self::Microsecond => "MICROSECOND",
+            Self::Millisecond => "MILLISECOND",
+            Self::Second => "SECOND",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "NANOSECOND" => Some(Self::Nanosecond),
+            "MICROSECOND" => Some(Self::Microsecond),
+            "MILLISECOND" => Some(Self::Millisecond),
+            "SECOND" => Some(Self::Second),
+            _ => None,
+        }
+    }
+}
@@ -1,33 +1,121 @@
-use std::{any::Any, io::Cursor, pin::Pin, sync::Arc};
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    io::{Cursor, Write},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use arrow::array::RecordBatch;
-use datafusion_common::{DataFusionError, exec_err, project_schema};
+use arrow::{
+    array::{
+        Array, ArrayRef, AsArray, BooleanArray, DurationNanosecondArray, MapArray, RecordBatch,
+        RecordBatchOptions, StringArray, StructArray, TimestampNanosecondArray, UInt32Array,
+        new_empty_array, new_null_array,
+    },
+    buffer::OffsetBuffer,
+    compute::{can_cast_types, cast, filter_record_batch, take},
+    datatypes::{DataType, Field, FieldRef, Schema, SchemaRef},
+};
+use datafusion_common::{
+    DataFusionError, JoinType, ScalarValue, Statistics,
+    config::ConfigOptions,
+    exec_err, internal_err, plan_err, project_schema,
+    stats::Precision,
+    tree_node::{Transformed, TreeNode},
+};
 use datafusion_execution::{SendableRecordBatchStream, TaskContext};
-use datafusion_physical_expr::EquivalenceProperties;
+use datafusion_physical_expr::{EquivalenceProperties, PhysicalSortExpr, expressions::Column};
+use datafusion_physical_optimizer::PhysicalOptimizerRule;
 use datafusion_physical_plan::{
     DisplayAs, DisplayFormatType, ExecutionPlan, Partitioning, PlanProperties,
+    SortOrderPushdownResult,
+    coalesce_partitions::CoalescePartitionsExec,
+    coop::CooperativeExec,
     display::ProjectSchemaDisplay,
     execution_plan::{Boundedness, EmissionType},
+    joins::NestedLoopJoinExec,
+    metrics::{BaselineMetrics, Count, ExecutionPlanMetricsSet, MetricBuilder, MetricsSet, Time},
+    projection::ProjectionExec,
+    repartition::RepartitionExec,
     stream::RecordBatchStreamAdapter,
 };
+use flate2::write::GzDecoder;
 use futures::{Stream, StreamExt, TryStreamExt};
-use log::debug;
-use parquet::arrow::{ParquetRecordBatchStreamBuilder, ProjectionMask};
-use reqwest::{Client, RequestBuilder};
+use parquet::arrow::{
+    ParquetRecordBatchStreamBuilder, ProjectionMask, async_reader::AsyncFileReader,
+};
+use reqwest::{
+    Client, RequestBuilder, Response,
+    header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE},
+};
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tracing::{Instrument, Span, debug, debug_span, field::Empty, info_span, warn};
 
 use crate::{
-    DFResult, LOG_TABLE_SCHEMA, current_timestamp_ns, thirty_days_before_now_timestamp_ns,
+    AGE_FIELD_REF, DFResult, LOG_TABLE_SCHEMA, LokiHttpOptions, LokiStream,
+    STRUCTURED_METADATA_FIELD_REF, SharedClient, TRUNCATED_FIELD_REF, current_timestamp_ns,
+    has_sorted_keys, read_error_text, status_error, streams_batch,
+    thirty_days_before_now_timestamp_ns,
 };
 
-#[derive(Debug)]
+const TIMESTAMP_COLUMN_INDEX: usize = 0;
+const LABELS_COLUMN_INDEX: usize = 1;
+const LINE_COLUMN_INDEX: usize = 2;
+
+/// Loki's default `max_entries_limit_per_query`.
+pub const DEFAULT_MAX_ENTRIES_LIMIT: usize = 5000;
+
+/// Rows per batch decoded from loki's parquet responses, unless set otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 4096;
+
+#[derive(Debug, Clone)]
 pub struct LokiLogScanExec {
     pub endpoint: String,
     pub log_query: String,
     pub start: Option<i64>,
     pub end: Option<i64>,
+    /// Table schema the decoded batches are cast into, defaults to [`LOG_TABLE_SCHEMA`].
+    pub schema: SchemaRef,
     pub projection: Option<Vec<usize>>,
     pub limit: Option<usize>,
-    client: Client,
+    /// Tags sent in the `X-Query-Tags` header.
+    pub query_tags: HashMap<String, String>,
+    /// Emits the synthetic [`AGE_FIELD_REF`] column, measured from this timestamp (ns).
+    pub age_reference: Option<i64>,
+    /// Emits the [`STRUCTURED_METADATA_FIELD_REF`] column.
+    pub structured_metadata: bool,
+    /// Emits a constant `Utf8` column, named by the first element, holding the second.
+    pub source_column: Option<(String, String)>,
+    pub partition_error_policy: PartitionErrorPolicy,
+    pub http_options: LokiHttpOptions,
+    /// Splits scans bounded by both `start` and `end` into up to this many equal
+    /// time windows, each scanned by its own partition.
+    pub target_partitions: usize,
+    /// Loki's `max_entries_limit_per_query`, queries returning this many logs are
+    /// continued with follow-up queries for the rest of their time range.
+    pub max_entries_limit: Option<usize>,
+    pub direction: LokiDirection,
+    /// Rows per batch decoded from loki's parquet responses.
+    pub batch_size: usize,
+    /// Fails the scan if none of its partitions returned any logs.
+    pub fail_on_empty: bool,
+    /// Truncates each labels map to this many labels, the first by key.
+    pub max_labels_per_stream: Option<usize>,
+    /// Emits the synthetic [`TRUNCATED_FIELD_REF`] column.
+    pub truncation_flag: bool,
+    /// Loki's `step` query parameter, e.g. `30s`, bucketing the results of metric queries.
+    pub step: Option<String>,
+    /// Sends `Accept-Encoding: gzip`, letting loki or a proxy compress responses.
+    pub response_compression: bool,
+    /// Timestamps (ns) of the oldest and newest log matching the query when planned,
+    /// narrowing the timestamp statistics of the scan.
+    pub timestamp_range: Option<(i64, i64)>,
+    scan_row_count: Arc<Mutex<ScanRowCount>>,
+    response_metadata: Arc<Mutex<Vec<LokiResponseMetadata>>>,
+    metrics: ExecutionPlanMetricsSet,
+    pub(crate) client: SharedClient,
     plan_properties: Arc<PlanProperties>,
 }
 
@@ -41,26 +129,409 @@ impl LokiLogScanExec {
         limit: Option<usize>,
     ) -> DFResult<Self> {
         let projected_schema = project_schema(&LOG_TABLE_SCHEMA, projection.as_ref())?;
-        let plan_properties = PlanProperties::new(
-            EquivalenceProperties::new(projected_schema),
-            Partitioning::UnknownPartitioning(1),
-            EmissionType::Incremental,
-            Boundedness::Bounded,
-        );
-        let client = Client::builder()
-            .build()
-            .map_err(|e| DataFusionError::Plan(format!("Failed to build http client: {e}")))?;
+        let plan_properties = make_plan_properties(projected_schema, 1);
         Ok(LokiLogScanExec {
             endpoint,
             log_query,
             start,
             end,
+            schema: LOG_TABLE_SCHEMA.clone(),
             projection,
             limit,
-            client,
+            query_tags: HashMap::new(),
+            age_reference: None,
+            structured_metadata: false,
+            source_column: None,
+            partition_error_policy: PartitionErrorPolicy::default(),
+            http_options: LokiHttpOptions::default(),
+            target_partitions: 1,
+            max_entries_limit: Some(DEFAULT_MAX_ENTRIES_LIMIT),
+            direction: LokiDirection::default(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            fail_on_empty: false,
+            max_labels_per_stream: None,
+            truncation_flag: false,
+            step: None,
+            response_compression: true,
+            timestamp_range: None,
+            scan_row_count: Arc::new(Mutex::new(ScanRowCount::default())),
+            response_metadata: Arc::new(Mutex::new(Vec::new())),
+            metrics: ExecutionPlanMetricsSet::new(),
+            client: SharedClient::default(),
             plan_properties: Arc::new(plan_properties),
         })
     }
+
+    /// Casts decoded batches into `schema` instead of [`LOG_TABLE_SCHEMA`].
+    ///
+    /// Fields are matched to loki columns by name, falling back to the column at
+    /// the same position for renamed fields.
+    pub fn with_schema(mut self, schema: SchemaRef) -> DFResult<Self> {
+        self.schema = schema;
+        self.update_plan_properties()?;
+        Ok(self)
+    }
+
+    /// Sends requests with `client`, e.g. shared with other plans to reuse its connections.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = SharedClient::new(client);
+        self
+    }
+
+    pub fn with_query_tags(mut self, query_tags: HashMap<String, String>) -> Self {
+        self.query_tags = query_tags;
+        self
+    }
+
+    pub fn with_age_reference(mut self, age_reference: Option<i64>) -> DFResult<Self> {
+        self.age_reference = age_reference;
+        self.update_plan_properties()?;
+        Ok(self)
+    }
+
+    pub fn with_structured_metadata(mut self, structured_metadata: bool) -> DFResult<Self> {
+        self.structured_metadata = structured_metadata;
+        self.update_plan_properties()?;
+        Ok(self)
+    }
+
+    pub fn with_source_column(mut self, source_column: Option<(String, String)>) -> DFResult<Self> {
+        self.source_column = source_column;
+        self.update_plan_properties()?;
+        Ok(self)
+    }
+
+    pub fn with_partition_error_policy(
+        mut self,
+        partition_error_policy: PartitionErrorPolicy,
+    ) -> Self {
+        self.partition_error_policy = partition_error_policy;
+        self
+    }
+
+    pub fn with_http_options(mut self, http_options: LokiHttpOptions) -> Self {
+        self.http_options = http_options;
+        self
+    }
+
+    pub fn with_target_partitions(mut self, target_partitions: usize) -> DFResult<Self> {
+        self.target_partitions = target_partitions.max(1);
+        self.update_plan_properties()?;
+        Ok(self)
+    }
+
+    pub fn with_step(mut self, step: Option<String>) -> Self {
+        self.step = step;
+        self
+    }
+
+    pub fn with_response_compression(mut self, response_compression: bool) -> Self {
+        self.response_compression = response_compression;
+        self
+    }
+
+    pub fn with_max_entries_limit(mut self, max_entries_limit: Option<usize>) -> Self {
+        self.max_entries_limit = max_entries_limit;
+        self
+    }
+
+    pub fn with_timestamp_range(mut self, timestamp_range: Option<(i64, i64)>) -> Self {
+        self.timestamp_range = timestamp_range;
+        self
+    }
+
+    pub fn with_direction(mut self, direction: LokiDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn with_fail_on_empty(mut self, fail_on_empty: bool) -> Self {
+        self.fail_on_empty = fail_on_empty;
+        self
+    }
+
+    pub fn with_max_labels_per_stream(mut self, max_labels_per_stream: Option<usize>) -> Self {
+        self.max_labels_per_stream = max_labels_per_stream;
+        self
+    }
+
+    pub fn with_truncation_flag(mut self, truncation_flag: bool) -> DFResult<Self> {
+        self.truncation_flag = truncation_flag;
+        self.update_plan_properties()?;
+        Ok(self)
+    }
+
+    /// Projects the output, indices referring to [`Self::table_schema`].
+    ///
+    /// Should be applied after builders adding synthetic columns.
+    pub fn with_projection(mut self, projection: Option<Vec<usize>>) -> DFResult<Self> {
+        self.projection = projection;
+        self.update_plan_properties()?;
+        Ok(self)
+    }
+
+    /// Metadata of the loki responses received so far by executing this plan.
+    pub fn response_metadata(&self) -> Vec<LokiResponseMetadata> {
+        self.response_metadata
+            .lock()
+            .map(|m| m.clone())
+            .unwrap_or_default()
+    }
+
+    /// Totals of the query stats loki reported in the responses received so far.
+    pub fn query_stats(&self) -> LokiQueryStats {
+        self.response_metadata()
+            .iter()
+            .filter_map(|m| m.stats)
+            .fold(LokiQueryStats::default(), |total, stats| LokiQueryStats {
+                total_entries_returned: total.total_entries_returned + stats.total_entries_returned,
+                total_bytes_processed: total.total_bytes_processed + stats.total_bytes_processed,
+                exec_time: total.exec_time + stats.exec_time,
+            })
+    }
+
+    /// Whether the output column at `index` is loki's labels column.
+    pub(crate) fn is_labels_column(&self, index: usize) -> bool {
+        let Ok(columns) = self.output_columns() else {
+            return false;
+        };
+        let column = match &self.projection {
+            Some(proj) => proj.get(index).and_then(|i| columns.get(*i)),
+            None => columns.get(index),
+        };
+        matches!(column, Some(OutputColumn::Loki(LABELS_COLUMN_INDEX)))
+    }
+
+    /// Schema of all columns the scan can output, which `projection` refers to.
+    pub fn table_schema(&self) -> SchemaRef {
+        let mut fields = self.schema.fields().to_vec();
+        if self.structured_metadata {
+            fields.push(STRUCTURED_METADATA_FIELD_REF.clone());
+        }
+        if self.age_reference.is_some() {
+            fields.push(AGE_FIELD_REF.clone());
+        }
+        if let Some((name, _)) = &self.source_column {
+            fields.push(Arc::new(Field::new(name, DataType::Utf8, false)));
+        }
+        if self.truncation_flag {
+            fields.push(TRUNCATED_FIELD_REF.clone());
+        }
+        Arc::new(Schema::new_with_metadata(
+            fields,
+            self.schema.metadata().clone(),
+        ))
+    }
+
+    fn update_plan_properties(&mut self) -> DFResult<()> {
+        self.output_columns()?;
+        let projected_schema = project_schema(&self.table_schema(), self.projection.as_ref())?;
+        self.plan_properties = Arc::new(make_plan_properties(
+            projected_schema,
+            self.partition_windows().len(),
+        ));
+        Ok(())
+    }
+
+    /// The `[start, end)` time window queried by each partition.
+    ///
+    /// Only scans bounded on both ends are split, windows being adjacent so each log
+    /// falls into exactly one of them.
+    pub fn partition_windows(&self) -> Vec<(Option<i64>, Option<i64>)> {
+        let (Some(start), Some(end)) = (self.start, self.end) else {
+            return vec![(self.start, self.end)];
+        };
+        let range = end as i128 - start as i128;
+        if self.target_partitions <= 1 || range <= 1 {
+            return vec![(self.start, self.end)];
+        }
+        let partitions = (self.target_partitions as i128).min(range);
+        let boundary = |i: i128| (start as i128 + range * i / partitions) as i64;
+        (0..partitions)
+            .map(|i| (Some(boundary(i)), Some(boundary(i + 1))))
+            .collect()
+    }
+
+    /// Resolves how each field of [`Self::table_schema`] is produced.
+    fn output_columns(&self) -> DFResult<Vec<OutputColumn>> {
+        let mut columns = Vec::with_capacity(self.schema.fields().len() + 3);
+        for (i, field) in self.schema.fields().iter().enumerate() {
+            let source = LOG_TABLE_SCHEMA.index_of(field.name()).unwrap_or(i);
+            let Some(source_field) = LOG_TABLE_SCHEMA.fields().get(source) else {
+                return plan_err!("Field {} has no corresponding loki column", field.name());
+            };
+            if !can_cast_types(source_field.data_type(), field.data_type()) {
+                return plan_err!(
+                    "Cannot cast loki column {} of type {} to field {} of type {}",
+                    source_field.name(),
+                    source_field.data_type(),
+                    field.name(),
+                    field.data_type()
+                );
+            }
+            columns.push(OutputColumn::Loki(source));
+        }
+        if self.structured_metadata {
+            columns.push(OutputColumn::StructuredMetadata);
+        }
+        if let Some(reference) = self.age_reference {
+            columns.push(OutputColumn::Age(reference));
+        }
+        if let Some((name, value)) = &self.source_column {
+            if self.schema.field_with_name(name).is_ok() {
+                return plan_err!("Source column {name} conflicts with a table field");
+            }
+            columns.push(OutputColumn::Source(value.as_str().into()));
+        }
+        if self.truncation_flag {
+            columns.push(OutputColumn::Truncated);
+        }
+        Ok(columns)
+    }
+}
+
+/// Converts a loki timestamp (ns) into a statistics value of the timestamp field's type.
+fn timestamp_scalar(ns: i64, data_type: &DataType) -> Option<ScalarValue> {
+    ScalarValue::TimestampNanosecond(Some(ns), Some("UTC".into()))
+        .cast_to(data_type)
+        .ok()
+}
+
+/// Headers loki or its gateways may use to identify a query.
+const QUERY_ID_HEADERS: [&str; 2] = ["X-Query-Id", "X-Request-Id"];
+
+/// Metadata of a loki `query_range` response.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LokiResponseMetadata {
+    /// Query id for correlating with loki's own logs.
+    pub query_id: Option<String>,
+    pub stats: Option<LokiQueryStats>,
+}
+
+/// Parquet key-value metadata key under which loki's JSON stats block is returned.
+const STATS_METADATA_KEY: &str = "stats";
+
+/// Totals loki reports after executing a query, unlike the estimates of `index/stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LokiQueryStats {
+    pub total_entries_returned: u64,
+    pub total_bytes_processed: u64,
+    pub exec_time: Duration,
+}
+
+impl LokiQueryStats {
+    /// Parses the `summary` of loki's JSON stats block.
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        #[derive(Deserialize)]
+        struct Stats {
+            summary: Summary,
+        }
+        #[derive(Deserialize, Default)]
+        #[serde(rename_all = "camelCase", default)]
+        struct Summary {
+            total_entries_returned: u64,
+            total_bytes_processed: u64,
+            /// Seconds
+            exec_time: f64,
+        }
+
+        let summary = serde_json::from_str::<Stats>(json)?.summary;
+        Ok(LokiQueryStats {
+            total_entries_returned: summary.total_entries_returned,
+            total_bytes_processed: summary.total_bytes_processed,
+            exec_time: Duration::try_from_secs_f64(summary.exec_time).unwrap_or_default(),
+        })
+    }
+}
+
+/// How a scan handles a partition failing, e.g. its time range hitting a loki error.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PartitionErrorPolicy {
+    /// Fails the whole query.
+    #[default]
+    FailFast,
+    /// Logs the error and ends the failed partition, letting the other partitions
+    /// return their data.
+    ///
+    /// Query results are then silently incomplete, so only use this where partial
+    /// results beat no results, e.g. interactive exploration.
+    SkipAndWarn,
+}
+
+/// Which end of the time range loki returns logs from first, deciding which logs a
+/// limit keeps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LokiDirection {
+    /// Oldest logs first.
+    Forward,
+    /// Newest logs first, loki's default.
+    #[default]
+    Backward,
+}
+
+impl LokiDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LokiDirection::Forward => "forward",
+            LokiDirection::Backward => "backward",
+        }
+    }
+
+    /// Whether loki returns logs at timestamp `a` before those at `b`.
+    fn is_before(self, a: i64, b: i64) -> bool {
+        match self {
+            LokiDirection::Forward => a < b,
+            LokiDirection::Backward => a > b,
+        }
+    }
+
+    fn reverse(self) -> Self {
+        match self {
+            LokiDirection::Forward => LokiDirection::Backward,
+            LokiDirection::Backward => LokiDirection::Forward,
+        }
+    }
+}
+
+/// How a column of the scan output is produced.
+#[derive(Debug, Clone)]
+enum OutputColumn {
+    /// Cast from the loki column at this index of [`LOG_TABLE_SCHEMA`].
+    Loki(usize),
+    /// The optional structured metadata column of the response.
+    StructuredMetadata,
+    /// Elapsed time between the log timestamp and this reference timestamp (ns).
+    Age(i64),
+    /// This value on every row.
+    Source(Arc<str>),
+    /// Whether the partition returned as many logs as its limit, set once it has.
+    Truncated,
+}
+
+impl OutputColumn {
+    /// Name of the response column this column is produced from, if any.
+    fn source_name(&self) -> Option<&'static str> {
+        match self {
+            OutputColumn::Loki(i) => Some(LOG_TABLE_SCHEMA.field(*i).name()),
+            OutputColumn::StructuredMetadata => Some(STRUCTURED_METADATA_FIELD_REF.name()),
+            OutputColumn::Age(_) => Some(LOG_TABLE_SCHEMA.field(TIMESTAMP_COLUMN_INDEX).name()),
+            OutputColumn::Source(_) | OutputColumn::Truncated => None,
+        }
+    }
+}
+
+fn make_plan_properties(projected_schema: SchemaRef, partitions: usize) -> PlanProperties {
+    PlanProperties::new(
+        EquivalenceProperties::new(projected_schema),
+        Partitioning::UnknownPartitioning(partitions),
+        EmissionType::Incremental,
+        Boundedness::Bounded,
+    )
 }
 
 impl ExecutionPlan for LokiLogScanExec {
@@ -92,36 +563,120 @@ impl ExecutionPlan for LokiLogScanExec {
         partition: usize,
         _context: Arc<TaskContext>,
     ) -> DFResult<SendableRecordBatchStream> {
-        if partition != 0 {
-            return exec_err!("LokiLogScanExec does not support multiple partitions");
-        }
+        let windows = self.partition_windows();
+        let Some(&(start, end)) = windows.get(partition) else {
+            return exec_err!(
+                "LokiLogScanExec has {} partitions, cannot execute partition {partition}",
+                windows.len()
+            );
+        };
 
         debug!(
-            "[datafusion-loki] starting to scan logs: query: {}, start: {:?}, end: {:?}, limit: {:?}",
-            self.log_query, self.start, self.end, self.limit
+            "[datafusion-loki] starting to scan logs: query: {}, partition: {partition}, start: {start:?}, end: {end:?}, limit: {:?}",
+            self.log_query, self.limit
         );
 
-        let mut query = Vec::new();
-        query.push(("query", self.log_query.clone()));
-
-        let start = self.start.unwrap_or(thirty_days_before_now_timestamp_ns());
-        query.push(("start", start.to_string()));
-
-        let end = self.end.unwrap_or(current_timestamp_ns());
-        query.push(("end", end.to_string()));
-
-        if let Some(limit) = self.limit {
-            query.push(("limit", limit.to_string()));
+        let start = start.unwrap_or(thirty_days_before_now_timestamp_ns());
+        let end = end.unwrap_or(current_timestamp_ns());
+        let mut query = vec![("query", self.log_query.clone())];
+        if let Some(step) = &self.step {
+            query.push(("step", step.clone()));
         }
 
-        let req_builder = self
-            .client
-            .get(format!("{}/loki/api/v1/query_range", self.endpoint))
+        let mut req_builder = self
+            .http_options
+            .apply(
+                self.client
+                    .get()?
+                    .get(format!("{}/loki/api/v1/query_range", self.endpoint)),
+            )
             .header("Accept", "application/vnd.apache.parquet")
             .query(&query);
+        if self.response_compression {
+            req_builder = req_builder.header(ACCEPT_ENCODING, "gzip");
+        }
+        if !self.query_tags.is_empty() {
+            req_builder = req_builder.header("X-Query-Tags", format_query_tags(&self.query_tags));
+        }
+
+        let columns = self.output_columns()?;
+        let columns = match &self.projection {
+            Some(proj) => proj.iter().map(|i| columns[*i].clone()).collect::<Vec<_>>(),
+            None => columns,
+        };
+        let mut parquet_projection = columns
+            .iter()
+            .filter_map(|column| column.source_name())
+            .collect::<Vec<_>>();
+        // Needed to drop logs a backend returns at window boundaries twice
+        let partitioned = windows.len() > 1;
+        if partitioned {
+            parquet_projection.push(LOG_TABLE_SCHEMA.field(TIMESTAMP_COLUMN_INDEX).name());
+        }
+        // Needed to continue capped queries without repeating logs
+        if self.max_entries_limit.is_some() {
+            parquet_projection.push(LOG_TABLE_SCHEMA.field(TIMESTAMP_COLUMN_INDEX).name());
+            parquet_projection.push(LOG_TABLE_SCHEMA.field(LINE_COLUMN_INDEX).name());
+        }
+        parquet_projection.sort_unstable();
+        parquet_projection.dedup();
+
+        let fetcher = LogFetcher {
+            req_builder,
+            endpoint: self.endpoint.clone(),
+            log_query: self.log_query.clone(),
+            direction: self.direction,
+            http_options: self.http_options.clone(),
+            projection: parquet_projection,
+            batch_size: self.batch_size,
+            response_metadata: self.response_metadata.clone(),
+            metrics: ScanMetrics::new(&self.metrics, partition),
+        };
+        let stream = match self.max_entries_limit {
+            Some(max_entries_limit) => {
+                windowed_log_stream(fetcher, start, end, self.limit, max_entries_limit)
+            }
+            None => fetcher.fetch(start, end, self.limit),
+        };
 
-        let fut = fetch_log_stream(req_builder, self.projection.clone());
-        let stream = futures::stream::once(fut).try_flatten();
+        let truncated_index = columns
+            .iter()
+            .position(|column| matches!(column, OutputColumn::Truncated));
+        let projected_schema = self.schema();
+        let max_labels_per_stream = self.max_labels_per_stream;
+        let stream = stream.map(move |batch| {
+            let batch = batch?;
+            let batch = if partitioned {
+                filter_window(batch, start, end)?
+            } else {
+                batch
+            };
+            let batch = match max_labels_per_stream {
+                Some(max_labels) => truncate_labels(batch, max_labels)?,
+                None => batch,
+            };
+            adapt_batch(batch, &projected_schema, &columns)
+        });
+        let stream = match self.limit {
+            Some(limit) => limit_stream(stream.boxed(), limit),
+            None => stream.boxed(),
+        };
+        let stream = match (self.limit, truncated_index) {
+            (Some(limit), Some(index)) => flag_truncation_stream(stream, limit, index),
+            _ => stream,
+        };
+        let stream = match self.partition_error_policy {
+            PartitionErrorPolicy::FailFast => stream,
+            PartitionErrorPolicy::SkipAndWarn => skip_on_error_stream(stream, partition),
+        };
+        let stream = if self.fail_on_empty {
+            fail_on_empty_stream(stream, self.scan_row_count.clone(), windows.len())
+        } else {
+            stream
+        };
+        let baseline_metrics = BaselineMetrics::new(&self.metrics, partition);
+        let stream =
+            stream.inspect_ok(move |batch| baseline_metrics.record_output(batch.num_rows()));
         Ok(Box::pin(RecordBatchStreamAdapter::new(
             self.schema(),
             stream,
@@ -129,21 +684,194 @@ impl ExecutionPlan for LokiLogScanExec {
     }
 
     fn with_fetch(&self, limit: Option<usize>) -> Option<Arc<dyn ExecutionPlan>> {
-        Self::try_new(
-            self.endpoint.clone(),
-            self.log_query.clone(),
-            self.start,
-            self.end,
-            self.projection.clone(),
-            limit,
-        )
-        .ok()
-        .map(|exec| Arc::new(exec) as Arc<dyn ExecutionPlan>)
+        let mut exec = self.clone();
+        exec.limit = limit;
+        Some(Arc::new(exec))
     }
 
     fn fetch(&self) -> Option<usize> {
         self.limit
     }
+
+    fn metrics(&self) -> Option<MetricsSet> {
+        Some(self.metrics.clone_inner())
+    }
+
+    /// Loki reports nothing about a query's results up front, so only the limit, applied
+    /// by each partition, bounds the rows.
+    fn partition_statistics(&self, partition: Option<usize>) -> DFResult<Statistics> {
+        let partitions = self.properties().partitioning.partition_count();
+        if let Some(partition) = partition
+            && partition >= partitions
+        {
+            return internal_err!(
+                "LokiLogScanExec has {partitions} partitions, cannot get statistics of partition {partition}"
+            );
+        }
+        let schema = self.schema();
+        let mut statistics = Statistics::new_unknown(&schema);
+        if let Some(limit) = self.limit {
+            statistics.num_rows = match partition {
+                Some(_) => Precision::Inexact(limit),
+                None => Precision::Inexact(limit.saturating_mul(partitions)),
+            };
+        }
+
+        // Scanned logs lie within the queried window and the probed timestamp range
+        let (start, end) = match partition {
+            Some(partition) => self.partition_windows()[partition],
+            None => (self.start, self.end),
+        };
+        let min = [start, self.timestamp_range.map(|(min, _)| min)]
+            .into_iter()
+            .flatten()
+            .max();
+        let max = [
+            end.map(|end| end.saturating_sub(1)),
+            self.timestamp_range.map(|(_, max)| max),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        if let (Some(min), Some(max)) = (min, max)
+            && min > max
+        {
+            return Ok(statistics);
+        }
+        let columns = self.output_columns()?;
+        let output_columns = match &self.projection {
+            Some(proj) => proj.iter().filter_map(|i| columns.get(*i)).collect(),
+            None => columns.iter().collect::<Vec<_>>(),
+        };
+        for (i, column) in output_columns.into_iter().enumerate() {
+            if !matches!(column, OutputColumn::Loki(TIMESTAMP_COLUMN_INDEX)) {
+                continue;
+            }
+            let data_type = schema.field(i).data_type();
+            let column_statistics = &mut statistics.column_statistics[i];
+            if let Some(min) = min.and_then(|min| timestamp_scalar(min, data_type)) {
+                column_statistics.min_value = Precision::Inexact(min);
+            }
+            if let Some(max) = max.and_then(|max| timestamp_scalar(max, data_type)) {
+                column_statistics.max_value = Precision::Inexact(max);
+            }
+        }
+        Ok(statistics)
+    }
+
+    /// Queries loki in the direction of an `ORDER BY timestamp`, so a limit keeps the
+    /// logs the sort wants. Results stay unsorted across streams, so the sort is kept.
+    fn try_pushdown_sort(
+        &self,
+        order: &[PhysicalSortExpr],
+    ) -> DFResult<SortOrderPushdownResult<Arc<dyn ExecutionPlan>>> {
+        let Some(column) = order
+            .first()
+            .and_then(|sort| sort.expr.as_any().downcast_ref::<Column>())
+        else {
+            return Ok(SortOrderPushdownResult::Unsupported);
+        };
+        let columns = self.output_columns()?;
+        let output_column = match &self.projection {
+            Some(proj) => proj.get(column.index()).and_then(|i| columns.get(*i)),
+            None => columns.get(column.index()),
+        };
+        let ascending = match output_column {
+            Some(OutputColumn::Loki(TIMESTAMP_COLUMN_INDEX)) => LokiDirection::Forward,
+            // Ages grow as timestamps shrink
+            Some(OutputColumn::Age(_)) => LokiDirection::Backward,
+            _ => return Ok(SortOrderPushdownResult::Unsupported),
+        };
+        let direction = if order[0].options.descending {
+            ascending.reverse()
+        } else {
+            ascending
+        };
+        Ok(SortOrderPushdownResult::Inexact {
+            inner: Arc::new(self.clone().with_direction(direction)),
+        })
+    }
+}
+
+/// Physical optimizer rule fetching a single log for scans that only tell whether any
+/// log matches, e.g. the subquery of
+///
+/// ```sql
+/// select ... where exists (select 1 from loki where ...)
+/// ```
+///
+/// which DataFusion plans as a semi (or anti) join without join keys or filter, so
+/// the first row of the scan decides the join. Register it with
+/// `SessionStateBuilder::with_physical_optimizer_rule`.
+#[derive(Debug, Default)]
+pub struct LokiExistsLimit;
+
+impl LokiExistsLimit {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl PhysicalOptimizerRule for LokiExistsLimit {
+    fn optimize(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        _config: &ConfigOptions,
+    ) -> DFResult<Arc<dyn ExecutionPlan>> {
+        plan.transform_down(|plan| {
+            let Some(join) = plan.as_any().downcast_ref::<NestedLoopJoinExec>() else {
+                return Ok(Transformed::no(plan));
+            };
+            if join.filter().is_some() {
+                return Ok(Transformed::no(plan));
+            }
+            let (left, right) = match join.join_type() {
+                JoinType::LeftSemi | JoinType::LeftAnti => match limit_exists_scan(join.right())? {
+                    Some(right) => (join.left().clone(), right),
+                    None => return Ok(Transformed::no(plan)),
+                },
+                JoinType::RightSemi | JoinType::RightAnti => {
+                    match limit_exists_scan(join.left())? {
+                        Some(left) => (left, join.right().clone()),
+                        None => return Ok(Transformed::no(plan)),
+                    }
+                }
+                _ => return Ok(Transformed::no(plan)),
+            };
+            debug!("[datafusion-loki] limited the scan of an exists subquery to a single log");
+            Ok(Transformed::yes(plan.with_new_children(vec![left, right])?))
+        })
+        .map(|t| t.data)
+    }
+
+    fn name(&self) -> &str {
+        "loki_exists_limit"
+    }
+
+    fn schema_check(&self) -> bool {
+        true
+    }
+}
+
+/// Limits the loki scan below row-preserving `plan` to a single log, if there's one.
+fn limit_exists_scan(plan: &Arc<dyn ExecutionPlan>) -> DFResult<Option<Arc<dyn ExecutionPlan>>> {
+    if let Some(scan) = plan.as_any().downcast_ref::<LokiLogScanExec>() {
+        if scan.limit.is_some_and(|limit| limit <= 1) {
+            return Ok(None);
+        }
+        return Ok(scan.with_fetch(Some(1)));
+    }
+    if plan.as_any().is::<RepartitionExec>()
+        || plan.as_any().is::<CoalescePartitionsExec>()
+        || plan.as_any().is::<CooperativeExec>()
+        || plan.as_any().is::<ProjectionExec>()
+    {
+        let Some(child) = limit_exists_scan(plan.children()[0])? else {
+            return Ok(None);
+        };
+        return plan.clone().with_new_children(vec![child]).map(Some);
+    }
+    Ok(None)
 }
 
 impl DisplayAs for LokiLogScanExec {
@@ -153,12 +881,21 @@ impl DisplayAs for LokiLogScanExec {
             "LokiLogScanExec: endpoint={}, query={}",
             self.endpoint, self.log_query
         )?;
+        write!(f, "{}", self.http_options)?;
         if let Some(start) = self.start {
             write!(f, ", start={}", start)?;
         }
         if let Some(end) = self.end {
             write!(f, ", end={}", end)?;
         }
+        write!(f, ", direction={}", self.direction.as_str())?;
+        if let Some(step) = &self.step {
+            write!(f, ", step={step}")?;
+        }
+        let partitions = self.properties().partitioning.partition_count();
+        if partitions > 1 {
+            write!(f, ", partitions={partitions}")?;
+        }
         if self.projection.is_some() {
             let projected_schema = self.schema();
             write!(
@@ -174,43 +911,933 @@ impl DisplayAs for LokiLogScanExec {
     }
 }
 
+pub(crate) type SendableBatchStream = Pin<Box<dyn Stream<Item = DFResult<RecordBatch>> + Send>>;
+
+/// Parquet key-value metadata keys under which a backend may return a pagination cursor.
+const CURSOR_METADATA_KEYS: [&str; 2] = ["next", "cursor"];
+/// Query parameter the pagination cursor is sent back with.
+const CURSOR_PARAM: &str = "cursor";
+
+/// Issues `query_range` requests sharing the same query and options.
+#[derive(Debug)]
+struct LogFetcher {
+    req_builder: RequestBuilder,
+    endpoint: String,
+    /// Included in errors, e.g. to show which LogQL loki failed to parse.
+    log_query: String,
+    direction: LokiDirection,
+    http_options: LokiHttpOptions,
+    projection: Vec<&'static str>,
+    batch_size: usize,
+    response_metadata: Arc<Mutex<Vec<LokiResponseMetadata>>>,
+    metrics: ScanMetrics,
+}
+
+/// Metrics of a scan partition, besides its output rows.
+#[derive(Debug, Clone)]
+struct ScanMetrics {
+    /// Size of the parquet response bodies, after decompression.
+    bytes_scanned: Count,
+    /// Time spent sending requests and receiving their response bodies.
+    http_request_time: Time,
+    /// Time spent decoding parquet response bodies into batches.
+    parquet_decode_time: Time,
+}
+
+impl ScanMetrics {
+    fn new(metrics: &ExecutionPlanMetricsSet, partition: usize) -> Self {
+        Self {
+            bytes_scanned: MetricBuilder::new(metrics).counter("bytes_scanned", partition),
+            http_request_time: MetricBuilder::new(metrics)
+                .subset_time("http_request_time", partition),
+            parquet_decode_time: MetricBuilder::new(metrics)
+                .subset_time("parquet_decode_time", partition),
+        }
+    }
+}
+
+impl LogFetcher {
+    /// Fetches the logs within `[start, end)`, up to `limit` of them.
+    fn fetch(&self, start: i64, end: i64, limit: Option<usize>) -> SendableBatchStream {
+        let mut query = vec![
+            ("start", start.to_string()),
+            ("end", end.to_string()),
+            ("direction", self.direction.as_str().to_string()),
+        ];
+        if let Some(limit) = limit {
+            query.push(("limit", limit.to_string()));
+        }
+        let span = info_span!(
+            "loki_query_range",
+            endpoint = %self.endpoint,
+            log_query = %self.log_query,
+            start,
+            end,
+            limit,
+        );
+        match self.req_builder.try_clone() {
+            Some(req_builder) => paged_log_stream(
+                req_builder.query(&query),
+                span,
+                self.log_query.clone(),
+                self.http_options.clone(),
+                self.projection.clone(),
+                self.batch_size,
+                self.response_metadata.clone(),
+                self.metrics.clone(),
+            ),
+            None => {
+                let err = DataFusionError::Execution("Failed to clone loki request".to_string());
+                futures::stream::once(async { Err(err) }).boxed()
+            }
+        }
+    }
+}
+
+/// State of [`windowed_log_stream`].
+struct WindowState {
+    fetcher: LogFetcher,
+    start: i64,
+    end: i64,
+    /// Rows still wanted, `None` for the full range.
+    remaining: Option<usize>,
+    max_entries_limit: usize,
+    current: Option<SendableBatchStream>,
+    /// Limit sent with the current query.
+    query_limit: usize,
+    /// Rows returned by the current query.
+    query_rows: usize,
+    /// Last timestamp of the current query in the scan direction and the lines seen at it.
+    last: Option<(i64, HashSet<String>)>,
+    /// Where the current query continues the previous one, logs at it already emitted
+    /// by the previous one having these lines.
+    boundary: Option<(i64, HashSet<String>)>,
+}
+
+/// Fetches the logs within `[start, end)`, splitting it into consecutive queries when a
+/// query returns loki's `max_entries_limit` logs, as loki silently truncates such results.
+///
+/// Loki returns logs in the scan direction, so each follow-up query continues from the
+/// last timestamp returned so far, e.g. ending at the oldest for backward scans. Logs
+/// sharing that timestamp may straddle the two queries, so the follow-up includes it and
+/// skips lines already emitted at it.
+fn windowed_log_stream(
+    fetcher: LogFetcher,
+    start: i64,
+    end: i64,
+    limit: Option<usize>,
+    max_entries_limit: usize,
+) -> SendableBatchStream {
+    let query_limit = limit.map_or(max_entries_limit, |l| l.min(max_entries_limit));
+    let state = WindowState {
+        current: Some(fetcher.fetch(start, end, Some(query_limit))),
+        fetcher,
+        start,
+        end,
+        remaining: limit,
+        max_entries_limit,
+        query_limit,
+        query_rows: 0,
+        last: None,
+        boundary: None,
+    };
+    futures::stream::unfold(state, |mut state| async move {
+        loop {
+            let current = state.current.as_mut()?;
+            match current.next().await {
+                Some(Ok(batch)) => match state.track_batch(batch) {
+                    Ok(batch) if batch.num_rows() == 0 => continue,
+                    Ok(batch) => return Some((Ok(batch), state)),
+                    Err(e) => {
+                        state.current = None;
+                        return Some((Err(e), state));
+                    }
+                },
+                Some(Err(e)) => {
+                    state.current = None;
+                    return Some((Err(e), state));
+                }
+                None => state.next_query(),
+            }
+        }
+    })
+    .boxed()
+}
+
+impl WindowState {
+    /// Records the rows of a decoded batch, dropping those the previous query emitted.
+    fn track_batch(&mut self, batch: RecordBatch) -> DFResult<RecordBatch> {
+        self.query_rows += batch.num_rows();
+        let Some(timestamps) = batch
+            .column_by_name(LOG_TABLE_SCHEMA.field(TIMESTAMP_COLUMN_INDEX).name())
+            .and_then(|array| array.as_any().downcast_ref::<TimestampNanosecondArray>())
+        else {
+            return Ok(batch);
+        };
+        let Some(lines) = batch.column_by_name(LOG_TABLE_SCHEMA.field(LINE_COLUMN_INDEX).name())
+        else {
+            return Ok(batch);
+        };
+        let lines = cast(lines, &DataType::Utf8)?;
+        let lines = lines.as_string::<i32>();
+
+        let mut keep = Vec::with_capacity(batch.num_rows());
+        for (ts, line) in timestamps.iter().zip(lines.iter()) {
+            let (Some(ts), Some(line)) = (ts, line) else {
+                keep.push(true);
+                continue;
+            };
+            match &mut self.last {
+                Some((last, lines)) if ts == *last => {
+                    lines.insert(line.to_string());
+                }
+                Some((last, _)) if self.fetcher.direction.is_before(ts, *last) => {}
+                _ => self.last = Some((ts, HashSet::from([line.to_string()]))),
+            }
+            keep.push(
+                !matches!(&self.boundary, Some((boundary, lines)) if ts == *boundary && lines.contains(line)),
+            );
+        }
+        let batch = filter_record_batch(&batch, &BooleanArray::from(keep))?;
+        if let Some(remaining) = &mut self.remaining {
+            *remaining = remaining.saturating_sub(batch.num_rows());
+        }
+        Ok(batch)
+    }
+
+    /// Starts the follow-up query if the current one was truncated by loki.
+    fn next_query(&mut self) {
+        self.current = None;
+        // Loki never returns more than asked, a server that did isn't capping results
+        if self.query_rows != self.query_limit
+            || self.query_limit < self.max_entries_limit
+            || self.remaining == Some(0)
+        {
+            return;
+        }
+        let Some((last, lines)) = self.last.take() else {
+            return;
+        };
+        // The follow-up only returned logs at its boundary, so can't make progress
+        if self
+            .boundary
+            .take()
+            .is_some_and(|(boundary, _)| boundary == last)
+        {
+            warn!(
+                "[datafusion-loki] more than {} logs at timestamp {last}, results will be incomplete",
+                self.max_entries_limit
+            );
+            return;
+        }
+        debug!(
+            "[datafusion-loki] query hit max entries limit {}, continuing from {last}",
+            self.max_entries_limit
+        );
+        // Also room for the lines at the boundary skipped as already emitted
+        self.query_limit = self.remaining.map_or(self.max_entries_limit, |r| {
+            (r + lines.len()).min(self.max_entries_limit)
+        });
+        self.query_rows = 0;
+        let (start, end) = match self.fetcher.direction {
+            LokiDirection::Forward => (last, self.end),
+            LokiDirection::Backward => (self.start, last.saturating_add(1)),
+        };
+        self.current = Some(self.fetcher.fetch(start, end, Some(self.query_limit)));
+        self.boundary = Some((last, lines));
+    }
+}
+
+/// Fetches logs, following pagination cursors returned by the backend until exhausted.
+///
+/// Pages are fetched lazily, so dropping the stream stops paging. Each page request is
+/// traced in a child span of `span`.
+#[allow(clippy::too_many_arguments)]
+fn paged_log_stream(
+    req_builder: RequestBuilder,
+    span: Span,
+    log_query: String,
+    http_options: LokiHttpOptions,
+    projection: Vec<&'static str>,
+    batch_size: usize,
+    response_metadata: Arc<Mutex<Vec<LokiResponseMetadata>>>,
+    metrics: ScanMetrics,
+) -> SendableBatchStream {
+    futures::stream::unfold(Some(None), move |cursor: Option<Option<String>>| {
+        let page_builder = req_builder.try_clone().map(|builder| match &cursor {
+            Some(Some(cursor)) => builder.query(&[(CURSOR_PARAM, cursor)]),
+            _ => builder,
+        });
+        let span = span.clone();
+        let log_query = log_query.clone();
+        let http_options = http_options.clone();
+        let projection = projection.clone();
+        let response_metadata = response_metadata.clone();
+        let metrics = metrics.clone();
+        async move {
+            let cursor = cursor?;
+            let request_span = debug_span!(
+                parent: &span,
+                "loki_request",
+                cursor = ?cursor,
+                status = Empty,
+                bytes = Empty,
+            );
+            let Some(page_builder) = page_builder else {
+                let err = DataFusionError::Execution("Failed to clone loki request".to_string());
+                return Some((futures::stream::once(async { Err(err) }).boxed(), None));
+            };
+            match fetch_log_stream(
+                page_builder,
+                &log_query,
+                &http_options,
+                projection,
+                batch_size,
+                response_metadata,
+                metrics,
+            )
+            .instrument(request_span)
+            .await
+            {
+                Ok((stream, next)) => Some((stream, next.map(Some))),
+                Err(e) => Some((futures::stream::once(async { Err(e) }).boxed(), None)),
+            }
+        }
+    })
+    .flatten()
+    .boxed()
+}
+
+/// Fetches a single page of logs, returning its batches and the cursor of the next page.
+///
+/// Records the response status and body size on the current span.
 async fn fetch_log_stream(
     req_builder: RequestBuilder,
-    projection: Option<Vec<usize>>,
-) -> DFResult<Pin<Box<dyn Stream<Item = DFResult<RecordBatch>> + Send>>> {
-    let resp = req_builder
-        .send()
+    log_query: &str,
+    http_options: &LokiHttpOptions,
+    projection: Vec<&'static str>,
+    batch_size: usize,
+    response_metadata: Arc<Mutex<Vec<LokiResponseMetadata>>>,
+    metrics: ScanMetrics,
+) -> DFResult<(SendableBatchStream, Option<String>)> {
+    let mut request_timer = metrics.http_request_time.timer();
+    let resp = http_options
+        .send(req_builder)
         .await
-        .map_err(|e| DataFusionError::Execution(format!("Failed to send request to loki: {e}")))?;
+        .map_err(|e| http_options.map_request_error(e, "Failed to send request to loki"))?;
+
+    let query_id = QUERY_ID_HEADERS.iter().find_map(|name| {
+        resp.headers()
+            .get(*name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    });
+    debug!(
+        "[datafusion-loki] received response with status {}, query id: {query_id:?}",
+        resp.status()
+    );
+    let record_metadata = |stats: Option<LokiQueryStats>| {
+        if let Some(stats) = &stats {
+            debug!("[datafusion-loki] query id: {query_id:?}, stats: {stats:?}");
+        }
+        if let Ok(mut metadata) = response_metadata.lock() {
+            metadata.push(LokiResponseMetadata {
+                query_id: query_id.clone(),
+                stats,
+            });
+        }
+    };
+    // E.g. loki builds without parquet support, or proxies rewriting the Accept header
+    let json_body = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
     let status = resp.status();
+    let span = Span::current();
+    span.record("status", status.as_u16());
     if !status.is_success() {
+        record_metadata(None);
         let url = resp.url().clone();
-        let with_text = if let Ok(text) = resp.text().await {
-            format!(", text: {text}")
-        } else {
-            String::new()
-        };
-        return exec_err!("Request to logi failed with status {status}, url: {url}{with_text}");
+        let with_text = read_error_text(resp)
+            .await
+            .map(|text| format!(", text: {text}"))
+            .unwrap_or_default();
+        warn!("[datafusion-loki] request to loki failed with status {status}{with_text}");
+        return Err(status_error(
+            status,
+            format!(
+                "Request to loki failed with status {status}, url: {url}, query: {log_query}{with_text}"
+            ),
+        ));
     }
-    let bytes = resp.bytes().await.map_err(|e| {
-        DataFusionError::Execution(format!("Failed to get response body as bytes: {e}"))
-    })?;
-    let cursor = Cursor::new(bytes);
+    let body = receive_body(resp, http_options).await;
+    request_timer.stop();
+    let decode_time = metrics.parquet_decode_time;
+    let page = match body {
+        Ok(body) if json_body => match body.into_bytes().await {
+            Ok(bytes) => {
+                debug!("[datafusion-loki] decoding JSON response instead of parquet");
+                span.record("bytes", bytes.len());
+                metrics.bytes_scanned.add(bytes.len());
+                read_json(&bytes, batch_size, decode_time)
+            }
+            Err(e) => Err(e),
+        },
+        Ok(ResponseBody::Memory(bytes)) => {
+            span.record("bytes", bytes.len());
+            metrics.bytes_scanned.add(bytes.len());
+            read_parquet(Cursor::new(bytes), projection, batch_size, decode_time).await
+        }
+        Ok(ResponseBody::Spilled(file)) => match file.metadata().await {
+            Ok(file_metadata) => {
+                span.record("bytes", file_metadata.len());
+                metrics.bytes_scanned.add(file_metadata.len() as usize);
+                read_parquet(file, projection, batch_size, decode_time).await
+            }
+            Err(e) => Err(e.into()),
+        },
+        Err(e) => Err(e),
+    };
+    match page {
+        Ok(page) => {
+            record_metadata(page.stats);
+            Ok((page.stream, page.next_cursor))
+        }
+        Err(e) => {
+            record_metadata(None);
+            Err(e)
+        }
+    }
+}
 
-    let builder = ParquetRecordBatchStreamBuilder::new(cursor).await?;
-    let parquet_schema = builder.parquet_schema();
+/// Response bodies larger than this are spilled to a temp file rather than buffered.
+const SPILL_THRESHOLD_BYTES: usize = 8 * 1024 * 1024;
+
+enum ResponseBody {
+    Memory(Vec<u8>),
+    Spilled(tokio::fs::File),
+}
+
+impl ResponseBody {
+    async fn into_bytes(self) -> DFResult<Vec<u8>> {
+        match self {
+            ResponseBody::Memory(bytes) => Ok(bytes),
+            ResponseBody::Spilled(mut file) => {
+                let mut bytes = Vec::new();
+                file.read_to_end(&mut bytes).await?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+/// Magic bytes every gzip stream starts with.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Receives the response body chunk by chunk, spilling it to an anonymous temp file once it
+/// exceeds [`SPILL_THRESHOLD_BYTES`].
+///
+/// Parquet keeps its metadata in the footer, so decoding can't start before the whole body
+/// is received, but spilling keeps memory bounded for large results.
+///
+/// The http client doesn't decompress responses, so gzip bodies, as announced by
+/// `Content-Encoding` or detected by their magic bytes, e.g. when compressed twice by a
+/// gateway, are decompressed here.
+async fn receive_body(resp: Response, http_options: &LokiHttpOptions) -> DFResult<ResponseBody> {
+    let gzip_encoded = resp
+        .headers()
+        .get(CONTENT_ENCODING)
+        .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"gzip"));
+    let mut chunks = resp.bytes_stream();
+    let mut body = BodyBuffer::default();
+    let mut decoder: Option<GzDecoder<Vec<u8>>> = None;
+    let mut first_chunk = true;
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk
+            .map_err(|e| http_options.map_request_error(e, "Failed to receive response body"))?;
+        if std::mem::take(&mut first_chunk) && (gzip_encoded || chunk.starts_with(&GZIP_MAGIC)) {
+            debug!("[datafusion-loki] decompressing gzip response body");
+            decoder = Some(GzDecoder::new(Vec::new()));
+        }
+        match &mut decoder {
+            Some(decoder) => {
+                decoder.write_all(&chunk).map_err(gzip_error)?;
+                body.append(&std::mem::take(decoder.get_mut())).await?;
+            }
+            None => body.append(&chunk).await?,
+        }
+    }
+    if let Some(decoder) = decoder {
+        let rest = decoder.finish().map_err(gzip_error)?;
+        body.append(&rest).await?;
+    }
+    body.finish().await
+}
+
+fn gzip_error(e: std::io::Error) -> DataFusionError {
+    DataFusionError::Execution(format!("Failed to decompress gzip response body: {e}"))
+}
+
+/// A response body being received, see [`receive_body`].
+#[derive(Default)]
+struct BodyBuffer {
+    buffer: Vec<u8>,
+    spill_file: Option<tokio::fs::File>,
+}
+
+impl BodyBuffer {
+    async fn append(&mut self, data: &[u8]) -> DFResult<()> {
+        match &mut self.spill_file {
+            Some(file) => file.write_all(data).await?,
+            None => {
+                self.buffer.extend_from_slice(data);
+                if self.buffer.len() > SPILL_THRESHOLD_BYTES {
+                    debug!("[datafusion-loki] spilling large response body to a temp file");
+                    let mut file = tokio::fs::File::from_std(tempfile::tempfile()?);
+                    file.write_all(&self.buffer).await?;
+                    self.buffer = Vec::new();
+                    self.spill_file = Some(file);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn finish(self) -> DFResult<ResponseBody> {
+        match self.spill_file {
+            Some(mut file) => {
+                file.flush().await?;
+                file.rewind().await?;
+                Ok(ResponseBody::Spilled(file))
+            }
+            None => Ok(ResponseBody::Memory(self.buffer)),
+        }
+    }
+}
 
-    let projection_mask = match projection {
-        Some(proj) => ProjectionMask::roots(parquet_schema, proj),
-        None => ProjectionMask::all(),
+/// A decoded response body.
+struct ResponsePage {
+    stream: SendableBatchStream,
+    next_cursor: Option<String>,
+    stats: Option<LokiQueryStats>,
+}
+
+/// Decodes a parquet body along with the pagination cursor and query stats in its metadata.
+///
+/// Row groups are only read and decoded as the returned stream is polled, so a consumer
+/// not taking batches holds back decoding, bounding memory beyond the received body,
+/// spilled to disk when large, to about one row group.
+///
+/// Reading the footer and polling the stream are recorded in `decode_time`.
+async fn read_parquet<R>(
+    reader: R,
+    projection: Vec<&'static str>,
+    batch_size: usize,
+    decode_time: Time,
+) -> DFResult<ResponsePage>
+where
+    R: AsyncFileReader + Unpin + Send + 'static,
+{
+    let footer_timer = decode_time.timer();
+    let builder = ParquetRecordBatchStreamBuilder::new(reader).await?;
+    footer_timer.done();
+    let parquet_schema = builder.parquet_schema();
+    let key_value_metadata = builder.metadata().file_metadata().key_value_metadata();
+    let metadata_value = |keys: &[&str]| {
+        key_value_metadata.and_then(|kvs| {
+            kvs.iter()
+                .find(|kv| keys.contains(&kv.key.as_str()))
+                .and_then(|kv| kv.value.clone())
+        })
     };
+    let next_cursor = metadata_value(&CURSOR_METADATA_KEYS).filter(|cursor| !cursor.is_empty());
+    let stats = metadata_value(&[STATS_METADATA_KEY]).and_then(|stats| {
+        LokiQueryStats::from_json(&stats)
+            .inspect_err(|e| warn!("[datafusion-loki] failed to parse query stats: {e}"))
+            .ok()
+    });
+
+    // Columns are resolved by name, tolerating optional columns absent from the response
+    let projection = projection
+        .iter()
+        .filter_map(|name| builder.schema().index_of(name).ok())
+        .collect::<Vec<_>>();
+    let projection_mask = ProjectionMask::roots(parquet_schema, projection);
 
-    let stream = builder
-        .with_batch_size(4096)
+    let mut stream = builder
+        .with_batch_size(batch_size)
         .with_projection(projection_mask)
         .build()?
-        .map_err(|e| DataFusionError::ParquetError(Box::new(e)))
-        .boxed();
+        .map_err(|e| DataFusionError::ParquetError(Box::new(e)));
+    let stream = futures::stream::poll_fn(move |cx| {
+        let _timer = decode_time.timer();
+        stream.poll_next_unpin(cx)
+    })
+    .boxed();
+
+    Ok(ResponsePage {
+        stream,
+        next_cursor,
+        stats,
+    })
+}
+
+/// Decodes the `streams` result of a JSON `query_range` response, as returned by servers
+/// not producing parquet, into batches of the parquet response columns.
+fn read_json(body: &[u8], batch_size: usize, decode_time: Time) -> DFResult<ResponsePage> {
+    #[derive(Deserialize)]
+    struct QueryResponse {
+        data: QueryData,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct QueryData {
+        result_type: String,
+        #[serde(default)]
+        result: serde_json::Value,
+        #[serde(default)]
+        stats: Option<serde_json::Value>,
+    }
+
+    let _timer = decode_time.timer();
+    let response: QueryResponse = serde_json::from_slice(body).map_err(|e| {
+        DataFusionError::Execution(format!("Failed to decode loki JSON response: {e}"))
+    })?;
+    if response.data.result_type != "streams" {
+        return exec_err!(
+            "Loki JSON response has result type {}, expected streams of logs",
+            response.data.result_type
+        );
+    }
+    let streams: Vec<LokiStream> = serde_json::from_value(response.data.result).map_err(|e| {
+        DataFusionError::Execution(format!("Failed to decode loki JSON response streams: {e}"))
+    })?;
+    let stats = response.data.stats.and_then(|stats| {
+        LokiQueryStats::from_json(&stats.to_string())
+            .inspect_err(|e| warn!("[datafusion-loki] failed to parse query stats: {e}"))
+            .ok()
+    });
+
+    let batch = streams_batch(&streams)?;
+    let batches = (0..batch.num_rows())
+        .step_by(batch_size)
+        .map(|offset| Ok(batch.slice(offset, batch_size.min(batch.num_rows() - offset))))
+        .collect::<Vec<_>>();
+    Ok(ResponsePage {
+        stream: futures::stream::iter(batches).boxed(),
+        next_cursor: None,
+        stats,
+    })
+}
+
+/// Truncates the stream to `limit` rows, dropping the underlying response as soon as
+/// the limit is reached, e.g. for `EXISTS`-style probes only needing a single row.
+///
+/// Dropping the stream cancels its in-flight request and the download of its response
+/// body, so consumers holding the stream after the limit don't keep the transfer going.
+pub(crate) fn limit_stream(stream: SendableBatchStream, limit: usize) -> SendableBatchStream {
+    let state = (limit > 0).then_some((stream, limit));
+    futures::stream::unfold(state, |state| async move {
+        let (mut stream, remaining) = state?;
+        match stream.next().await? {
+            Ok(batch) => {
+                let batch = batch.slice(0, batch.num_rows().min(remaining));
+                let remaining = remaining - batch.num_rows();
+                let state = (remaining > 0).then_some((stream, remaining));
+                Some((Ok(batch), state))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    })
+    .boxed()
+}
+
+/// Sets the [`TRUNCATED_FIELD_REF`] column at `index` of the batch with which the stream
+/// reaches `limit` rows, as loki may have had more logs to return.
+fn flag_truncation_stream(
+    stream: SendableBatchStream,
+    limit: usize,
+    index: usize,
+) -> SendableBatchStream {
+    let mut rows = 0;
+    stream
+        .map(move |batch| {
+            let batch = batch?;
+            let reached = rows < limit && rows + batch.num_rows() >= limit;
+            rows += batch.num_rows();
+            if !reached {
+                return Ok(batch);
+            }
+            let mut columns = batch.columns().to_vec();
+            columns[index] = Arc::new(BooleanArray::from(vec![true; batch.num_rows()]));
+            Ok(RecordBatch::try_new(batch.schema(), columns)?)
+        })
+        .boxed()
+}
+
+/// Ends the stream at its first error, logging it instead of failing the query.
+fn skip_on_error_stream(stream: SendableBatchStream, partition: usize) -> SendableBatchStream {
+    stream
+        .scan((), move |_, batch| {
+            let batch = match batch {
+                Ok(batch) => Some(Ok(batch)),
+                Err(e) => {
+                    warn!(
+                        "[datafusion-loki] skipping rest of failed partition {partition}, results will be incomplete: {e}"
+                    );
+                    None
+                }
+            };
+            futures::future::ready(batch)
+        })
+        .boxed()
+}
+
+/// Rows returned by the partitions of a scan finished so far.
+#[derive(Debug, Default)]
+struct ScanRowCount {
+    rows: usize,
+    finished_partitions: usize,
+}
+
+/// Counts the rows of a partition into `row_count`, failing the partition finishing last
+/// if none of the `partitions` returned any.
+fn fail_on_empty_stream(
+    stream: SendableBatchStream,
+    row_count: Arc<Mutex<ScanRowCount>>,
+    partitions: usize,
+) -> SendableBatchStream {
+    let counted = {
+        let row_count = row_count.clone();
+        stream.inspect_ok(move |batch| {
+            if let Ok(mut row_count) = row_count.lock() {
+                row_count.rows += batch.num_rows();
+            }
+        })
+    };
+    let check = futures::stream::once(async move {
+        let mut row_count = row_count
+            .lock()
+            .map_err(|e| DataFusionError::Execution(format!("Failed to lock row count: {e}")))?;
+        row_count.finished_partitions += 1;
+        if row_count.finished_partitions < partitions {
+            return Ok(());
+        }
+        // Reset for executing the plan again
+        let rows = std::mem::take(&mut *row_count).rows;
+        if rows == 0 {
+            return exec_err!("Loki query returned no results");
+        }
+        Ok(())
+    })
+    .try_filter_map(|()| futures::future::ready(Ok(None)));
+    counted.chain(check).boxed()
+}
+
+/// Formats tags as `key1=val1,key2=val2`, sorted by key for stable output.
+///
+/// `,` and `=` in keys and values are replaced by `_` so they can't forge other tags.
+pub(crate) fn format_query_tags(query_tags: &HashMap<String, String>) -> String {
+    let sanitize = |s: &str| s.replace([',', '='], "_");
+    let mut tags = query_tags
+        .iter()
+        .map(|(k, v)| format!("{}={}", sanitize(k), sanitize(v)))
+        .collect::<Vec<_>>();
+    tags.sort();
+    tags.join(",")
+}
+
+/// Casts and reorders a decoded batch into `schema`, `columns` describing how each
+/// field is produced.
+fn adapt_batch(
+    batch: RecordBatch,
+    schema: &SchemaRef,
+    columns: &[OutputColumn],
+) -> DFResult<RecordBatch> {
+    let mut arrays: Vec<ArrayRef> = Vec::with_capacity(columns.len());
+    for (field, column) in schema.fields().iter().zip(columns) {
+        if let OutputColumn::Source(value) = column {
+            let values = vec![value.as_ref(); batch.num_rows()];
+            arrays.push(Arc::new(StringArray::from(values)));
+            continue;
+        }
+        if let OutputColumn::Truncated = column {
+            arrays.push(Arc::new(BooleanArray::from(vec![false; batch.num_rows()])));
+            continue;
+        }
+        let Some(source_name) = column.source_name() else {
+            return internal_err!("Field {} has no response column", field.name());
+        };
+        let Some(array) = batch.column_by_name(source_name) else {
+            match column {
+                OutputColumn::StructuredMetadata => {
+                    arrays.push(make_empty_map_array(field, batch.num_rows())?);
+                    continue;
+                }
+                // E.g. a pipeline dropping the timestamp server-side
+                OutputColumn::Loki(_) if field.is_nullable() => {
+                    warn!(
+                        "[datafusion-loki] loki response is missing column {source_name}, filling field {} with nulls",
+                        field.name()
+                    );
+                    arrays.push(new_null_array(field.data_type(), batch.num_rows()));
+                    continue;
+                }
+                _ => {}
+            }
+            return exec_err!(
+                "Loki response is missing column {source_name}, declare field {} nullable to read it as nulls",
+                field.name()
+            );
+        };
+        let array = match column {
+            OutputColumn::Age(reference) => make_age_array(array, *reference)?,
+            OutputColumn::StructuredMetadata if array.data_type() == field.data_type() => {
+                array.clone()
+            }
+            OutputColumn::Loki(_) if array.data_type() == field.data_type() => array.clone(),
+            OutputColumn::Loki(_)
+            | OutputColumn::StructuredMetadata
+            | OutputColumn::Source(_)
+            | OutputColumn::Truncated => cast(array, field.data_type()).map_err(|e| {
+                DataFusionError::Execution(format!(
+                    "Failed to cast loki column {source_name} to field {} of type {}: {e}",
+                    field.name(),
+                    field.data_type()
+                ))
+            })?,
+        };
+        let array = if has_sorted_keys(field) {
+            sort_map_keys(&array)?
+        } else {
+            array
+        };
+        arrays.push(array);
+    }
+    let options = RecordBatchOptions::new().with_row_count(Some(batch.num_rows()));
+    let batch = RecordBatch::try_new_with_options(schema.clone(), arrays, &options)?;
+    Ok(batch)
+}
+
+/// Sorts the entries of each map by key, keeping the order of duplicate keys.
+fn sort_map_keys(array: &ArrayRef) -> DFResult<ArrayRef> {
+    let Some(map) = array.as_map_opt() else {
+        return exec_err!("Cannot sort keys of non-map type {}", array.data_type());
+    };
+    let keys = cast(map.keys(), &DataType::Utf8)?;
+    let keys = keys.as_string::<i32>();
+    let offsets = map.value_offsets();
+    let mut indices = Vec::with_capacity(keys.len());
+    for row in 0..map.len() {
+        let start = indices.len();
+        indices.extend(offsets[row] as u32..offsets[row + 1] as u32);
+        indices[start..].sort_by_key(|&i| keys.value(i as usize));
+    }
+    let entries = take(map.entries(), &UInt32Array::from(indices), None)?;
+    let (entries_field, offsets, _, nulls, ordered) = map.clone().into_parts();
+    let map = MapArray::try_new(
+        entries_field,
+        offsets,
+        entries.as_struct().clone(),
+        nulls,
+        ordered,
+    )?;
+    Ok(Arc::new(map))
+}
+
+/// Truncates the labels map of each row of a decoded batch to its first `max_labels`
+/// labels by key.
+fn truncate_labels(batch: RecordBatch, max_labels: usize) -> DFResult<RecordBatch> {
+    let Ok(index) = batch
+        .schema()
+        .index_of(LOG_TABLE_SCHEMA.field(LABELS_COLUMN_INDEX).name())
+    else {
+        return Ok(batch);
+    };
+    let Some(map) = batch.column(index).as_map_opt() else {
+        return Ok(batch);
+    };
+    let keys = cast(map.keys(), &DataType::Utf8)?;
+    let keys = keys.as_string::<i32>();
+    let offsets = map.value_offsets();
+    if offsets
+        .windows(2)
+        .all(|w| (w[1] - w[0]) as usize <= max_labels)
+    {
+        return Ok(batch);
+    }
+
+    let mut indices = Vec::with_capacity(keys.len());
+    let mut new_offsets = Vec::with_capacity(map.len() + 1);
+    new_offsets.push(0i32);
+    for row in 0..map.len() {
+        let mut row_indices = (offsets[row] as u32..offsets[row + 1] as u32).collect::<Vec<_>>();
+        row_indices.sort_by_key(|&i| keys.value(i as usize));
+        row_indices.truncate(max_labels);
+        indices.extend(row_indices);
+        new_offsets.push(indices.len() as i32);
+    }
+    let dropped = keys.len() - indices.len();
+    debug!("[datafusion-loki] dropped {dropped} labels exceeding {max_labels} per stream");
+
+    let entries = take(map.entries(), &UInt32Array::from(indices), None)?;
+    let (entries_field, _, _, nulls, ordered) = map.clone().into_parts();
+    let map = MapArray::try_new(
+        entries_field,
+        OffsetBuffer::new(new_offsets.into()),
+        entries.as_struct().clone(),
+        nulls,
+        ordered,
+    )?;
+    let mut columns = batch.columns().to_vec();
+    columns[index] = Arc::new(map);
+    Ok(RecordBatch::try_new(batch.schema(), columns)?)
+}
+
+/// Keeps the rows of a decoded batch within the `[start, end)` window.
+fn filter_window(batch: RecordBatch, start: i64, end: i64) -> DFResult<RecordBatch> {
+    let Some(timestamps) = batch
+        .column_by_name(LOG_TABLE_SCHEMA.field(TIMESTAMP_COLUMN_INDEX).name())
+        .and_then(|array| array.as_any().downcast_ref::<TimestampNanosecondArray>())
+    else {
+        return Ok(batch);
+    };
+    let mask = timestamps
+        .iter()
+        .map(|ts| Some(ts.is_none_or(|ts| start <= ts && ts < end)))
+        .collect::<BooleanArray>();
+    Ok(filter_record_batch(&batch, &mask)?)
+}
+
+/// Builds `num_rows` empty maps, for responses without structured metadata.
+fn make_empty_map_array(field: &FieldRef, num_rows: usize) -> DFResult<ArrayRef> {
+    let DataType::Map(entries_field, ordered) = field.data_type() else {
+        return exec_err!("Field {} is not a map", field.name());
+    };
+    let DataType::Struct(entry_fields) = entries_field.data_type() else {
+        return exec_err!("Map entries of field {} are not a struct", field.name());
+    };
+    let entries = StructArray::try_new(
+        entry_fields.clone(),
+        entry_fields
+            .iter()
+            .map(|f| new_empty_array(f.data_type()))
+            .collect(),
+        None,
+    )?;
+    let offsets = OffsetBuffer::new_zeroed(num_rows);
+    let array = MapArray::try_new(entries_field.clone(), offsets, entries, None, *ordered)?;
+    Ok(Arc::new(array))
+}
 
-    Ok(stream)
+fn make_age_array(timestamps: &ArrayRef, reference: i64) -> DFResult<ArrayRef> {
+    let timestamps = timestamps
+        .as_any()
+        .downcast_ref::<TimestampNanosecondArray>()
+        .ok_or_else(|| {
+            DataFusionError::Execution("Failed to downcast timestamp array".to_string())
+        })?;
+    let ages: DurationNanosecondArray = timestamps.unary(|ts| reference - ts);
+    Ok(Arc::new(ages))
 }
@@ -1,16 +1,41 @@
-use std::sync::{Arc, LazyLock};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
 
-use arrow::datatypes::{DataType, Field, FieldRef, Schema, SchemaRef, TimeUnit};
+use arrow::{
+    array::{
+        AsArray, Int64Array, ListBuilder, MapBuilder, MapFieldNames, RecordBatch, StringBuilder,
+        UInt32Array,
+    },
+    compute::{cast, concat_batches, take},
+    datatypes::{DataType, Field, FieldRef, Schema, SchemaRef, TimeUnit},
+    temporal_conversions::timestamp_ns_to_datetime,
+};
 use datafusion_catalog::{Session, TableProvider};
-use datafusion_common::{DataFusionError, exec_err};
+use datafusion_common::{DataFusionError, ScalarValue, exec_err, internal_err, plan_err};
 use datafusion_expr::{Expr, TableProviderFilterPushDown, TableType, dml::InsertOp};
-use datafusion_physical_plan::ExecutionPlan;
+use datafusion_physical_plan::{ExecutionPlan, collect};
+use reqwest::Client;
+use serde::Deserialize;
+use tracing::warn;
 
 use crate::{
-    DFResult, LokiLogInsertExec, LokiLogScanExec, TimestampBound, expr_to_label_filter,
-    expr_to_line_filter, parse_timestamp_bound,
+    DEFAULT_MAX_ENTRIES_LIMIT, DEFAULT_MAX_PUSH_CONCURRENCY, DEFAULT_PUSH_CONTENT_TYPE, DFResult,
+    DuplicateLabelPolicy, LokiBasicAuth, LokiDeleteRequest, LokiDirection, LokiHttpOptions,
+    LokiLogInsertExec, LokiLogScanExec, LokiLogTailExec, LokiRequestMiddleware,
+    PartitionErrorPolicy, TimestampBound, build_client, cast_dictionary_labels,
+    cast_int64_timestamp_to_nanos, cast_timestamp_to_nanos, current_timestamp_ns,
+    expr_to_inexact_line_filter, expr_to_label_filter, expr_to_line_filter,
+    expr_to_structured_metadata_filter, is_label_name, negated_label_filter, parse_age_bound,
+    parse_label_matcher, parse_timestamp_bound, read_error_text, status_error,
+    thirty_days_before_now_timestamp_ns,
 };
 
+/// Lookback of scans without a start bound, see [`LokiLogTable::with_default_lookback`].
+pub const DEFAULT_LOOKBACK: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
 pub static TIMESTAMP_FIELD_REF: LazyLock<FieldRef> = LazyLock::new(|| {
     Arc::new(Field::new(
         "timestamp",
@@ -25,9 +50,69 @@ pub static LABELS_FIELD_REF: LazyLock<FieldRef> = LazyLock::new(|| {
     let map_field = Arc::new(Field::new("key_value", entry_struct, false));
     Arc::new(Field::new("labels", DataType::Map(map_field, false), false))
 });
+/// Labels map with dictionary-encoded keys and values, see
+/// [`LokiLogTable::with_dictionary_labels`].
+pub static DICTIONARY_LABELS_FIELD_REF: LazyLock<FieldRef> = LazyLock::new(|| {
+    let dict_type = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+    let key_field = Field::new("key", dict_type.clone(), false);
+    let value_field = Field::new("value", dict_type, false);
+    let entry_struct = DataType::Struct(vec![key_field, value_field].into());
+    let map_field = Arc::new(Field::new("key_value", entry_struct, false));
+    Arc::new(Field::new("labels", DataType::Map(map_field, false), false))
+});
+/// Field metadata marking a map column whose entries are sorted by key within each row,
+/// see [`LokiLogTable::with_sorted_labels`].
+pub const SORTED_KEYS_METADATA_KEY: &str = "datafusion_loki.sorted_keys";
+
+/// Whether `field` is a map marked by [`SORTED_KEYS_METADATA_KEY`].
+pub fn has_sorted_keys(field: &Field) -> bool {
+    matches!(field.data_type(), DataType::Map(_, _))
+        && field
+            .metadata()
+            .get(SORTED_KEYS_METADATA_KEY)
+            .is_some_and(|v| v == "true")
+}
+
 pub static LINE_FIELD_REF: LazyLock<FieldRef> =
     LazyLock::new(|| Arc::new(Field::new("line", DataType::Utf8, false)));
 
+/// Optional column holding the structured metadata attached to each log.
+pub static STRUCTURED_METADATA_FIELD_REF: LazyLock<FieldRef> = LazyLock::new(|| {
+    Arc::new(
+        LABELS_FIELD_REF
+            .as_ref()
+            .clone()
+            .with_name("structured_metadata"),
+    )
+});
+
+/// Synthetic column flagging rows of scans that returned as many logs as their limit,
+/// see [`LokiLogTable::with_truncation_flag`].
+pub static TRUNCATED_FIELD_REF: LazyLock<FieldRef> =
+    LazyLock::new(|| Arc::new(Field::new("__truncated", DataType::Boolean, false)));
+
+/// Synthetic column holding the time elapsed since each log's timestamp.
+pub static AGE_FIELD_REF: LazyLock<FieldRef> = LazyLock::new(|| {
+    Arc::new(Field::new(
+        "age",
+        DataType::Duration(TimeUnit::Nanosecond),
+        false,
+    ))
+});
+
+/// Schema of [`LokiLogTable::scan_streams`], one row per stream.
+pub static LOG_STREAMS_SCHEMA: LazyLock<SchemaRef> = LazyLock::new(|| {
+    Arc::new(Schema::new(vec![
+        LABELS_FIELD_REF.as_ref().clone(),
+        Field::new(
+            "lines",
+            DataType::List(Arc::new(Field::new_list_field(DataType::Utf8, false))),
+            false,
+        ),
+        Field::new("count", DataType::Int64, false),
+    ]))
+});
+
 pub static LOG_TABLE_SCHEMA: LazyLock<SchemaRef> = LazyLock::new(|| {
     Arc::new(Schema::new(vec![
         TIMESTAMP_FIELD_REF.clone(),
@@ -36,10 +121,56 @@ pub static LOG_TABLE_SCHEMA: LazyLock<SchemaRef> = LazyLock::new(|| {
     ]))
 });
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LokiLogTable {
     pub endpoint: String,
     pub default_label: Option<String>,
+    /// Matchers ANDed into every scan's stream selector, e.g. `cluster="prod"`.
+    pub mandatory_selector: Option<String>,
+    pub schema: SchemaRef,
+    pub query_tags: HashMap<String, String>,
+    pub int64_timestamp_as_nanos: bool,
+    pub age_column: bool,
+    /// Scan bounds (ns) used unless overridden by timestamp filters.
+    pub default_start: Option<i64>,
+    pub default_end: Option<i64>,
+    /// How far back from their end scans without a start bound look.
+    pub default_lookback: Duration,
+    pub structured_metadata_column: bool,
+    /// Name and value of a constant column identifying the table's rows.
+    pub source_column: Option<(String, String)>,
+    /// Labels for inserts omitting the labels column.
+    pub insert_default_labels: HashMap<String, String>,
+    pub duplicate_label_policy: DuplicateLabelPolicy,
+    pub clock_skew_tolerance: Option<Duration>,
+    pub push_content_type: String,
+    pub max_push_bytes: Option<usize>,
+    pub max_push_lines: Option<usize>,
+    pub push_compression: bool,
+    pub max_push_concurrency: usize,
+    pub detailed_insert_result: bool,
+    pub pushdown_verification: bool,
+    pub dictionary_labels: bool,
+    pub timestamp_unit: TimeUnit,
+    pub sorted_labels: bool,
+    pub partition_error_policy: PartitionErrorPolicy,
+    pub http_options: LokiHttpOptions,
+    pub target_partitions: Option<usize>,
+    pub scan_batch_size: Option<usize>,
+    pub response_compression: bool,
+    pub max_entries_limit: Option<usize>,
+    pub fail_on_empty: bool,
+    pub max_labels_per_stream: Option<usize>,
+    pub truncation_flag: bool,
+    /// Enables `insert overwrite`, waiting up to this long for loki to process its delete.
+    pub overwrite_delete_timeout: Option<Duration>,
+    pub timestamp_statistics: bool,
+    /// Caps of the streams of [`Self::tail`].
+    pub tail_max_duration: Option<Duration>,
+    pub tail_max_lines: Option<usize>,
+    insert_default_labels_expr: Option<Expr>,
+    /// Shared by the table's plans and requests, reusing pooled connections across queries.
+    client: Client,
 }
 
 impl LokiLogTable {
@@ -49,6 +180,44 @@ impl LokiLogTable {
         Ok(LokiLogTable {
             endpoint,
             default_label: None,
+            mandatory_selector: None,
+            schema: LOG_TABLE_SCHEMA.clone(),
+            query_tags: HashMap::new(),
+            int64_timestamp_as_nanos: false,
+            age_column: false,
+            default_start: None,
+            default_end: None,
+            default_lookback: DEFAULT_LOOKBACK,
+            structured_metadata_column: false,
+            source_column: None,
+            insert_default_labels: HashMap::new(),
+            duplicate_label_policy: DuplicateLabelPolicy::default(),
+            clock_skew_tolerance: None,
+            push_content_type: DEFAULT_PUSH_CONTENT_TYPE.to_string(),
+            max_push_bytes: None,
+            max_push_lines: None,
+            push_compression: false,
+            max_push_concurrency: DEFAULT_MAX_PUSH_CONCURRENCY,
+            detailed_insert_result: false,
+            pushdown_verification: false,
+            dictionary_labels: false,
+            timestamp_unit: TimeUnit::Nanosecond,
+            sorted_labels: false,
+            partition_error_policy: PartitionErrorPolicy::default(),
+            http_options: LokiHttpOptions::default(),
+            target_partitions: None,
+            scan_batch_size: None,
+            response_compression: true,
+            max_entries_limit: Some(DEFAULT_MAX_ENTRIES_LIMIT),
+            fail_on_empty: false,
+            max_labels_per_stream: None,
+            truncation_flag: false,
+            overwrite_delete_timeout: None,
+            timestamp_statistics: false,
+            tail_max_duration: None,
+            tail_max_lines: None,
+            insert_default_labels_expr: None,
+            client: build_client()?,
         })
     }
 
@@ -57,11 +226,452 @@ impl LokiLogTable {
         self
     }
 
+    /// Always ANDs the matchers of `selector`, e.g. `cluster="prod"` or
+    /// `{cluster="prod", env!="dev"}`, into the stream selector of scans and tails.
+    ///
+    /// Unlike the default label, which only stands in for missing label filters, the
+    /// selector is prepended to user filters too, so queries can't reach other streams:
+    /// user filters on the same labels can only narrow the result further.
+    pub fn with_mandatory_selector(mut self, selector: impl Into<String>) -> Self {
+        let selector = selector.into();
+        let selector = selector.trim();
+        let selector = selector
+            .strip_prefix('{')
+            .and_then(|s| s.strip_suffix('}'))
+            .unwrap_or(selector)
+            .trim();
+        self.mandatory_selector = (!selector.is_empty()).then(|| selector.to_string());
+        self
+    }
+
+    /// Exposes the table with a customized schema, e.g. renamed or retyped columns.
+    ///
+    /// Fields are matched to loki columns by name, falling back to the column at the
+    /// same position, and scanned batches are cast into this schema.
+    pub fn with_schema(mut self, schema: SchemaRef) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    /// Tags scan requests via loki's `X-Query-Tags` header, e.g. to attribute query load
+    /// to the issuing dashboard or user.
+    ///
+    /// The header separates tags by `,` and keys from values by `=`, so those characters
+    /// are sent as `_`.
+    pub fn with_query_tags(mut self, query_tags: HashMap<String, String>) -> Self {
+        self.query_tags = query_tags;
+        self
+    }
+
+    /// Accepts inserts whose timestamp column is `Int64`, interpreted as epoch nanoseconds.
+    ///
+    /// Disabled by default since the unit of an integer timestamp is ambiguous.
+    pub fn with_int64_timestamp_as_nanos(mut self, int64_timestamp_as_nanos: bool) -> Self {
+        self.int64_timestamp_as_nanos = int64_timestamp_as_nanos;
+        self
+    }
+
+    /// Adds the synthetic [`AGE_FIELD_REF`] column, so that e.g. `age < interval '1 hour'`
+    /// queries the last hour of logs.
+    pub fn with_age_column(mut self, age_column: bool) -> Self {
+        self.age_column = age_column;
+        self
+    }
+
+    /// Adds a constant `Utf8` column `name` holding `value` on every row, e.g.
+    /// `source = 'loki'` to tell rows apart when unioning with other log sources.
+    pub fn with_source_column(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.source_column = Some((name.into(), value.into()));
+        self
+    }
+
+    /// Bounds every scan to `[start, end)` (ns), for tools that can't express timestamp
+    /// predicates in SQL.
+    ///
+    /// Timestamp or age filters of a query take precedence over these bounds, which in
+    /// turn take precedence over the default lookback of scans.
+    pub fn with_time_bounds(mut self, start: Option<i64>, end: Option<i64>) -> Self {
+        self.default_start = start;
+        self.default_end = end;
+        self
+    }
+
+    /// Sets how far back from their end scans without a start bound look, defaulting to
+    /// [`DEFAULT_LOOKBACK`] rather than loki's often much shorter default.
+    ///
+    /// Scans without an end bound end at the query start.
+    pub fn with_default_lookback(mut self, default_lookback: Duration) -> Self {
+        self.default_lookback = default_lookback;
+        self
+    }
+
+    /// Adds the [`STRUCTURED_METADATA_FIELD_REF`] column, empty for responses without it.
+    ///
+    /// Predicates on its entries are pushed down as label filter stages, e.g.
+    /// `structured_metadata['trace_id'] = 'abc'` as `| trace_id="abc"`. Entries of
+    /// inserted rows are pushed as the structured metadata of their logs.
+    pub fn with_structured_metadata_column(mut self, structured_metadata_column: bool) -> Self {
+        self.structured_metadata_column = structured_metadata_column;
+        self
+    }
+
+    /// Labels used by inserts with a column list omitting the labels column, e.g.
+    /// `insert into loki (timestamp, line) values (...)`.
+    pub fn with_insert_default_labels(
+        mut self,
+        insert_default_labels: HashMap<String, String>,
+    ) -> DFResult<Self> {
+        self.insert_default_labels_expr = if insert_default_labels.is_empty() {
+            None
+        } else {
+            Some(labels_literal(&insert_default_labels)?)
+        };
+        self.insert_default_labels = insert_default_labels;
+        Ok(self)
+    }
+
+    /// Controls how inserts handle labels maps with duplicate keys, see
+    /// [`DuplicateLabelPolicy`].
+    pub fn with_duplicate_label_policy(
+        mut self,
+        duplicate_label_policy: DuplicateLabelPolicy,
+    ) -> Self {
+        self.duplicate_label_policy = duplicate_label_policy;
+        self
+    }
+
+    /// Clamps inserted timestamps more than `tolerance` in the future to now plus
+    /// `tolerance`, so loki doesn't reject logs of producers whose clocks run ahead.
+    ///
+    /// This modifies the inserted data: clamped logs are stored with a timestamp other
+    /// than the one inserted, and logs clamped to the same timestamp lose their order.
+    pub fn with_clock_skew_tolerance(mut self, tolerance: Duration) -> Self {
+        self.clock_skew_tolerance = Some(tolerance);
+        self
+    }
+
+    /// Sends push requests with this `Content-Type` header instead of
+    /// `application/json`, e.g. `application/json; charset=utf-8` for proxies requiring
+    /// a charset. The body is JSON regardless.
+    pub fn with_push_content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.push_content_type = content_type.into();
+        self
+    }
+
+    /// Splits inserted batches into push requests of at most this many bytes, e.g. to
+    /// stay below loki's `max_body_size`. Streams are split across requests as needed.
+    pub fn with_max_push_bytes(mut self, max_push_bytes: usize) -> Self {
+        self.max_push_bytes = Some(max_push_bytes);
+        self
+    }
+
+    /// Splits inserted batches into push requests of at most this many logs.
+    pub fn with_max_push_lines(mut self, max_push_lines: usize) -> Self {
+        self.max_push_lines = Some(max_push_lines);
+        self
+    }
+
+    /// Gzip-compresses push request bodies, sent with `Content-Encoding: gzip`, cutting
+    /// egress for high-volume ingestion. The compressed JSON is the same, so
+    /// [`Self::with_max_push_bytes`] bounds its size before compression.
+    pub fn with_push_compression(mut self, push_compression: bool) -> Self {
+        self.push_compression = push_compression;
+        self
+    }
+
+    /// Pushes up to this many partitions of an insert's input concurrently, defaults
+    /// to [`DEFAULT_MAX_PUSH_CONCURRENCY`].
+    ///
+    /// Each push request sorts its logs by timestamp, but concurrent pushes interleave,
+    /// so logs of a stream spread over several partitions may reach loki out of order.
+    /// Loki rejects those unless `unordered_writes` is enabled; use 1 to push the
+    /// partitions one after another, in partition order, when inserting into such a loki.
+    pub fn with_max_push_concurrency(mut self, max_push_concurrency: usize) -> Self {
+        self.max_push_concurrency = max_push_concurrency;
+        self
+    }
+
+    /// Has inserts return [`DETAILED_INSERT_RESULT_SCHEMA`](crate::DETAILED_INSERT_RESULT_SCHEMA) rather than a single
+    /// `count`, so pipelines can detect partial ingestion. Push requests failing are
+    /// then counted as failed logs rather than failing the insert.
+    pub fn with_detailed_insert_result(mut self, detailed_insert_result: bool) -> Self {
+        self.detailed_insert_result = detailed_insert_result;
+        self
+    }
+
+    /// Still pushes filters down to loki, but has DataFusion re-apply them on the
+    /// returned rows as a safety net against LogQL translation bugs.
+    pub fn with_pushdown_verification(mut self, pushdown_verification: bool) -> Self {
+        self.pushdown_verification = pushdown_verification;
+        self
+    }
+
+    /// Decodes the labels map keys and values as `Dictionary(Int32, Utf8)`, saving memory
+    /// for low-cardinality labels repeated across many rows.
+    pub fn with_dictionary_labels(mut self, dictionary_labels: bool) -> Self {
+        self.dictionary_labels = dictionary_labels;
+        self
+    }
+
+    /// Decodes the timestamp column in `unit` instead of nanoseconds, for engines and
+    /// formats that can't handle nanosecond timestamps.
+    ///
+    /// Timestamps are truncated to `unit`, so logs within the same micro or millisecond
+    /// become indistinguishable by timestamp. Timestamp filters are still pushed down
+    /// precisely, and inserted timestamps are cast back to nanoseconds.
+    pub fn with_timestamp_unit(mut self, unit: TimeUnit) -> Self {
+        self.timestamp_unit = unit;
+        self
+    }
+
+    /// Sorts the entries of each scanned labels map by key and marks the labels field
+    /// with [`SORTED_KEYS_METADATA_KEY`], letting [`crate::MapGet`] binary search
+    /// instead of scanning every label.
+    pub fn with_sorted_labels(mut self, sorted_labels: bool) -> Self {
+        self.sorted_labels = sorted_labels;
+        self
+    }
+
+    /// Controls whether a failing scan partition fails the query, see
+    /// [`PartitionErrorPolicy`].
+    pub fn with_partition_error_policy(
+        mut self,
+        partition_error_policy: PartitionErrorPolicy,
+    ) -> Self {
+        self.partition_error_policy = partition_error_policy;
+        self
+    }
+
+    /// Splits scans bounded by both a start and end timestamp into up to
+    /// `target_partitions` time windows, queried from loki in parallel.
+    ///
+    /// Defaults to the session's `datafusion.execution.target_partitions`.
+    pub fn with_target_partitions(mut self, target_partitions: usize) -> Self {
+        self.target_partitions = Some(target_partitions);
+        self
+    }
+
+    /// Sets the rows per batch decoded from loki's responses, e.g. smaller for wide rows
+    /// in memory-constrained environments.
+    ///
+    /// Defaults to the session's `datafusion.execution.batch_size`.
+    pub fn with_scan_batch_size(mut self, scan_batch_size: usize) -> Self {
+        self.scan_batch_size = Some(scan_batch_size);
+        self
+    }
+
+    /// Asks for gzip-compressed scan responses via `Accept-Encoding: gzip`, transferring
+    /// large results faster over slow links. Enabled by default, it can be disabled for
+    /// proxies mishandling compressed responses.
+    pub fn with_response_compression(mut self, response_compression: bool) -> Self {
+        self.response_compression = response_compression;
+        self
+    }
+
+    /// Sets loki's `max_entries_limit_per_query`, defaulting to [`DEFAULT_MAX_ENTRIES_LIMIT`].
+    ///
+    /// Scans continue queries returning this many logs with follow-up queries, `None`
+    /// trusting each query to return its full result.
+    pub fn with_max_entries_limit(mut self, max_entries_limit: Option<usize>) -> Self {
+        self.max_entries_limit = max_entries_limit;
+        self
+    }
+
+    /// Fails scans returning no logs, for validation jobs where an empty result means
+    /// something is wrong.
+    pub fn with_fail_on_empty(mut self, fail_on_empty: bool) -> Self {
+        self.fail_on_empty = fail_on_empty;
+        self
+    }
+
+    /// Keeps only the first `max_labels` labels by key of each scanned labels map,
+    /// protecting memory against very wide label sets of a misconfigured pipeline.
+    /// The number of dropped labels is logged at debug level.
+    pub fn with_max_labels_per_stream(mut self, max_labels: usize) -> Self {
+        self.max_labels_per_stream = Some(max_labels);
+        self
+    }
+
+    /// Adds the synthetic [`TRUNCATED_FIELD_REF`] column, set on the batch with which a
+    /// scan partition returns as many logs as the pushed down `LIMIT`.
+    ///
+    /// This is a heuristic: loki may have had more logs to return, or exactly that many.
+    /// Scans without a limit are never flagged, as they're continued past loki's
+    /// `max_entries_limit_per_query`.
+    pub fn with_truncation_flag(mut self, truncation_flag: bool) -> Self {
+        self.truncation_flag = truncation_flag;
+        self
+    }
+
+    /// Supports `insert overwrite` by first deleting the logs of the table's mandatory
+    /// selector, or else default label, within its time bounds or default lookback via
+    /// loki's delete API, waiting up to `timeout` for loki to process the delete.
+    ///
+    /// Disabled by default as deletes are destructive and asynchronous: loki's compactor
+    /// only processes them after its `delete_request_cancel_period`, which must be shorter
+    /// than `timeout`. Overwrites failing after the delete don't restore the deleted logs.
+    pub fn with_overwrite_via_delete(mut self, timeout: Option<Duration>) -> Self {
+        self.overwrite_delete_timeout = timeout;
+        self
+    }
+
+    /// Probes loki for the oldest and newest log of each scan while planning, reporting
+    /// their timestamps as the scan's inexact timestamp min/max statistics instead of
+    /// its time bounds.
+    ///
+    /// Disabled by default as it costs two `limit=1` queries per scan. Failed probes are
+    /// logged and fall back to the time bounds.
+    pub fn with_timestamp_statistics(mut self, timestamp_statistics: bool) -> Self {
+        self.timestamp_statistics = timestamp_statistics;
+        self
+    }
+
+    /// Ends the streams of [`Self::tail`] gracefully after `max_duration`, e.g. for
+    /// bounded live-debug sessions.
+    pub fn with_tail_max_duration(mut self, max_duration: Duration) -> Self {
+        self.tail_max_duration = Some(max_duration);
+        self
+    }
+
+    /// Ends the streams of [`Self::tail`] gracefully once they returned `max_lines` logs.
+    pub fn with_tail_max_lines(mut self, max_lines: usize) -> Self {
+        self.tail_max_lines = Some(max_lines);
+        self
+    }
+
+    /// Authenticates requests with an `Authorization: Bearer <token>` header.
+    pub fn with_bearer_token(mut self, bearer_token: Option<String>) -> Self {
+        self.http_options.bearer_token = bearer_token;
+        self
+    }
+
+    /// Authenticates requests with http basic auth.
+    pub fn with_basic_auth(mut self, username: String, password: Option<String>) -> Self {
+        self.http_options.basic_auth = Some(LokiBasicAuth { username, password });
+        self
+    }
+
+    /// Sets the `X-Scope-OrgID` header of scans and inserts, required by multi-tenant loki.
+    pub fn with_tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.http_options.tenant = Some(tenant.into());
+        self
+    }
+
+    /// Adds custom headers to every request, e.g. for routing through a proxy.
+    ///
+    /// Headers override auth and tenant headers of the same name.
+    pub fn with_headers(mut self, headers: HashMap<String, String>) -> Self {
+        self.http_options.headers = headers;
+        self
+    }
+
+    /// Fails scan and insert requests taking longer than `timeout`, by default requests
+    /// never time out.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.http_options.timeout = Some(timeout);
+        self
+    }
+
+    /// Runs `middleware` on every scan, insert and connection check request just before
+    /// sending, e.g. to sign requests for a gateway.
+    ///
+    /// The middleware runs after auth, tenant and custom headers are set, so it can
+    /// override them. It is transport-only config and not serialized by
+    /// [`crate::LokiPhysicalCodec`], so it must be set again on decoded plans.
+    pub fn with_request_middleware(
+        mut self,
+        middleware: Arc<dyn Fn(&mut reqwest::Request) + Send + Sync>,
+    ) -> Self {
+        self.http_options.middleware = Some(LokiRequestMiddleware(middleware));
+        self
+    }
+
+    /// The delete preceding an `insert overwrite`, of the logs a scan without filters
+    /// would return.
+    fn overwrite_delete_request(&self) -> DFResult<LokiDeleteRequest> {
+        let Some(timeout) = self.overwrite_delete_timeout else {
+            return exec_err!(
+                "Insert overwrite deletes logs and must be enabled with LokiLogTable::with_overwrite_via_delete"
+            );
+        };
+        let query = if let Some(selector) = &self.mandatory_selector {
+            format!("{{{selector}}}")
+        } else if let Some(default_label) = &self.default_label {
+            format!("{{{default_label}=~\".+\"}}")
+        } else {
+            return exec_err!("Insert overwrite requires a mandatory selector or default label");
+        };
+        let lookback = self.default_lookback.as_nanos().min(i64::MAX as u128) as i64;
+        let end = self.default_end.unwrap_or_else(current_timestamp_ns);
+        let start = self
+            .default_start
+            .unwrap_or_else(|| end.saturating_sub(lookback));
+        Ok(LokiDeleteRequest {
+            query,
+            start,
+            end,
+            timeout,
+        })
+    }
+
+    /// Schema of the loki columns, i.e. without synthetic columns.
+    fn base_schema(&self) -> SchemaRef {
+        if !self.dictionary_labels
+            && self.timestamp_unit == TimeUnit::Nanosecond
+            && !self.sorted_labels
+        {
+            return self.schema.clone();
+        }
+        let fields = self
+            .schema
+            .fields()
+            .iter()
+            .map(|field| match field.data_type() {
+                data_type if data_type == LABELS_FIELD_REF.data_type() => {
+                    let mut field = field.as_ref().clone();
+                    if self.dictionary_labels {
+                        field =
+                            field.with_data_type(DICTIONARY_LABELS_FIELD_REF.data_type().clone());
+                    }
+                    if self.sorted_labels {
+                        let mut metadata = field.metadata().clone();
+                        metadata.insert(SORTED_KEYS_METADATA_KEY.to_string(), "true".to_string());
+                        field = field.with_metadata(metadata);
+                    }
+                    Arc::new(field)
+                }
+                DataType::Timestamp(TimeUnit::Nanosecond, tz) => Arc::new(
+                    field
+                        .as_ref()
+                        .clone()
+                        .with_data_type(DataType::Timestamp(self.timestamp_unit, tz.clone())),
+                ),
+                _ => field.clone(),
+            })
+            .collect::<Vec<_>>();
+        Arc::new(Schema::new_with_metadata(
+            fields,
+            self.schema.metadata().clone(),
+        ))
+    }
+
+    /// Clones the table configuration against another endpoint, e.g. to register the same
+    /// table for dev, staging and prod deployments.
+    pub fn clone_with_endpoint(&self, endpoint: impl Into<String>) -> DFResult<Self> {
+        Ok(LokiLogTable {
+            endpoint: endpoint.into(),
+            ..self.clone()
+        })
+    }
+
     pub async fn check_connection(&self) -> DFResult<()> {
-        let client = reqwest::Client::new();
-        let resp = client
-            .get(format!("{}/loki/api/v1/status/buildinfo", self.endpoint))
-            .send()
+        let req_builder = self.http_options.apply(
+            self.client
+                .get(format!("{}/loki/api/v1/status/buildinfo", self.endpoint)),
+        );
+        let resp = self
+            .http_options
+            .send(req_builder)
             .await
             .map_err(|e| DataFusionError::External(Box::new(e)))?;
 
@@ -71,6 +681,323 @@ impl LokiLogTable {
             exec_err!("Failed to connect to loki with status {}", resp.status())
         }
     }
+
+    /// Fetches the fields loki detects in logs of the streams matching `matchers`, e.g.
+    /// `{app="my-app"}`, to hint at a schema for their parsed fields.
+    ///
+    /// Bounds are epoch nanoseconds, defaulting to the last 30 days.
+    pub async fn detected_fields(
+        &self,
+        matchers: &str,
+        start: Option<i64>,
+        end: Option<i64>,
+    ) -> DFResult<Vec<DetectedField>> {
+        let start = start.unwrap_or_else(thirty_days_before_now_timestamp_ns);
+        let end = end.unwrap_or_else(current_timestamp_ns);
+        let req_builder = self
+            .http_options
+            .apply(
+                self.client
+                    .get(format!("{}/loki/api/v1/detected_fields", self.endpoint)),
+            )
+            .query(&[
+                ("query", matchers.to_string()),
+                ("start", start.to_string()),
+                ("end", end.to_string()),
+            ]);
+        let resp = self
+            .http_options
+            .send(req_builder)
+            .await
+            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+
+        let status = resp.status();
+        if !status.is_success() {
+            let text = read_error_text(resp).await.unwrap_or_default();
+            return Err(status_error(
+                status,
+                format!("Failed to fetch detected fields with status {status}: {text}"),
+            ));
+        }
+        let resp: DetectedFieldsResponse = resp.json().await.map_err(|e| {
+            DataFusionError::Execution(format!("Failed to decode detected fields: {e}"))
+        })?;
+        Ok(resp.fields)
+    }
+
+    /// Fetches the names of the labels of streams with logs within the bounds, e.g. to
+    /// discover which keys of the labels column to query. Scoped to the streams of the
+    /// mandatory selector, if any.
+    ///
+    /// Bounds are epoch nanoseconds, defaulting to the last 30 days.
+    pub async fn label_names(&self, start: Option<i64>, end: Option<i64>) -> DFResult<Vec<String>> {
+        self.fetch_label_data("labels", start, end, "label names")
+            .await
+    }
+
+    /// Fetches the values `label` has in streams with logs within the bounds, e.g. to
+    /// fill a dropdown without writing LogQL. Scoped to the streams of the mandatory
+    /// selector, if any.
+    ///
+    /// Bounds are epoch nanoseconds, defaulting to the last 30 days.
+    pub async fn label_values(
+        &self,
+        label: &str,
+        start: Option<i64>,
+        end: Option<i64>,
+    ) -> DFResult<Vec<String>> {
+        if !is_label_name(label) {
+            return plan_err!("Invalid label name: {label}");
+        }
+        self.fetch_label_data(
+            &format!("label/{label}/values"),
+            start,
+            end,
+            &format!("values of label {label}"),
+        )
+        .await
+    }
+
+    /// Fetches a list of strings from a labels endpoint of loki, e.g. `labels`.
+    async fn fetch_label_data(
+        &self,
+        path: &str,
+        start: Option<i64>,
+        end: Option<i64>,
+        what: &str,
+    ) -> DFResult<Vec<String>> {
+        let start = start.unwrap_or_else(thirty_days_before_now_timestamp_ns);
+        let end = end.unwrap_or_else(current_timestamp_ns);
+        let mut query = vec![("start", start.to_string()), ("end", end.to_string())];
+        if let Some(selector) = &self.mandatory_selector {
+            query.push(("query", format!("{{{selector}}}")));
+        }
+        let req_builder = self
+            .http_options
+            .apply(
+                self.client
+                    .get(format!("{}/loki/api/v1/{path}", self.endpoint)),
+            )
+            .query(&query);
+        let resp = self
+            .http_options
+            .send(req_builder)
+            .await
+            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+
+        let status = resp.status();
+        if !status.is_success() {
+            let text = read_error_text(resp).await.unwrap_or_default();
+            return Err(status_error(
+                status,
+                format!("Failed to fetch {what} with status {status}: {text}"),
+            ));
+        }
+        let resp: LabelDataResponse = resp
+            .json()
+            .await
+            .map_err(|e| DataFusionError::Execution(format!("Failed to decode {what}: {e}")))?;
+        Ok(resp.data)
+    }
+
+    /// Timestamps of the oldest and newest log of `log_query` within `[start, end)`, or
+    /// `None` if there is none.
+    async fn probe_timestamp_range(
+        &self,
+        log_query: &str,
+        start: i64,
+        end: i64,
+    ) -> DFResult<Option<(i64, i64)>> {
+        let (min, max) = futures::try_join!(
+            self.probe_timestamp(log_query, start, end, LokiDirection::Forward),
+            self.probe_timestamp(log_query, start, end, LokiDirection::Backward),
+        )?;
+        Ok(min.zip(max))
+    }
+
+    /// Timestamp of the first log loki returns in `direction`, via a `limit=1` query.
+    async fn probe_timestamp(
+        &self,
+        log_query: &str,
+        start: i64,
+        end: i64,
+        direction: LokiDirection,
+    ) -> DFResult<Option<i64>> {
+        let req_builder = self
+            .http_options
+            .apply(
+                self.client
+                    .get(format!("{}/loki/api/v1/query_range", self.endpoint)),
+            )
+            .query(&[
+                ("query", log_query.to_string()),
+                ("start", start.to_string()),
+                ("end", end.to_string()),
+                ("limit", "1".to_string()),
+                ("direction", direction.as_str().to_string()),
+            ]);
+        let resp = self.http_options.send(req_builder).await.map_err(|e| {
+            self.http_options
+                .map_request_error(e, "Failed to send timestamp probe to loki")
+        })?;
+
+        let status = resp.status();
+        if !status.is_success() {
+            let text = read_error_text(resp).await.unwrap_or_default();
+            return Err(status_error(
+                status,
+                format!("Failed to probe timestamps with status {status}: {text}"),
+            ));
+        }
+        let resp: ProbeResponse = resp.json().await.map_err(|e| {
+            DataFusionError::Execution(format!("Failed to decode timestamp probe: {e}"))
+        })?;
+        let timestamps = resp
+            .data
+            .result
+            .iter()
+            .flat_map(|stream| &stream.values)
+            .filter_map(|value| value.first()?.as_str()?.parse::<i64>().ok());
+        Ok(match direction {
+            LokiDirection::Forward => timestamps.min(),
+            LokiDirection::Backward => timestamps.max(),
+        })
+    }
+
+    /// Follows the logs matching the LogQL `log_query`, e.g. `{app="my-app"} |= "error"`,
+    /// as loki receives them, for a "follow" mode complementing bounded scans.
+    ///
+    /// Tailed logs always have [`LOG_TABLE_SCHEMA`], regardless of the table's schema options.
+    pub fn tail(&self, log_query: impl Into<String>) -> DFResult<LokiLogTailExec> {
+        let mut log_query = log_query.into();
+        if let Some(selector) = &self.mandatory_selector {
+            let Some(rest) = log_query.trim_start().strip_prefix('{') else {
+                return plan_err!("Tail query should start with a stream selector: {log_query}");
+            };
+            log_query = format!("{{{selector}, {}", rest.trim_start());
+        }
+        Ok(LokiLogTailExec::try_new(self.endpoint.clone(), log_query)?
+            .with_client(self.client.clone())
+            .with_http_options(self.http_options.clone())
+            .with_max_duration(self.tail_max_duration)
+            .with_max_lines(self.tail_max_lines))
+    }
+
+    /// Index of the table field scanned from loki column `column`, matched the way
+    /// [`Self::with_schema`] matches fields.
+    fn loki_column_index(&self, column: &str) -> DFResult<usize> {
+        let source = LOG_TABLE_SCHEMA.index_of(column)?;
+        self.schema
+            .fields()
+            .iter()
+            .enumerate()
+            .position(|(i, field)| LOG_TABLE_SCHEMA.index_of(field.name()).unwrap_or(i) == source)
+            .ok_or_else(|| {
+                DataFusionError::Plan(format!(
+                    "Table schema has no field for loki column {column}"
+                ))
+            })
+    }
+
+    /// Scans the logs matching `filters` and groups them by stream, returning a batch with
+    /// [`LOG_STREAMS_SCHEMA`], i.e. each distinct label set with its lines and their count.
+    ///
+    /// Streams are ordered by their first log and lines kept in scan order. The grouping
+    /// happens client-side, so all matching logs are fetched.
+    pub async fn scan_streams(
+        &self,
+        state: &dyn Session,
+        filters: &[Expr],
+    ) -> DFResult<RecordBatch> {
+        let projection = vec![
+            self.loki_column_index(LABELS_FIELD_REF.name())?,
+            self.loki_column_index(LINE_FIELD_REF.name())?,
+        ];
+        let exec = self.scan(state, Some(&projection), filters, None).await?;
+        let batches = collect(exec.clone(), state.task_ctx()).await?;
+        let batch = concat_batches(&exec.schema(), &batches)?;
+        let labels = cast(batch.column(0), LABELS_FIELD_REF.data_type())?;
+        let labels = labels.as_map();
+        let lines = cast(batch.column(1), &DataType::Utf8)?;
+        let lines = lines.as_string::<i32>();
+
+        // Loki identifies streams by their label set, regardless of label order
+        let mut stream_indices = HashMap::new();
+        let mut streams: Vec<(u32, Vec<usize>)> = Vec::new();
+        let keys = labels.keys().as_string::<i32>();
+        let values = labels.values().as_string::<i32>();
+        for row in 0..batch.num_rows() {
+            let offsets = &labels.value_offsets()[row..row + 2];
+            let mut label_set = (offsets[0] as usize..offsets[1] as usize)
+                .map(|i| (keys.value(i), values.value(i)))
+                .collect::<Vec<_>>();
+            label_set.sort();
+            let index = *stream_indices.entry(label_set).or_insert_with(|| {
+                streams.push((row as u32, Vec::new()));
+                streams.len() - 1
+            });
+            streams[index].1.push(row);
+        }
+
+        let first_rows = UInt32Array::from_iter_values(streams.iter().map(|(row, _)| *row));
+        let mut lines_builder = ListBuilder::new(StringBuilder::new())
+            .with_field(Arc::new(Field::new_list_field(DataType::Utf8, false)));
+        for (_, rows) in &streams {
+            for row in rows {
+                lines_builder.values().append_value(lines.value(*row));
+            }
+            lines_builder.append(true);
+        }
+        let counts =
+            Int64Array::from_iter_values(streams.iter().map(|(_, rows)| rows.len() as i64));
+        Ok(RecordBatch::try_new(
+            LOG_STREAMS_SCHEMA.clone(),
+            vec![
+                take(labels, &first_rows, None)?,
+                Arc::new(lines_builder.finish()),
+                Arc::new(counts),
+            ],
+        )?)
+    }
+}
+
+/// A field loki detected in log lines, see [`LokiLogTable::detected_fields`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DetectedField {
+    pub label: String,
+    /// Detected value type, e.g. `string`, `int`, `float`, `duration` or `bytes`.
+    pub r#type: String,
+    pub cardinality: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct DetectedFieldsResponse {
+    #[serde(default)]
+    fields: Vec<DetectedField>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LabelDataResponse {
+    #[serde(default)]
+    data: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProbeResponse {
+    data: ProbeData,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProbeData {
+    #[serde(default)]
+    result: Vec<ProbeStream>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProbeStream {
+    /// `["<timestamp ns>", "<line>", ...]` entries
+    #[serde(default)]
+    values: Vec<Vec<serde_json::Value>>,
 }
 
 #[async_trait::async_trait]
@@ -80,7 +1007,28 @@ impl TableProvider for LokiLogTable {
     }
 
     fn schema(&self) -> SchemaRef {
-        LOG_TABLE_SCHEMA.clone()
+        let schema = self.base_schema();
+        if !self.structured_metadata_column
+            && !self.age_column
+            && self.source_column.is_none()
+            && !self.truncation_flag
+        {
+            return schema;
+        }
+        let mut fields = schema.fields().to_vec();
+        if self.structured_metadata_column {
+            fields.push(STRUCTURED_METADATA_FIELD_REF.clone());
+        }
+        if self.age_column {
+            fields.push(AGE_FIELD_REF.clone());
+        }
+        if let Some((name, _)) = &self.source_column {
+            fields.push(Arc::new(Field::new(name, DataType::Utf8, false)));
+        }
+        if self.truncation_flag {
+            fields.push(TRUNCATED_FIELD_REF.clone());
+        }
+        Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()))
     }
 
     fn table_type(&self) -> TableType {
@@ -89,31 +1037,84 @@ impl TableProvider for LokiLogTable {
 
     async fn scan(
         &self,
-        _state: &dyn Session,
+        state: &dyn Session,
         projection: Option<&Vec<usize>>,
         filters: &[Expr],
         limit: Option<usize>,
     ) -> DFResult<Arc<dyn ExecutionPlan>> {
+        // Measure ages from the query start, consistent with `now()`
+        let now = state
+            .execution_props()
+            .query_execution_start_time
+            .and_then(|t| t.timestamp_nanos_opt())
+            .unwrap_or_else(current_timestamp_ns);
+
         let mut label_filters = Vec::with_capacity(filters.len());
+        // Values each label must not equal, merged into one matcher at the label's first filter
+        let mut negated_labels: Vec<(&str, usize, Vec<&str>)> = Vec::new();
         let mut line_filters = Vec::with_capacity(filters.len());
+        let mut metadata_filters = Vec::with_capacity(filters.len());
         let mut start = None;
-        let mut end = None;
+        let mut end: Option<i64> = None;
         for filter in filters {
-            if let Some(label_filter) = expr_to_label_filter(filter) {
+            if let Some((label, "!=", value)) = parse_label_matcher(filter) {
+                match negated_labels.iter_mut().find(|(l, _, _)| *l == label) {
+                    Some((_, _, values)) => values.push(value),
+                    None => {
+                        negated_labels.push((label, label_filters.len(), vec![value]));
+                        label_filters.push(String::new());
+                    }
+                }
+            } else if let Some(label_filter) = expr_to_label_filter(filter) {
                 label_filters.push(label_filter);
-            } else if let Some(line_filter) = expr_to_line_filter(filter) {
+            } else if let Some(metadata_filter) = expr_to_structured_metadata_filter(filter)
+                .filter(|_| self.structured_metadata_column)
+            {
+                metadata_filters.push(metadata_filter);
+            } else if let Some(line_filter) =
+                expr_to_line_filter(filter).or_else(|| expr_to_inexact_line_filter(filter))
+            {
                 line_filters.push(line_filter);
-            } else if let Some(timestamp_bound) = parse_timestamp_bound(filter) {
-                match timestamp_bound {
-                    TimestampBound::Start(v) => start = v,
-                    TimestampBound::End(v) => end = v,
-                }
+            } else if let Some(timestamp_bound) = parse_timestamp_bound(filter)
+                .or_else(|| parse_age_bound(filter, now).filter(|_| self.age_column))
+            {
+                let (filter_start, filter_end) = match timestamp_bound {
+                    TimestampBound::Start(v) => (v, None),
+                    TimestampBound::End(v) => (None, v),
+                    TimestampBound::Range(s, e) => (s, e),
+                };
+                // All bounds must hold, so each narrows the range of the others
+                start = start.max(filter_start);
+                end = match (end, filter_end) {
+                    (Some(end), Some(filter_end)) => Some(end.min(filter_end)),
+                    (end, filter_end) => end.or(filter_end),
+                };
             } else {
                 return exec_err!("Unsupported filter: {filter}");
             }
         }
 
-        if label_filters.is_empty() {
+        check_label_filter_conflicts(filters)?;
+        for (label, index, values) in negated_labels {
+            label_filters[index] = negated_label_filter(label, &values);
+        }
+        let start = start.or(self.default_start);
+        let end = end.or(self.default_end);
+        if let (Some(start), Some(end)) = (start, end)
+            && start > end
+        {
+            return exec_err!(
+                "Start bound {} is after end bound {}",
+                format_timestamp_ns(start),
+                format_timestamp_ns(end)
+            );
+        }
+        let mut seen_label_filters = HashSet::new();
+        label_filters.retain(|label_filter| seen_label_filters.insert(label_filter.clone()));
+
+        if let Some(selector) = &self.mandatory_selector {
+            label_filters.insert(0, selector.clone());
+        } else if label_filters.is_empty() {
             if let Some(default_label) = &self.default_label {
                 label_filters.push(format!("{default_label}=~\".+\""));
             } else {
@@ -121,19 +1122,53 @@ impl TableProvider for LokiLogTable {
             }
         }
 
+        // Line filters first, as LogQL evaluates them cheaper than label filter stages
+        line_filters.extend(metadata_filters);
         let log_query = format!(
             "{{{}}} {}",
             label_filters.join(", "),
             line_filters.join(" ")
         );
+        let lookback = self.default_lookback.as_nanos().min(i64::MAX as u128) as i64;
+        let end = end.unwrap_or(now);
+        let start = start.unwrap_or_else(|| end.saturating_sub(lookback));
+        let timestamp_range = if self.timestamp_statistics {
+            self.probe_timestamp_range(&log_query, start, end)
+                .await
+                .inspect_err(|e| warn!("[datafusion-loki] failed to probe timestamp range: {e}"))
+                .ok()
+                .flatten()
+        } else {
+            None
+        };
         let exec = LokiLogScanExec::try_new(
             self.endpoint.clone(),
             log_query,
-            start,
-            end,
-            projection.cloned(),
+            Some(start),
+            Some(end),
+            None,
             limit,
-        )?;
+        )?
+        .with_schema(self.base_schema())?
+        .with_client(self.client.clone())
+        .with_query_tags(self.query_tags.clone())
+        .with_partition_error_policy(self.partition_error_policy)
+        .with_http_options(self.http_options.clone())
+        .with_target_partitions(
+            self.target_partitions
+                .unwrap_or(state.config().target_partitions()),
+        )?
+        .with_batch_size(self.scan_batch_size.unwrap_or(state.config().batch_size()))
+        .with_max_entries_limit(self.max_entries_limit)
+        .with_fail_on_empty(self.fail_on_empty)
+        .with_response_compression(self.response_compression)
+        .with_timestamp_range(timestamp_range)
+        .with_max_labels_per_stream(self.max_labels_per_stream)
+        .with_structured_metadata(self.structured_metadata_column)?
+        .with_age_reference(self.age_column.then_some(now))?
+        .with_source_column(self.source_column.clone())?
+        .with_truncation_flag(self.truncation_flag)?
+        .with_projection(projection.cloned())?;
         Ok(Arc::new(exec))
     }
 
@@ -146,8 +1181,18 @@ impl TableProvider for LokiLogTable {
             if expr_to_label_filter(filter).is_some()
                 || expr_to_line_filter(filter).is_some()
                 || parse_timestamp_bound(filter).is_some()
+                || (self.age_column && parse_age_bound(filter, current_timestamp_ns()).is_some())
+            {
+                if self.pushdown_verification {
+                    pushdown.push(TableProviderFilterPushDown::Inexact);
+                } else {
+                    pushdown.push(TableProviderFilterPushDown::Exact);
+                }
+            } else if expr_to_inexact_line_filter(filter).is_some()
+                || (self.structured_metadata_column
+                    && expr_to_structured_metadata_filter(filter).is_some())
             {
-                pushdown.push(TableProviderFilterPushDown::Exact);
+                pushdown.push(TableProviderFilterPushDown::Inexact);
             } else {
                 pushdown.push(TableProviderFilterPushDown::Unsupported);
             }
@@ -155,20 +1200,128 @@ impl TableProvider for LokiLogTable {
         Ok(pushdown)
     }
 
+    fn get_column_default(&self, column: &str) -> Option<&Expr> {
+        if column == LABELS_FIELD_REF.name() {
+            self.insert_default_labels_expr.as_ref()
+        } else {
+            None
+        }
+    }
+
     async fn insert_into(
         &self,
         _state: &dyn Session,
         input: Arc<dyn ExecutionPlan>,
         insert_op: InsertOp,
     ) -> DFResult<Arc<dyn ExecutionPlan>> {
-        match insert_op {
-            InsertOp::Append => {}
-            InsertOp::Overwrite | InsertOp::Replace => {
-                return exec_err!("Only support append insert operation");
+        let overwrite_delete = match insert_op {
+            InsertOp::Append => None,
+            InsertOp::Overwrite => Some(self.overwrite_delete_request()?),
+            InsertOp::Replace => {
+                return exec_err!(
+                    "Loki has no primary key to replace logs by, only append and overwrite inserts are supported"
+                );
             }
-        }
+        };
+
+        let input = if self.int64_timestamp_as_nanos {
+            cast_int64_timestamp_to_nanos(input)?
+        } else {
+            input
+        };
+        let input = if self.dictionary_labels {
+            cast_dictionary_labels(input)?
+        } else {
+            input
+        };
+        let input = if self.timestamp_unit != TimeUnit::Nanosecond {
+            cast_timestamp_to_nanos(input)?
+        } else {
+            input
+        };
 
-        let exec = LokiLogInsertExec::try_new(input, self.endpoint.clone())?;
+        let exec = LokiLogInsertExec::try_new(input, self.endpoint.clone())?
+            .with_client(self.client.clone())
+            .with_http_options(self.http_options.clone())
+            .with_duplicate_label_policy(self.duplicate_label_policy)
+            .with_clock_skew_tolerance(self.clock_skew_tolerance)
+            .with_push_content_type(self.push_content_type.clone())
+            .with_max_push_bytes(self.max_push_bytes)
+            .with_max_push_lines(self.max_push_lines)
+            .with_push_compression(self.push_compression)
+            .with_max_push_concurrency(self.max_push_concurrency)?
+            .with_detailed_result(self.detailed_insert_result)
+            .with_overwrite_delete(overwrite_delete);
         Ok(Arc::new(exec))
     }
 }
+
+/// Rejects label filters no log can satisfy, e.g. `labels['app'] = 'a' and labels['app'] = 'b'`,
+/// which would otherwise be sent to loki as an ambiguous stream selector.
+fn check_label_filter_conflicts(filters: &[Expr]) -> DFResult<()> {
+    let matchers = filters
+        .iter()
+        .filter_map(parse_label_matcher)
+        .collect::<Vec<_>>();
+    let mut equals = HashMap::new();
+    for (label, op, value) in &matchers {
+        if *op == "="
+            && let Some(other) = equals.insert(*label, *value)
+            && other != *value
+        {
+            return exec_err!(
+                "Conflicting label filters {label}=\"{other}\" and {label}=\"{value}\""
+            );
+        }
+    }
+    for (label, op, value) in &matchers {
+        if *op == "!=" && equals.get(label) == Some(value) {
+            return exec_err!(
+                "Conflicting label filters {label}=\"{value}\" and {label}!=\"{value}\""
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Formats epoch nanoseconds for error messages, e.g. `1735689600000000000 (2025-01-01T00:00:00+00:00)`.
+fn format_timestamp_ns(ns: i64) -> String {
+    let datetime = timestamp_ns_to_datetime(ns).map(|dt| dt.and_utc().to_rfc3339());
+    match datetime {
+        Some(datetime) => format!("{ns} ({datetime})"),
+        None => ns.to_string(),
+    }
+}
+
+fn labels_literal(labels: &HashMap<String, String>) -> DFResult<Expr> {
+    let DataType::Map(entries_field, _) = LABELS_FIELD_REF.data_type() else {
+        return internal_err!("labels field should be a map");
+    };
+    let DataType::Struct(entry_fields) = entries_field.data_type() else {
+        return internal_err!("labels map entries should be a struct");
+    };
+    let field_names = MapFieldNames {
+        entry: entries_field.name().clone(),
+        key: entry_fields[0].name().clone(),
+        value: entry_fields[1].name().clone(),
+    };
+    let mut builder = MapBuilder::new(
+        Some(field_names),
+        StringBuilder::new(),
+        StringBuilder::new(),
+    )
+    .with_values_field(entry_fields[1].clone());
+
+    let mut labels = labels.iter().collect::<Vec<_>>();
+    labels.sort();
+    for (key, value) in labels {
+        builder.keys().append_value(key);
+        builder.values().append_value(value);
+    }
+    builder.append(true)?;
+
+    Ok(Expr::Literal(
+        ScalarValue::Map(Arc::new(builder.finish())),
+        None,
+    ))
+}
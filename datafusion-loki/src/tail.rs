@@ -0,0 +1,481 @@
+use std::{
+    any::Any,
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    sync::Arc,
+    time::Duration,
+};
+
+use arrow::array::{
+    MapBuilder, MapFieldNames, RecordBatch, StringArray, StringBuilder, TimestampNanosecondArray,
+};
+use arrow::datatypes::{DataType, Schema};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use datafusion_common::{DataFusionError, exec_err, internal_err};
+use datafusion_execution::{SendableRecordBatchStream, TaskContext};
+use datafusion_physical_expr::EquivalenceProperties;
+use datafusion_physical_plan::{
+    DisplayAs, DisplayFormatType, ExecutionPlan, Partitioning, PlanProperties,
+    execution_plan::{Boundedness, EmissionType},
+    stream::RecordBatchStreamAdapter,
+};
+use futures::{StreamExt, TryStreamExt};
+use reqwest::{
+    Client, StatusCode,
+    header::{CONNECTION, UPGRADE},
+};
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::{debug, warn};
+
+use crate::{
+    DFResult, LABELS_FIELD_REF, LINE_FIELD_REF, LOG_TABLE_SCHEMA, LokiHttpOptions,
+    STRUCTURED_METADATA_FIELD_REF, SharedClient, TIMESTAMP_FIELD_REF, limit_stream,
+    read_error_text, status_error,
+};
+
+/// Follows the logs matching a LogQL query as loki receives them, via the
+/// `/loki/api/v1/tail` websocket, outputting [`LOG_TABLE_SCHEMA`] batches.
+///
+/// Without a duration or line cap the plan is unbounded, its stream only ending when
+/// loki closes the connection.
+#[derive(Debug, Clone)]
+pub struct LokiLogTailExec {
+    pub endpoint: String,
+    pub log_query: String,
+    /// Timestamp (ns) of the first logs sent, loki defaulting to an hour ago.
+    pub start: Option<i64>,
+    /// Ends the stream this long after it's first polled.
+    pub max_duration: Option<Duration>,
+    /// Ends the stream once it returned this many logs.
+    pub max_lines: Option<usize>,
+    pub http_options: LokiHttpOptions,
+    pub(crate) client: SharedClient,
+    plan_properties: Arc<PlanProperties>,
+}
+
+impl LokiLogTailExec {
+    pub fn try_new(endpoint: String, log_query: String) -> DFResult<Self> {
+        Ok(LokiLogTailExec {
+            endpoint,
+            log_query,
+            start: None,
+            max_duration: None,
+            max_lines: None,
+            http_options: LokiHttpOptions::default(),
+            client: SharedClient::default(),
+            plan_properties: Arc::new(make_plan_properties(false)),
+        })
+    }
+
+    pub fn with_start(mut self, start: Option<i64>) -> Self {
+        self.start = start;
+        self
+    }
+
+    /// Closes the tail after `max_duration`, completing the stream gracefully, e.g. for
+    /// bounded live-debug sessions.
+    pub fn with_max_duration(mut self, max_duration: Option<Duration>) -> Self {
+        self.max_duration = max_duration;
+        self.update_plan_properties();
+        self
+    }
+
+    /// Closes the tail once it returned `max_lines` logs, completing the stream gracefully.
+    pub fn with_max_lines(mut self, max_lines: Option<usize>) -> Self {
+        self.max_lines = max_lines;
+        self.update_plan_properties();
+        self
+    }
+
+    fn update_plan_properties(&mut self) {
+        let bounded = self.max_duration.is_some() || self.max_lines.is_some();
+        self.plan_properties = Arc::new(make_plan_properties(bounded));
+    }
+
+    /// Sends requests with `client`, e.g. shared with other plans to reuse its connections.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = SharedClient::new(client);
+        self
+    }
+
+    pub fn with_http_options(mut self, http_options: LokiHttpOptions) -> Self {
+        self.http_options = http_options;
+        self
+    }
+}
+
+fn make_plan_properties(bounded: bool) -> PlanProperties {
+    PlanProperties::new(
+        EquivalenceProperties::new(LOG_TABLE_SCHEMA.clone()),
+        Partitioning::UnknownPartitioning(1),
+        EmissionType::Incremental,
+        if bounded {
+            Boundedness::Bounded
+        } else {
+            Boundedness::Unbounded {
+                requires_infinite_memory: false,
+            }
+        },
+    )
+}
+
+impl ExecutionPlan for LokiLogTailExec {
+    fn name(&self) -> &str {
+        "LokiLogTailExec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &Arc<PlanProperties> {
+        &self.plan_properties
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        _children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DFResult<Arc<dyn ExecutionPlan>> {
+        Ok(self)
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        _context: Arc<TaskContext>,
+    ) -> DFResult<SendableRecordBatchStream> {
+        if partition != 0 {
+            return exec_err!(
+                "LokiLogTailExec has 1 partition, cannot execute partition {partition}"
+            );
+        }
+        debug!(
+            "[datafusion-loki] starting to tail logs: query: {}, start: {:?}",
+            self.log_query, self.start
+        );
+
+        let mut query = vec![("query", self.log_query.clone())];
+        if let Some(start) = self.start {
+            query.push(("start", start.to_string()));
+        }
+        let req_builder = self
+            .http_options
+            .apply(
+                self.client
+                    .get()?
+                    .get(format!("{}/loki/api/v1/tail", self.endpoint)),
+            )
+            .query(&query)
+            .header(CONNECTION, "Upgrade")
+            .header(UPGRADE, "websocket")
+            .header("Sec-WebSocket-Version", "13")
+            .header("Sec-WebSocket-Key", websocket_key());
+
+        let http_options = self.http_options.clone();
+        let stream = futures::stream::once(async move {
+            let resp = http_options
+                .send(req_builder)
+                .await
+                .map_err(|e| http_options.map_request_error(e, "Failed to send request to loki"))?;
+            let status = resp.status();
+            if status != StatusCode::SWITCHING_PROTOCOLS {
+                let text = read_error_text(resp).await.unwrap_or_default();
+                return Err(status_error(
+                    status,
+                    format!("Failed to tail loki logs with status {status}: {text}"),
+                ));
+            }
+            let upgraded = resp.upgrade().await.map_err(|e| {
+                DataFusionError::Execution(format!("Failed to upgrade loki tail connection: {e}"))
+            })?;
+            Ok(tail_stream(WebSocket { io: upgraded }))
+        })
+        .try_flatten()
+        .boxed();
+        // Dropping the inner stream once a cap is reached closes the connection
+        let stream = match self.max_lines {
+            Some(max_lines) => limit_stream(stream, max_lines),
+            None => stream,
+        };
+        let stream = match self.max_duration {
+            Some(max_duration) => stream
+                .take_until(async move { tokio::time::sleep(max_duration).await })
+                .boxed(),
+            None => stream,
+        };
+        Ok(Box::pin(RecordBatchStreamAdapter::new(
+            LOG_TABLE_SCHEMA.clone(),
+            stream,
+        )))
+    }
+}
+
+impl DisplayAs for LokiLogTailExec {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "LokiLogTailExec: endpoint={}, query={}",
+            self.endpoint, self.log_query
+        )?;
+        write!(f, "{}", self.http_options)?;
+        if let Some(start) = self.start {
+            write!(f, ", start={}", start)?;
+        }
+        if let Some(max_duration) = self.max_duration {
+            write!(f, ", max_duration={max_duration:?}")?;
+        }
+        if let Some(max_lines) = self.max_lines {
+            write!(f, ", max_lines={max_lines}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A random `Sec-WebSocket-Key`.
+fn websocket_key() -> String {
+    let mut key = [0u8; 16];
+    key[..8].copy_from_slice(&RandomState::new().hash_one(0u8).to_le_bytes());
+    key[8..].copy_from_slice(&RandomState::new().hash_one(1u8).to_le_bytes());
+    STANDARD.encode(key)
+}
+
+/// Decodes each tail message into a batch, ending when loki closes the connection.
+fn tail_stream<S>(socket: WebSocket<S>) -> impl futures::Stream<Item = DFResult<RecordBatch>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    futures::stream::unfold(Some(socket), |socket| async move {
+        let mut socket = socket?;
+        loop {
+            let message = match socket.read_message().await {
+                Ok(Some(message)) => message,
+                Ok(None) => return None,
+                Err(e) => return Some((Err(e), None)),
+            };
+            match tail_batch(&message) {
+                Ok(batch) if batch.num_rows() == 0 => continue,
+                Ok(batch) => return Some((Ok(batch), Some(socket))),
+                Err(e) => return Some((Err(e), None)),
+            }
+        }
+    })
+    .boxed()
+}
+
+#[derive(Debug, Deserialize)]
+struct TailMessage {
+    #[serde(default)]
+    streams: Vec<LokiStream>,
+    #[serde(default)]
+    dropped_entries: Option<Vec<serde_json::Value>>,
+}
+
+/// A stream of logs as encoded in loki's JSON, e.g. of tail messages and `query_range`
+/// responses.
+#[derive(Debug, Deserialize)]
+pub(crate) struct LokiStream {
+    #[serde(default)]
+    stream: HashMap<String, String>,
+    /// `["<timestamp ns>", "<line>"]` entries, optionally followed by structured metadata
+    #[serde(default)]
+    values: Vec<Vec<serde_json::Value>>,
+}
+
+/// Converts a tail message into a [`LOG_TABLE_SCHEMA`] batch.
+fn tail_batch(message: &str) -> DFResult<RecordBatch> {
+    let message: TailMessage = serde_json::from_str(message).map_err(|e| {
+        DataFusionError::Execution(format!("Failed to decode loki tail message: {e}"))
+    })?;
+    if let Some(dropped) = &message.dropped_entries
+        && !dropped.is_empty()
+    {
+        warn!(
+            "[datafusion-loki] loki dropped {} tailed logs, the consumer is too slow",
+            dropped.len()
+        );
+    }
+    Ok(streams_batch(&message.streams)?.project(&[0, 1, 2])?)
+}
+
+/// Converts streams into a batch of the [`LOG_TABLE_SCHEMA`] columns followed by the
+/// [`STRUCTURED_METADATA_FIELD_REF`] column.
+pub(crate) fn streams_batch(streams: &[LokiStream]) -> DFResult<RecordBatch> {
+    let DataType::Map(entries_field, _) = LABELS_FIELD_REF.data_type() else {
+        return internal_err!("labels field should be a map");
+    };
+    let DataType::Struct(entry_fields) = entries_field.data_type() else {
+        return internal_err!("labels map entries should be a struct");
+    };
+    let map_builder = || {
+        let field_names = MapFieldNames {
+            entry: entries_field.name().clone(),
+            key: entry_fields[0].name().clone(),
+            value: entry_fields[1].name().clone(),
+        };
+        MapBuilder::new(
+            Some(field_names),
+            StringBuilder::new(),
+            StringBuilder::new(),
+        )
+        .with_values_field(entry_fields[1].clone())
+    };
+    let mut labels = map_builder();
+    let mut structured_metadata = map_builder();
+    let mut timestamps = Vec::new();
+    let mut lines = Vec::new();
+    for stream in streams {
+        let mut stream_labels = stream.stream.iter().collect::<Vec<_>>();
+        stream_labels.sort();
+        for value in &stream.values {
+            let (Some(timestamp), Some(line)) = (
+                value.first().and_then(|v| v.as_str()),
+                value.get(1).and_then(|v| v.as_str()),
+            ) else {
+                return exec_err!("Invalid loki stream entry: {value:?}");
+            };
+            let Ok(timestamp) = timestamp.parse::<i64>() else {
+                return exec_err!("Invalid loki stream timestamp: {timestamp}");
+            };
+            timestamps.push(timestamp);
+            lines.push(line);
+            for (key, value) in &stream_labels {
+                labels.keys().append_value(key);
+                labels.values().append_value(value);
+            }
+            labels.append(true)?;
+            if let Some(metadata) = value.get(2).and_then(|v| v.as_object()) {
+                for (key, value) in metadata {
+                    if let Some(value) = value.as_str() {
+                        structured_metadata.keys().append_value(key);
+                        structured_metadata.values().append_value(value);
+                    }
+                }
+            }
+            structured_metadata.append(true)?;
+        }
+    }
+
+    let schema = Schema::new(vec![
+        TIMESTAMP_FIELD_REF.clone(),
+        LABELS_FIELD_REF.clone(),
+        LINE_FIELD_REF.clone(),
+        STRUCTURED_METADATA_FIELD_REF.clone(),
+    ]);
+    Ok(RecordBatch::try_new(
+        Arc::new(schema),
+        vec![
+            Arc::new(TimestampNanosecondArray::from(timestamps).with_timezone("UTC")),
+            Arc::new(labels.finish()),
+            Arc::new(StringArray::from(lines)),
+            Arc::new(structured_metadata.finish()),
+        ],
+    )?)
+}
+
+/// Websocket messages larger than this are rejected rather than buffered.
+const MAX_MESSAGE_BYTES: u64 = 64 * 1024 * 1024;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+/// The client side of a websocket connection, only as much of RFC 6455 as tailing needs.
+struct WebSocket<S> {
+    io: S,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WebSocket<S> {
+    /// Reads the next text or binary message, answering pings on the way, or `None`
+    /// once the server closed the connection.
+    async fn read_message(&mut self) -> DFResult<Option<String>> {
+        let mut message = Vec::new();
+        loop {
+            let mut header = [0u8; 2];
+            match self.io.read_exact(&mut header).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && message.is_empty() => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.into()),
+            }
+            let fin = header[0] & 0x80 != 0;
+            let opcode = header[0] & 0x0f;
+            let masked = header[1] & 0x80 != 0;
+            let len = match header[1] & 0x7f {
+                126 => self.io.read_u16().await? as u64,
+                127 => self.io.read_u64().await?,
+                len => len as u64,
+            };
+            if message.len() as u64 + len > MAX_MESSAGE_BYTES {
+                return exec_err!(
+                    "Loki tail message exceeds {MAX_MESSAGE_BYTES} bytes, dropping the connection"
+                );
+            }
+            let mut mask = [0u8; 4];
+            if masked {
+                self.io.read_exact(&mut mask).await?;
+            }
+            let mut payload = vec![0u8; len as usize];
+            self.io.read_exact(&mut payload).await?;
+            if masked {
+                payload
+                    .iter_mut()
+                    .enumerate()
+                    .for_each(|(i, b)| *b ^= mask[i % 4]);
+            }
+
+            match opcode {
+                OPCODE_TEXT | OPCODE_BINARY | OPCODE_CONTINUATION => {
+                    message.extend_from_slice(&payload);
+                    if fin {
+                        return String::from_utf8(message).map(Some).map_err(|e| {
+                            DataFusionError::Execution(format!(
+                                "Loki tail message is not utf-8: {e}"
+                            ))
+                        });
+                    }
+                }
+                OPCODE_CLOSE => {
+                    debug!("[datafusion-loki] loki closed the tail connection");
+                    // The connection ends either way
+                    let _ = self.write_frame(OPCODE_CLOSE, &payload).await;
+                    return Ok(None);
+                }
+                OPCODE_PING => self.write_frame(OPCODE_PONG, &payload).await?,
+                OPCODE_PONG => {}
+                _ => return exec_err!("Unexpected websocket opcode {opcode} from loki"),
+            }
+        }
+    }
+
+    /// Writes a single masked frame, as clients must mask every frame they send.
+    async fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> DFResult<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(0x80 | opcode);
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len @ 126..=0xffff => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        let mask = (RandomState::new().hash_one(0u8) as u32).to_be_bytes();
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        self.io.write_all(&frame).await?;
+        self.io.flush().await?;
+        Ok(())
+    }
+}
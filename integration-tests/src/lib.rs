@@ -1,9 +1,11 @@
 mod cmd;
 mod docker;
+mod mock;
 mod utils;
 
 pub use cmd::*;
 pub use docker::*;
+pub use mock::*;
 pub use utils::*;
 
 use datafusion::prelude::SessionContext;
@@ -0,0 +1,342 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use datafusion::{
+    arrow::{
+        array::{
+            MapArray, MapBuilder, MapFieldNames, RecordBatch, StringArray, StringBuilder,
+            TimestampNanosecondArray,
+        },
+        datatypes::{DataType, Field},
+    },
+    parquet::{
+        arrow::ArrowWriter,
+        file::{metadata::KeyValue, properties::WriterProperties},
+    },
+};
+use datafusion_loki::LOG_TABLE_SCHEMA;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+/// A request received by [`MockLoki`].
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    /// Header names are lowercased, values of repeated headers are comma-joined.
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl MockRequest {
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(|v| v.as_str())
+    }
+}
+
+/// A canned response returned by [`MockLoki`].
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Keep the connection open after the body until the client hangs up.
+    pub hold_open: bool,
+}
+
+impl MockResponse {
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: body.into(),
+            hold_open: false,
+        }
+    }
+
+    pub fn ok() -> Self {
+        Self::new(200, vec![])
+    }
+
+    pub fn parquet(batches: &[RecordBatch]) -> Self {
+        Self::parquet_with_metadata(batches, &[])
+    }
+
+    /// A parquet response carrying `metadata` as file key-value metadata.
+    pub fn parquet_with_metadata(batches: &[RecordBatch], metadata: &[(&str, &str)]) -> Self {
+        Self::new(200, parquet_bytes_with_metadata(batches, metadata))
+            .with_header("Content-Type", "application/vnd.apache.parquet")
+    }
+
+    /// A websocket upgrade sending each message as a text frame, then closing.
+    pub fn websocket(messages: &[&str]) -> Self {
+        let mut frames = Vec::new();
+        for message in messages {
+            frames.extend(websocket_frame(0x1, message.as_bytes()));
+        }
+        frames.extend(websocket_frame(0x8, &1000u16.to_be_bytes()));
+        Self::new(101, frames)
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade")
+    }
+
+    /// A websocket upgrade sending each message as a text frame, then idling
+    /// without ever closing.
+    pub fn websocket_open(messages: &[&str]) -> Self {
+        let mut frames = Vec::new();
+        for message in messages {
+            frames.extend(websocket_frame(0x1, message.as_bytes()));
+        }
+        Self {
+            hold_open: true,
+            ..Self::new(101, frames)
+                .with_header("Upgrade", "websocket")
+                .with_header("Connection", "Upgrade")
+        }
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+type Handler = dyn Fn(&MockRequest) -> MockResponse + Send + Sync;
+
+/// A minimal HTTP server standing in for Loki, recording every request it receives.
+///
+/// The server is shut down via [`Drop`].
+pub struct MockLoki {
+    endpoint: String,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+    task: JoinHandle<()>,
+}
+
+impl MockLoki {
+    pub async fn start(
+        handler: impl Fn(&MockRequest) -> MockResponse + Send + Sync + 'static,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let task = tokio::spawn({
+            let requests = requests.clone();
+            async move {
+                while let Ok((socket, _)) = listener.accept().await {
+                    let requests = requests.clone();
+                    let handler = handler.clone();
+                    tokio::spawn(async move {
+                        let _ = serve_connection(socket, requests, handler).await;
+                    });
+                }
+            }
+        });
+
+        Self {
+            endpoint,
+            requests,
+            task,
+        }
+    }
+
+    pub fn endpoint(&self) -> String {
+        self.endpoint.clone()
+    }
+
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn requests_to(&self, path: &str) -> Vec<MockRequest> {
+        self.requests()
+            .into_iter()
+            .filter(|r| r.path == path)
+            .collect()
+    }
+}
+
+impl Drop for MockLoki {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve_connection(
+    socket: TcpStream,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+    handler: Arc<Handler>,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(socket);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers
+                .entry(name.trim().to_ascii_lowercase())
+                .and_modify(|v: &mut String| {
+                    v.push_str(", ");
+                    v.push_str(value.trim());
+                })
+                .or_insert_with(|| value.trim().to_string());
+        }
+    }
+
+    let content_length = headers
+        .get("content-length")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+
+    let url = url::Url::parse(&format!("http://localhost{target}")).unwrap();
+    let request = MockRequest {
+        method,
+        path: url.path().to_string(),
+        query: url.query_pairs().into_owned().collect(),
+        headers,
+        body,
+    };
+    let response = handler(&request);
+    requests.lock().unwrap().push(request);
+
+    // Upgraded connections carry websocket frames rather than a sized body
+    let mut head = if response.status == 101 {
+        "HTTP/1.1 101 Switching Protocols\r\n".to_string()
+    } else {
+        format!(
+            "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n",
+            response.status,
+            response.body.len()
+        )
+    };
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+
+    let socket = reader.get_mut();
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(&response.body).await?;
+    if response.hold_open {
+        let mut buf = [0; 1024];
+        while reader.read(&mut buf).await? > 0 {}
+        return Ok(());
+    }
+    socket.shutdown().await?;
+    Ok(())
+}
+
+/// An unmasked, unfragmented websocket frame, as sent by servers.
+pub fn websocket_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+pub fn parquet_bytes(batches: &[RecordBatch]) -> Vec<u8> {
+    parquet_bytes_with_metadata(batches, &[])
+}
+
+pub fn parquet_bytes_with_metadata(batches: &[RecordBatch], metadata: &[(&str, &str)]) -> Vec<u8> {
+    let metadata = metadata
+        .iter()
+        .map(|(k, v)| KeyValue::new(k.to_string(), v.to_string()))
+        .collect::<Vec<_>>();
+    let props = WriterProperties::builder()
+        .set_key_value_metadata(Some(metadata))
+        .build();
+    let mut buf = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut buf, batches[0].schema(), Some(props)).unwrap();
+    for batch in batches {
+        writer.write(batch).unwrap();
+    }
+    writer.close().unwrap();
+    buf
+}
+
+/// A `(timestamp_ns, labels, line)` log row.
+pub type LogRow<'a> = (i64, &'a [(&'a str, &'a str)], &'a str);
+
+/// Builds a batch with [`LOG_TABLE_SCHEMA`] from log rows.
+pub fn log_batch(rows: &[LogRow]) -> RecordBatch {
+    let labels = rows
+        .iter()
+        .map(|(_, labels, _)| *labels)
+        .collect::<Vec<_>>();
+    let timestamps =
+        TimestampNanosecondArray::from(rows.iter().map(|(ts, _, _)| *ts).collect::<Vec<_>>())
+            .with_timezone("UTC");
+    let lines = StringArray::from(rows.iter().map(|(_, _, line)| *line).collect::<Vec<_>>());
+
+    RecordBatch::try_new(
+        LOG_TABLE_SCHEMA.clone(),
+        vec![
+            Arc::new(timestamps),
+            Arc::new(labels_array(&labels)),
+            Arc::new(lines),
+        ],
+    )
+    .unwrap()
+}
+
+/// Builds a map array of the labels type from key-value pairs of each row.
+pub fn labels_array(rows: &[&[(&str, &str)]]) -> MapArray {
+    let mut builder = MapBuilder::new(
+        Some(MapFieldNames {
+            entry: "key_value".to_string(),
+            key: "key".to_string(),
+            value: "value".to_string(),
+        }),
+        StringBuilder::new(),
+        StringBuilder::new(),
+    )
+    .with_values_field(Arc::new(Field::new("value", DataType::Utf8, false)));
+    for entries in rows {
+        for (key, value) in entries.iter() {
+            builder.keys().append_value(key);
+            builder.values().append_value(value);
+        }
+        builder.append(true).unwrap();
+    }
+    builder.finish()
+}
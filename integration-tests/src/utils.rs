@@ -9,10 +9,11 @@ use datafusion::{
         datatypes::{DataType, Field},
         util::pretty::pretty_format_batches,
     },
-    physical_plan::{collect, display::DisplayableExecutionPlan},
+    physical_plan::{ExecutionPlan, collect, display::DisplayableExecutionPlan},
     prelude::SessionContext,
 };
-use datafusion_loki::{DFResult, TIMESTAMP_FIELD_REF};
+use datafusion_loki::{DFResult, LokiLogScanExec, LokiPhysicalCodec, TIMESTAMP_FIELD_REF};
+use datafusion_proto::{physical_plan::AsExecutionPlan, protobuf::PhysicalPlanNode};
 
 use crate::{build_session_context, setup_loki};
 
@@ -169,3 +170,34 @@ fn sort_map_array(arr: &MapArray, map_field: &Field) -> MapArray {
     }
     builder.finish()
 }
+
+/// Finds the first [`LokiLogScanExec`] in `plan`.
+pub fn find_scan_exec(plan: &Arc<dyn ExecutionPlan>) -> Option<&LokiLogScanExec> {
+    if let Some(exec) = plan.as_any().downcast_ref::<LokiLogScanExec>() {
+        return Some(exec);
+    }
+    plan.children().into_iter().find_map(find_scan_exec)
+}
+
+/// Plans `sql` and returns its [`LokiLogScanExec`].
+pub async fn plan_scan_exec(
+    ctx: &SessionContext,
+    sql: &str,
+) -> Result<LokiLogScanExec, Box<dyn std::error::Error>> {
+    let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+    let exec = find_scan_exec(&plan).ok_or("plan has no LokiLogScanExec")?;
+    Ok(exec.clone())
+}
+
+/// Round-trips a plan through [`LokiPhysicalCodec`], as for distributed execution.
+pub fn roundtrip_plan(
+    plan: Arc<dyn ExecutionPlan>,
+    ctx: &SessionContext,
+) -> Result<Arc<dyn ExecutionPlan>, Box<dyn std::error::Error>> {
+    let codec = LokiPhysicalCodec {};
+    let mut buf = vec![];
+    PhysicalPlanNode::try_from_physical_plan(plan, &codec)?.try_encode(&mut buf)?;
+    let plan =
+        PhysicalPlanNode::try_decode(&buf)?.try_into_physical_plan(&ctx.task_ctx(), &codec)?;
+    Ok(plan)
+}
@@ -0,0 +1,349 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use datafusion::{
+    arrow::util::pretty::pretty_format_batches,
+    physical_plan::{ExecutionPlan, collect, empty::EmptyExec},
+    prelude::SessionContext,
+};
+use datafusion_loki::{
+    DEFAULT_BATCH_SIZE, DEFAULT_MAX_PUSH_CONCURRENCY, DEFAULT_PUSH_CONTENT_TYPE,
+    DuplicateLabelPolicy, LOG_TABLE_SCHEMA, LokiDirection, LokiHttpOptions, LokiLogInsertExec,
+    LokiLogScanExec, LokiLogTable, LokiLogicalCodec, LokiPhysicalCodec, PartitionErrorPolicy,
+    current_timestamp_ns, register_loki_udfs,
+};
+use datafusion_proto::{
+    bytes::{
+        logical_plan_from_bytes_with_extension_codec, logical_plan_to_bytes_with_extension_codec,
+    },
+    physical_plan::{AsExecutionPlan, PhysicalExtensionCodec},
+    protobuf::PhysicalPlanNode,
+};
+use integration_tests::{MockLoki, MockResponse, log_batch};
+use prost::Message;
+
+/// `LokiPhysicalPlanNode` as serialized by the first released version, standing in for
+/// an older node of a rolling upgrade.
+#[derive(Clone, PartialEq, Message)]
+struct LegacyPlanNode {
+    #[prost(oneof = "LegacyPlanType", tags = "1, 2")]
+    plan: Option<LegacyPlanType>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+enum LegacyPlanType {
+    #[prost(message, tag = "1")]
+    Scan(LegacyScanExec),
+    #[prost(message, tag = "2")]
+    Insert(LegacyInsertExec),
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct LegacyScanExec {
+    #[prost(string, tag = "1")]
+    endpoint: String,
+    #[prost(string, tag = "2")]
+    log_query: String,
+    #[prost(int64, optional, tag = "3")]
+    start: Option<i64>,
+    #[prost(int64, optional, tag = "4")]
+    end: Option<i64>,
+    #[prost(message, optional, tag = "5")]
+    projection: Option<LegacyProjection>,
+    #[prost(int32, optional, tag = "6")]
+    limit: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct LegacyProjection {
+    #[prost(uint32, repeated, tag = "1")]
+    projection: Vec<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct LegacyInsertExec {
+    #[prost(string, tag = "1")]
+    endpoint: String,
+}
+
+#[tokio::test]
+async fn decode_scan_serialized_by_older_version() -> Result<(), Box<dyn std::error::Error>> {
+    let now = current_timestamp_ns();
+    let batch = log_batch(&[(now - 1_000_000_000, &[("app", "my-app")], "this is aaa log")]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let legacy = LegacyPlanNode {
+        plan: Some(LegacyPlanType::Scan(LegacyScanExec {
+            endpoint: mock.endpoint(),
+            log_query: r#"{app="my-app"}"#.to_string(),
+            start: Some(now - 60_000_000_000),
+            end: Some(now),
+            projection: Some(LegacyProjection {
+                projection: vec![2],
+            }),
+            limit: Some(10),
+        })),
+    };
+    let ctx = SessionContext::new();
+    let plan = LokiPhysicalCodec.try_decode(&legacy.encode_to_vec(), &[], &ctx.task_ctx())?;
+
+    let exec = plan.as_any().downcast_ref::<LokiLogScanExec>().unwrap();
+    assert_eq!(exec.projection, Some(vec![2]));
+    assert_eq!(exec.limit, Some(10));
+    assert_eq!(exec.schema, *LOG_TABLE_SCHEMA);
+    assert_eq!(exec.batch_size, DEFAULT_BATCH_SIZE);
+    assert_eq!(exec.target_partitions, 1);
+    assert_eq!(exec.direction, LokiDirection::Backward);
+    assert_eq!(exec.partition_error_policy, PartitionErrorPolicy::FailFast);
+    assert_eq!(exec.http_options.tenant, None);
+    assert!(!exec.structured_metadata && !exec.truncation_flag && !exec.fail_on_empty);
+    assert!(!exec.response_compression);
+
+    let batches = collect(plan, ctx.task_ctx()).await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-----------------+
+| line            |
++-----------------+
+| this is aaa log |
++-----------------+"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn decode_insert_serialized_by_older_version() -> Result<(), Box<dyn std::error::Error>> {
+    let legacy = LegacyPlanNode {
+        plan: Some(LegacyPlanType::Insert(LegacyInsertExec {
+            endpoint: "http://localhost:3100".to_string(),
+        })),
+    };
+    let input: Arc<dyn ExecutionPlan> = Arc::new(EmptyExec::new(LOG_TABLE_SCHEMA.clone()));
+    let ctx = SessionContext::new();
+    let plan = LokiPhysicalCodec.try_decode(&legacy.encode_to_vec(), &[input], &ctx.task_ctx())?;
+
+    let exec = plan.as_any().downcast_ref::<LokiLogInsertExec>().unwrap();
+    assert_eq!(exec.endpoint, "http://localhost:3100");
+    assert_eq!(exec.push_content_type, DEFAULT_PUSH_CONTENT_TYPE);
+    assert_eq!(exec.duplicate_label_policy, DuplicateLabelPolicy::KeepLast);
+    assert_eq!(exec.clock_skew_tolerance, None);
+    assert_eq!((exec.max_push_bytes, exec.max_push_lines), (None, None));
+    assert_eq!(exec.max_push_concurrency, DEFAULT_MAX_PUSH_CONCURRENCY);
+    assert!(!exec.detailed_result);
+    Ok(())
+}
+
+#[tokio::test]
+async fn older_version_decodes_newer_scan() -> Result<(), Box<dyn std::error::Error>> {
+    let exec = LokiLogScanExec::try_new(
+        "http://localhost:3100".to_string(),
+        r#"{app="my-app"}"#.to_string(),
+        Some(1),
+        Some(2),
+        Some(vec![0, 2]),
+        Some(5),
+    )?
+    .with_direction(LokiDirection::Forward)
+    .with_http_options(LokiHttpOptions {
+        tenant: Some("team-a".to_string()),
+        ..Default::default()
+    })
+    .with_target_partitions(4)?;
+    let mut buf = Vec::new();
+    LokiPhysicalCodec.try_encode(Arc::new(exec), &mut buf)?;
+
+    // Fields added since are skipped as unknown
+    let legacy = LegacyPlanNode::decode(buf.as_slice())?;
+    assert_eq!(
+        legacy,
+        LegacyPlanNode {
+            plan: Some(LegacyPlanType::Scan(LegacyScanExec {
+                endpoint: "http://localhost:3100".to_string(),
+                log_query: r#"{app="my-app"}"#.to_string(),
+                start: Some(1),
+                end: Some(2),
+                projection: Some(LegacyProjection {
+                    projection: vec![0, 2],
+                }),
+                limit: Some(5),
+            })),
+        }
+    );
+    Ok(())
+}
+
+fn find_scan(plan: &Arc<dyn ExecutionPlan>) -> Option<&LokiLogScanExec> {
+    plan.as_any()
+        .downcast_ref::<LokiLogScanExec>()
+        .or_else(|| plan.children().into_iter().find_map(find_scan))
+}
+
+#[tokio::test]
+async fn roundtrip_preserves_table_configuration() -> Result<(), Box<dyn std::error::Error>> {
+    let table = LokiLogTable::try_new("http://localhost:3100")?
+        .with_default_label(Some("app".to_string()))
+        .with_query_tags(HashMap::from([(
+            "source".to_string(),
+            "grafana".to_string(),
+        )]))
+        .with_age_column(true)
+        .with_source_column("cluster", "eu-1")
+        .with_structured_metadata_column(true)
+        .with_sorted_labels(true)
+        .with_partition_error_policy(PartitionErrorPolicy::SkipAndWarn)
+        .with_target_partitions(3)
+        .with_max_entries_limit(Some(100))
+        .with_fail_on_empty(true)
+        .with_max_labels_per_stream(2)
+        .with_truncation_flag(true)
+        .with_bearer_token(Some("token".to_string()))
+        .with_basic_auth("user".to_string(), Some("secret".to_string()))
+        .with_tenant("team-a")
+        .with_headers(HashMap::from([("X-Env".to_string(), "prod".to_string())]))
+        .with_timeout(Duration::from_secs(7));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+    let plan = ctx
+        .sql("select * from loki where line like '%error%' limit 5")
+        .await?
+        .create_physical_plan()
+        .await?;
+
+    let codec = LokiPhysicalCodec;
+    let mut buf = Vec::new();
+    PhysicalPlanNode::try_from_physical_plan(plan.clone(), &codec)?.try_encode(&mut buf)?;
+    let decoded =
+        PhysicalPlanNode::try_decode(&buf)?.try_into_physical_plan(&ctx.task_ctx(), &codec)?;
+
+    let expected = find_scan(&plan).unwrap();
+    let actual = find_scan(&decoded).unwrap();
+    assert_eq!(actual.endpoint, expected.endpoint);
+    assert_eq!(actual.log_query, r#"{app=~".+"} |= `error`"#);
+    assert_eq!(actual.log_query, expected.log_query);
+    assert_eq!((actual.start, actual.end), (expected.start, expected.end));
+    assert_eq!(actual.schema, expected.schema);
+    assert_eq!(actual.projection, expected.projection);
+    assert_eq!(actual.limit, expected.limit);
+    assert_eq!(actual.query_tags, expected.query_tags);
+    assert_eq!(actual.age_reference, expected.age_reference);
+    assert_eq!(actual.source_column, expected.source_column);
+    assert_eq!(
+        actual.partition_error_policy,
+        PartitionErrorPolicy::SkipAndWarn
+    );
+    assert_eq!(actual.target_partitions, 3);
+    assert_eq!(actual.max_entries_limit, Some(100));
+    assert_eq!(actual.direction, expected.direction);
+    assert_eq!(actual.batch_size, expected.batch_size);
+    assert_eq!(actual.max_labels_per_stream, Some(2));
+    assert!(actual.structured_metadata && actual.truncation_flag && actual.fail_on_empty);
+    assert_eq!(
+        format!("{:?}", actual.http_options),
+        format!("{:?}", expected.http_options)
+    );
+    assert_eq!(actual.http_options.tenant.as_deref(), Some("team-a"));
+    assert_eq!(actual.http_options.timeout, Some(Duration::from_secs(7)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn logical_plan_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+    let now = current_timestamp_ns();
+    let batch = log_batch(&[(now - 1_000_000_000, &[("app", "my-app")], "this is aaa log")]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_mandatory_selector(r#"cluster="prod""#)
+        .with_tenant("team-a")
+        .with_bearer_token(Some("token".to_string()))
+        .with_source_column("source", "loki")
+        .with_max_entries_limit(None)
+        .with_insert_default_labels(HashMap::from([("app".to_string(), "my-app".to_string())]))?;
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+    let plan = ctx
+        .sql("select line, source from loki where line like '%aaa%'")
+        .await?
+        .into_optimized_plan()?;
+
+    let bytes = logical_plan_to_bytes_with_extension_codec(&plan, &LokiLogicalCodec)?;
+    let remote_ctx = SessionContext::new();
+    let decoded = logical_plan_from_bytes_with_extension_codec(
+        &bytes,
+        &remote_ctx.task_ctx(),
+        &LokiLogicalCodec,
+    )?;
+    assert_eq!(format!("{decoded}"), format!("{plan}"));
+
+    let batches = remote_ctx
+        .execute_logical_plan(decoded)
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-----------------+--------+
+| line            | source |
++-----------------+--------+
+| this is aaa log | loki   |
++-----------------+--------+"#
+    );
+    let request = mock.requests().pop().unwrap();
+    assert_eq!(
+        request.query_param("query"),
+        Some(r#"{cluster="prod"} |= `aaa`"#)
+    );
+    assert_eq!(request.query_param("limit"), None);
+    assert_eq!(request.header("X-Scope-OrgID"), Some("team-a"));
+    assert_eq!(request.header("Authorization"), Some("Bearer token"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn decode_loki_udfs_on_fresh_context() -> Result<(), Box<dyn std::error::Error>> {
+    let now = current_timestamp_ns();
+    let batch = log_batch(&[(
+        now - 1_000_000_000,
+        &[("app", "my-app")],
+        r#"{"level": "error"}"#,
+    )]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let mut ctx = SessionContext::new();
+    register_loki_udfs(&mut ctx)?;
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_default_label(Some("app".to_string()));
+    ctx.register_table("loki", Arc::new(table))?;
+    let df = ctx
+        .sql("select map_get(labels, 'app') as app, json_get(line, 'level') as level from loki")
+        .await?;
+    let expected = r#"+--------+-------+
+| app    | level |
++--------+-------+
+| my-app | error |
++--------+-------+"#;
+
+    // Neither context decoding the plans registered the functions
+    let remote_ctx = SessionContext::new();
+    let bytes = logical_plan_to_bytes_with_extension_codec(df.logical_plan(), &LokiLogicalCodec)?;
+    let logical_plan = logical_plan_from_bytes_with_extension_codec(
+        &bytes,
+        &remote_ctx.task_ctx(),
+        &LokiLogicalCodec,
+    )?;
+    let batches = remote_ctx
+        .execute_logical_plan(logical_plan)
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(pretty_format_batches(&batches)?.to_string(), expected);
+
+    let remote_ctx = SessionContext::new();
+    let plan = df.create_physical_plan().await?;
+    let mut buf = Vec::new();
+    PhysicalPlanNode::try_from_physical_plan(plan, &LokiPhysicalCodec)?.try_encode(&mut buf)?;
+    let plan = PhysicalPlanNode::try_decode(&buf)?
+        .try_into_physical_plan(&remote_ctx.task_ctx(), &LokiPhysicalCodec)?;
+    let batches = collect(plan, remote_ctx.task_ctx()).await?;
+    assert_eq!(pretty_format_batches(&batches)?.to_string(), expected);
+    Ok(())
+}
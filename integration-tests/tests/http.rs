@@ -0,0 +1,373 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use datafusion::{
+    physical_plan::{collect, displayable},
+    prelude::SessionContext,
+};
+use datafusion_loki::LokiLogTable;
+use integration_tests::{MockLoki, MockResponse, log_batch, parquet_bytes, roundtrip_plan};
+use tracing::{
+    Event, Metadata, Subscriber,
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+};
+use tracing_core::span::Current;
+
+#[tokio::test]
+async fn bearer_token() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|req| match req.path.as_str() {
+        "/loki/api/v1/push" => MockResponse::new(204, vec![]),
+        _ => MockResponse::parquet(&[log_batch(&[])]),
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_bearer_token(Some("secret-token".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    for sql in [
+        "select * from loki",
+        "insert into loki values (now(), Map {'app': 'my-app'}, 'bearer token')",
+    ] {
+        let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+        collect(plan.clone(), ctx.task_ctx()).await?;
+        collect(roundtrip_plan(plan, &ctx)?, ctx.task_ctx()).await?;
+    }
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 4);
+    for request in requests {
+        assert_eq!(
+            request.header("Authorization"),
+            Some("Bearer secret-token"),
+            "{}",
+            request.path
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn basic_auth() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|req| match req.path.as_str() {
+        "/loki/api/v1/push" => MockResponse::new(204, vec![]),
+        _ => MockResponse::parquet(&[log_batch(&[])]),
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_basic_auth("loki-user".to_string(), Some("loki-password".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    for sql in [
+        "select * from loki",
+        "insert into loki values (now(), Map {'app': 'my-app'}, 'basic auth')",
+    ] {
+        let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+        let plan_str = displayable(plan.as_ref()).indent(true).to_string();
+        assert!(plan_str.contains("basic_auth=***"), "{plan_str}");
+        assert!(!plan_str.contains("loki-password"), "{plan_str}");
+
+        collect(plan.clone(), ctx.task_ctx()).await?;
+        collect(roundtrip_plan(plan, &ctx)?, ctx.task_ctx()).await?;
+    }
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 4);
+    for request in requests {
+        // base64 of `loki-user:loki-password`
+        assert_eq!(
+            request.header("Authorization"),
+            Some("Basic bG9raS11c2VyOmxva2ktcGFzc3dvcmQ="),
+            "{}",
+            request.path
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn tenant() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|req| match req.path.as_str() {
+        "/loki/api/v1/push" => MockResponse::new(204, vec![]),
+        _ => MockResponse::parquet(&[log_batch(&[])]),
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table.clone()))?;
+    ctx.register_table("tenant_loki", Arc::new(table.with_tenant("team-a")))?;
+
+    for sql in [
+        "select * from tenant_loki",
+        "insert into tenant_loki values (now(), Map {'app': 'my-app'}, 'tenant')",
+    ] {
+        let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+        let plan_str = displayable(plan.as_ref()).indent(true).to_string();
+        assert!(plan_str.contains("tenant=team-a"), "{plan_str}");
+
+        collect(plan.clone(), ctx.task_ctx()).await?;
+        collect(roundtrip_plan(plan, &ctx)?, ctx.task_ctx()).await?;
+    }
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 4);
+    for request in requests {
+        assert_eq!(request.header("X-Scope-OrgID"), Some("team-a"));
+    }
+
+    ctx.sql("select * from loki").await?.collect().await?;
+    let request = mock.requests().pop().unwrap();
+    assert_eq!(request.header("X-Scope-OrgID"), None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn custom_headers() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|req| match req.path.as_str() {
+        "/loki/api/v1/push" => MockResponse::new(204, vec![]),
+        _ => MockResponse::parquet(&[log_batch(&[])]),
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_tenant("team-a")
+        .with_headers(HashMap::from([
+            ("X-Proxy-Route".to_string(), "loki-eu".to_string()),
+            ("X-Scope-OrgID".to_string(), "team-b".to_string()),
+        ]));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    for sql in [
+        "select * from loki",
+        "insert into loki values (now(), Map {'app': 'my-app'}, 'custom headers')",
+    ] {
+        let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+        collect(plan.clone(), ctx.task_ctx()).await?;
+        collect(roundtrip_plan(plan, &ctx)?, ctx.task_ctx()).await?;
+    }
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 4);
+    for request in requests {
+        assert_eq!(request.header("X-Proxy-Route"), Some("loki-eu"));
+        assert_eq!(request.header("X-Scope-OrgID"), Some("team-b"));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn request_middleware() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|req| match req.path.as_str() {
+        "/loki/api/v1/push" => MockResponse::new(204, vec![]),
+        "/loki/api/v1/status/buildinfo" => MockResponse::ok(),
+        _ => MockResponse::parquet(&[log_batch(&[])]),
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_headers(HashMap::from([(
+            "X-Signature".to_string(),
+            "overridden".to_string(),
+        )]))
+        .with_request_middleware(Arc::new(|req: &mut reqwest::Request| {
+            let signature = format!("signed:{}", req.url().path());
+            req.headers_mut()
+                .insert("X-Signature", signature.parse().unwrap());
+        }));
+    table.check_connection().await?;
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+    ctx.sql("select * from loki").await?.collect().await?;
+    ctx.sql("insert into loki values (now(), Map {'app': 'my-app'}, 'middleware')")
+        .await?
+        .collect()
+        .await?;
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 3);
+    for request in requests {
+        assert_eq!(
+            request.header("X-Signature"),
+            Some(format!("signed:{}", request.path).as_str())
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn timeout() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| {
+        std::thread::sleep(Duration::from_secs(2));
+        MockResponse::new(204, vec![])
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_timeout(Duration::from_millis(200));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    for sql in [
+        "select * from loki",
+        "insert into loki values (now(), Map {'app': 'my-app'}, 'timeout')",
+    ] {
+        let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+        let plan = roundtrip_plan(plan, &ctx)?;
+        let err = collect(plan, ctx.task_ctx()).await.unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("request to loki timed out after 200ms"),
+            "{err}"
+        );
+    }
+    Ok(())
+}
+
+/// Collects the name and fields of every span created while it's the default subscriber.
+#[derive(Debug, Default, Clone)]
+struct SpanRecorder {
+    spans: Arc<Mutex<Vec<RecordedSpan>>>,
+    /// Spans entered on the test's single thread.
+    entered: Arc<Mutex<Vec<Id>>>,
+}
+
+#[derive(Debug)]
+struct RecordedSpan {
+    metadata: &'static Metadata<'static>,
+    fields: HashMap<String, String>,
+}
+
+impl SpanRecorder {
+    fn spans_named(&self, name: &str) -> Vec<HashMap<String, String>> {
+        self.spans
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|span| span.metadata.name() == name)
+            .map(|span| span.fields.clone())
+            .collect()
+    }
+}
+
+struct FieldRecorder<'a>(&'a mut HashMap<String, String>);
+
+impl Visit for FieldRecorder<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}"));
+    }
+}
+
+impl Subscriber for SpanRecorder {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut fields = HashMap::new();
+        span.record(&mut FieldRecorder(&mut fields));
+        let mut spans = self.spans.lock().unwrap();
+        spans.push(RecordedSpan {
+            metadata: span.metadata(),
+            fields,
+        });
+        Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut spans = self.spans.lock().unwrap();
+        let span = &mut spans[span.into_u64() as usize - 1];
+        values.record(&mut FieldRecorder(&mut span.fields));
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, _event: &Event<'_>) {}
+
+    fn enter(&self, span: &Id) {
+        self.entered.lock().unwrap().push(span.clone());
+    }
+
+    fn exit(&self, _span: &Id) {
+        self.entered.lock().unwrap().pop();
+    }
+
+    fn current_span(&self) -> Current {
+        match self.entered.lock().unwrap().last() {
+            Some(id) => {
+                let metadata = self.spans.lock().unwrap()[id.into_u64() as usize - 1].metadata;
+                Current::new(id.clone(), metadata)
+            }
+            None => Current::none(),
+        }
+    }
+}
+
+#[tokio::test]
+async fn trace_scan_and_insert_requests() -> Result<(), Box<dyn std::error::Error>> {
+    let batch = log_batch(&[(1_000, &[("app", "my-app")], "traced")]);
+    let response_bytes = parquet_bytes(std::slice::from_ref(&batch)).len();
+    let mock = MockLoki::start(move |req| match req.path.as_str() {
+        "/loki/api/v1/push" => MockResponse::new(204, vec![]),
+        _ => MockResponse::parquet(std::slice::from_ref(&batch)),
+    })
+    .await;
+
+    let recorder = SpanRecorder::default();
+    let _guard = tracing::subscriber::set_default(recorder.clone());
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_time_bounds(Some(0), Some(1_000_000))
+        .with_target_partitions(1);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+    ctx.sql("select * from loki").await?.collect().await?;
+    ctx.sql("insert into loki values (now(), Map {'app': 'my-app'}, 'traced')")
+        .await?
+        .collect()
+        .await?;
+
+    let queries = recorder.spans_named("loki_query_range");
+    assert_eq!(queries.len(), 1);
+    assert_eq!(queries[0]["endpoint"], mock.endpoint());
+    assert_eq!(queries[0]["log_query"], r#"{app=~".+"} "#);
+    assert_eq!(queries[0]["start"], "0");
+    assert_eq!(queries[0]["end"], "1000000");
+    let requests = recorder.spans_named("loki_request");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0]["status"], "200");
+    assert_eq!(requests[0]["bytes"], response_bytes.to_string());
+
+    let pushes = recorder.spans_named("loki_push");
+    assert_eq!(pushes.len(), 1);
+    assert_eq!(pushes[0]["endpoint"], mock.endpoint());
+    assert_eq!(pushes[0]["lines"], "1");
+    assert_eq!(pushes[0]["streams"], "1");
+    assert_eq!(pushes[0]["status"], "204");
+    assert_eq!(
+        pushes[0]["bytes"],
+        mock.requests_to("/loki/api/v1/push")[0]
+            .body
+            .len()
+            .to_string()
+    );
+    Ok(())
+}
@@ -0,0 +1,843 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use datafusion::{
+    arrow::{
+        array::{
+            ArrayRef, Int64Array, LargeStringBuilder, MapBuilder, RecordBatch,
+            StringDictionaryBuilder,
+        },
+        datatypes::{DataType, Field, Int32Type, Schema},
+        util::pretty::pretty_format_batches,
+    },
+    catalog::TableProvider,
+    datasource::memory::MemorySourceConfig,
+    logical_expr::dml::InsertOp,
+    physical_plan::{ExecutionPlanProperties, collect},
+    prelude::SessionContext,
+};
+use datafusion_loki::{
+    DETAILED_INSERT_RESULT_SCHEMA, DuplicateLabelPolicy, LABELS_FIELD_REF, LINE_FIELD_REF,
+    LOG_TABLE_SCHEMA, LokiLogInsertExec, LokiLogTable, STRUCTURED_METADATA_FIELD_REF,
+    current_timestamp_ns,
+};
+use flate2::read::GzDecoder;
+use integration_tests::{MockLoki, MockResponse, labels_array, log_batch, roundtrip_plan};
+
+#[tokio::test]
+async fn insert_int64_timestamp_as_nanos() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::new(204, vec![])).await;
+
+    let logs = log_batch(&[(0, &[("app", "my-app1")], "this is aaa log")]);
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("timestamp", DataType::Int64, false),
+        LABELS_FIELD_REF.as_ref().clone(),
+        LINE_FIELD_REF.as_ref().clone(),
+    ]));
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(Int64Array::from(vec![1_700_000_000_123_456_789])),
+            logs.column(1).clone(),
+            logs.column(2).clone(),
+        ],
+    )?;
+    let input = MemorySourceConfig::try_new_exec(&[vec![batch]], schema, None)?;
+
+    let ctx = SessionContext::new();
+    let table = LokiLogTable::try_new(mock.endpoint())?;
+    assert!(
+        table
+            .insert_into(&ctx.state(), input.clone(), InsertOp::Append)
+            .await
+            .is_err()
+    );
+
+    let table = table.with_int64_timestamp_as_nanos(true);
+    let exec = table
+        .insert_into(&ctx.state(), input, InsertOp::Append)
+        .await?;
+    collect(exec, ctx.task_ctx()).await?;
+
+    let requests = mock.requests_to("/loki/api/v1/push");
+    assert_eq!(requests.len(), 1);
+    let body = String::from_utf8(requests[0].body.clone())?;
+    assert_eq!(
+        body,
+        r#"{"streams":[{"stream":{"app":"my-app1"},"values":[["1700000000123456789","this is aaa log"]]}]}"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_with_column_list() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::new(204, vec![])).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_insert_default_labels(HashMap::from([("app".to_string(), "my-app".to_string())]))?;
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    ctx.sql("insert into loki (timestamp, line) values ('2025-01-01T00:00:00Z', 'default labels')")
+        .await?
+        .collect()
+        .await?;
+    ctx.sql("insert into loki (line, labels, timestamp) values ('explicit labels', Map {'app': 'other-app'}, '2025-01-01T00:00:01Z')")
+        .await?
+        .collect()
+        .await?;
+
+    let bodies = mock
+        .requests_to("/loki/api/v1/push")
+        .into_iter()
+        .map(|r| String::from_utf8(r.body))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        bodies,
+        vec![
+            r#"{"streams":[{"stream":{"app":"my-app"},"values":[["1735689600000000000","default labels"]]}]}"#,
+            r#"{"streams":[{"stream":{"app":"other-app"},"values":[["1735689601000000000","explicit labels"]]}]}"#,
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_groups_rows_by_labels() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::new(204, vec![])).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?;
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    ctx.sql(
+        "insert into loki values \
+        ('2025-01-01T00:00:02Z', Map {'app': 'my-app', 'env': 'prod'}, 'third'), \
+        ('2025-01-01T00:00:00Z', Map {'app': 'other-app'}, 'other'), \
+        ('2025-01-01T00:00:00Z', Map {'env': 'prod', 'app': 'my-app'}, 'first'), \
+        ('2025-01-01T00:00:01Z', Map {'app': 'my-app', 'env': 'prod'}, 'second')",
+    )
+    .await?
+    .collect()
+    .await?;
+
+    let requests = mock.requests_to("/loki/api/v1/push");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(
+        String::from_utf8(requests[0].body.clone())?,
+        concat!(
+            r#"{"streams":[{"stream":{"app":"my-app","env":"prod"},"values":["#,
+            r#"["1735689600000000000","first"],["1735689601000000000","second"],["1735689602000000000","third"]]},"#,
+            r#"{"stream":{"app":"other-app"},"values":[["1735689600000000000","other"]]}]}"#
+        )
+    );
+    Ok(())
+}
+
+const CHUNKED_INSERT_SQL: &str = "insert into loki values \
+    ('2025-01-01T00:00:00Z', Map {'app': 'my-app'}, 'aaa'), \
+    ('2025-01-01T00:00:01Z', Map {'app': 'my-app'}, 'bbb'), \
+    ('2025-01-01T00:00:02Z', Map {'app': 'my-app'}, 'ccc'), \
+    ('2025-01-01T00:00:00Z', Map {'app': 'other-app'}, 'ddd'), \
+    ('2025-01-01T00:00:01Z', Map {'app': 'other-app'}, 'eee')";
+
+#[tokio::test]
+async fn insert_chunked_by_lines() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::new(204, vec![])).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_max_push_lines(2);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let batches = ctx.sql(CHUNKED_INSERT_SQL).await?.collect().await?;
+    assert_eq!(
+        batches[0].column(0).as_any().downcast_ref::<Int64Array>(),
+        Some(&Int64Array::from(vec![5]))
+    );
+
+    let bodies = mock
+        .requests_to("/loki/api/v1/push")
+        .iter()
+        .map(|req| String::from_utf8(req.body.clone()))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        bodies,
+        vec![
+            r#"{"streams":[{"stream":{"app":"my-app"},"values":[["1735689600000000000","aaa"],["1735689601000000000","bbb"]]}]}"#,
+            r#"{"streams":[{"stream":{"app":"my-app"},"values":[["1735689602000000000","ccc"]]},{"stream":{"app":"other-app"},"values":[["1735689600000000000","ddd"]]}]}"#,
+            r#"{"streams":[{"stream":{"app":"other-app"},"values":[["1735689601000000000","eee"]]}]}"#,
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_chunked_by_bytes() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::new(204, vec![])).await;
+
+    let max_push_bytes = 150;
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_max_push_bytes(max_push_bytes);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    ctx.sql(CHUNKED_INSERT_SQL).await?.collect().await?;
+
+    let requests = mock.requests_to("/loki/api/v1/push");
+    assert!(requests.len() > 1);
+    let mut lines = Vec::new();
+    for req in requests {
+        assert!(req.body.len() <= max_push_bytes);
+        let body: serde_json::Value = serde_json::from_slice(&req.body)?;
+        for stream in body["streams"].as_array().unwrap() {
+            for value in stream["values"].as_array().unwrap() {
+                lines.push(value[1].as_str().unwrap().to_string());
+            }
+        }
+    }
+    assert_eq!(lines, ["aaa", "bbb", "ccc", "ddd", "eee"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_chunk_failure() -> Result<(), Box<dyn std::error::Error>> {
+    let pushes = AtomicUsize::new(0);
+    let mock = MockLoki::start(move |_| match pushes.fetch_add(1, Ordering::SeqCst) {
+        0 => MockResponse::new(204, vec![]),
+        _ => MockResponse::new(413, "request body too large"),
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_max_push_lines(2);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let err = ctx
+        .sql(CHUNKED_INSERT_SQL)
+        .await?
+        .collect()
+        .await
+        .unwrap_err();
+    let message = err.to_string();
+    assert!(
+        message.contains("Failed to push chunk 2 of 3, 2 logs of the batch already pushed"),
+        "{message}"
+    );
+    assert!(message.contains("request body too large"), "{message}");
+    assert_eq!(mock.requests_to("/loki/api/v1/push").len(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_with_structured_metadata() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::new(204, vec![])).await;
+
+    let logs = log_batch(&[
+        (0, &[("app", "my-app")], "with metadata"),
+        (1, &[("app", "my-app")], "without metadata"),
+    ]);
+    let metadata = labels_array(&[&[("trace_id", "abc")], &[]]);
+    let mut fields = LOG_TABLE_SCHEMA.fields().to_vec();
+    fields.push(STRUCTURED_METADATA_FIELD_REF.clone());
+    let mut columns = logs.columns().to_vec();
+    columns.push(Arc::new(metadata));
+    let logs = RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)?;
+    let input = MemorySourceConfig::try_new_exec(&[vec![logs.clone()]], logs.schema(), None)?;
+
+    let ctx = SessionContext::new();
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_structured_metadata_column(true);
+    let exec = table
+        .insert_into(&ctx.state(), input, InsertOp::Append)
+        .await?;
+    let exec = roundtrip_plan(exec, &ctx)?;
+    collect(exec, ctx.task_ctx()).await?;
+
+    let requests = mock.requests_to("/loki/api/v1/push");
+    assert_eq!(
+        String::from_utf8(requests[0].body.clone())?,
+        concat!(
+            r#"{"streams":[{"stream":{"app":"my-app"},"values":["#,
+            r#"["0","with metadata",{"trace_id":"abc"}],"#,
+            r#"["1","without metadata"]]}]}"#
+        )
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_shuffled_timestamps() -> Result<(), Box<dyn std::error::Error>> {
+    // Rejects out-of-order entries within a stream, as older loki versions do
+    let mock = MockLoki::start(|req| {
+        let body: serde_json::Value = serde_json::from_slice(&req.body).unwrap();
+        let ordered = body["streams"].as_array().unwrap().iter().all(|stream| {
+            let timestamps = stream["values"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|value| value[0].as_str().unwrap().parse::<i64>().unwrap())
+                .collect::<Vec<_>>();
+            timestamps.is_sorted()
+        });
+        if ordered {
+            MockResponse::new(204, vec![])
+        } else {
+            MockResponse::new(400, "entry out of order")
+        }
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?;
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    ctx.sql(
+        "insert into loki values \
+        ('2025-01-01T00:00:03Z', Map {'app': 'my-app'}, 'ddd'), \
+        ('2025-01-01T00:00:01Z', Map {'app': 'my-app'}, 'bbb'), \
+        ('2025-01-01T00:00:04Z', Map {'app': 'my-app'}, 'eee'), \
+        ('2025-01-01T00:00:00Z', Map {'app': 'my-app'}, 'aaa'), \
+        ('2025-01-01T00:00:02Z', Map {'app': 'my-app'}, 'ccc')",
+    )
+    .await?
+    .collect()
+    .await?;
+
+    let body: serde_json::Value =
+        serde_json::from_slice(&mock.requests_to("/loki/api/v1/push")[0].body)?;
+    let lines = body["streams"][0]["values"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|value| value[1].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(lines, ["aaa", "bbb", "ccc", "ddd", "eee"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_multiple_partitions() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::new(204, vec![])).await;
+
+    let partitions = (0..3)
+        .map(|i| {
+            vec![log_batch(&[
+                (i, &[("app", "my-app")], "aaa"),
+                (i, &[("app", "other-app")], "bbb"),
+            ])]
+        })
+        .collect::<Vec<_>>();
+    let input = MemorySourceConfig::try_new_exec(&partitions, LOG_TABLE_SCHEMA.clone(), None)?;
+
+    let ctx = SessionContext::new();
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_max_push_concurrency(2);
+    let exec = table
+        .insert_into(&ctx.state(), input, InsertOp::Append)
+        .await?;
+    let exec = roundtrip_plan(exec, &ctx)?;
+    assert_eq!(exec.output_partitioning().partition_count(), 1);
+    let batches = collect(exec, ctx.task_ctx()).await?;
+    assert_eq!(
+        batches[0].column(0).as_any().downcast_ref::<Int64Array>(),
+        Some(&Int64Array::from(vec![6]))
+    );
+
+    let mut timestamps = mock
+        .requests_to("/loki/api/v1/push")
+        .iter()
+        .map(|req| {
+            let body: serde_json::Value = serde_json::from_slice(&req.body).unwrap();
+            body["streams"][0]["values"][0][0]
+                .as_str()
+                .unwrap()
+                .to_string()
+        })
+        .collect::<Vec<_>>();
+    timestamps.sort();
+    assert_eq!(timestamps, ["0", "1", "2"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_detailed_result() -> Result<(), Box<dyn std::error::Error>> {
+    let pushes = AtomicUsize::new(0);
+    let mock = MockLoki::start(move |_| match pushes.fetch_add(1, Ordering::SeqCst) {
+        1 => MockResponse::new(400, "rate limited"),
+        _ => MockResponse::new(204, vec![]),
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_max_push_lines(2)
+        .with_detailed_insert_result(true);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let batches = ctx.sql(CHUNKED_INSERT_SQL).await?.collect().await?;
+    assert_eq!(batches[0].schema(), *DETAILED_INSERT_RESULT_SCHEMA);
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+--------+--------+---------+
+| pushed | failed | streams |
++--------+--------+---------+
+| 3      | 2      | 2       |
++--------+--------+---------+"#
+    );
+    assert_eq!(mock.requests_to("/loki/api/v1/push").len(), 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_with_dictionary_labels() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::new(204, vec![])).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_dictionary_labels(true);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    ctx.sql("insert into loki values ('2025-01-01T00:00:00Z', Map {'app': 'my-app'}, 'dictionary labels')")
+        .await?
+        .collect()
+        .await?;
+
+    let requests = mock.requests_to("/loki/api/v1/push");
+    assert_eq!(
+        String::from_utf8(requests[0].body.clone())?,
+        r#"{"streams":[{"stream":{"app":"my-app"},"values":[["1735689600000000000","dictionary labels"]]}]}"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_reports_push_bytes() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::new(204, vec![])).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?;
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let plan = ctx
+        .sql(
+            "insert into loki values ('2025-01-01T00:00:00Z', Map {'app': 'my-app'}, 'push bytes')",
+        )
+        .await?
+        .create_physical_plan()
+        .await?;
+    collect(plan.clone(), ctx.task_ctx()).await?;
+
+    let requests = mock.requests_to("/loki/api/v1/push");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].header("Content-Type"), Some("application/json"));
+    let metrics = plan.metrics().unwrap();
+    assert_eq!(
+        metrics.sum_by_name("push_bytes").map(|v| v.as_usize()),
+        Some(requests[0].body.len())
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_metrics() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::new(204, vec![])).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?;
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let batches = ctx
+        .sql("explain analyze insert into loki values ('2025-01-01T00:00:00Z', Map {'app': 'my-app'}, 'aaa'), ('2025-01-01T00:00:01Z', Map {'app': 'my-app'}, 'bbb')")
+        .await?
+        .collect()
+        .await?;
+    let plan = pretty_format_batches(&batches)?.to_string();
+    let insert = plan
+        .lines()
+        .find(|line| line.contains("LokiLogInsertExec"))
+        .unwrap();
+    let requests = mock.requests_to("/loki/api/v1/push");
+    assert_eq!(requests.len(), 1);
+    for metric in [
+        "rows_pushed=2".to_string(),
+        "push_requests=1".to_string(),
+        format!("push_bytes={}", requests[0].body.len()),
+        "push_time=".to_string(),
+    ] {
+        assert!(insert.contains(&metric), "{metric} missing from {insert}");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_duplicate_label_policy() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::new(204, vec![])).await;
+
+    let logs = log_batch(&[(0, &[], "duplicate labels")]);
+    let batch = RecordBatch::try_new(
+        logs.schema(),
+        vec![
+            logs.column(0).clone(),
+            Arc::new(labels_array(&[&[("app", "first"), ("app", "last")]])),
+            logs.column(2).clone(),
+        ],
+    )?;
+    let input = MemorySourceConfig::try_new_exec(&[vec![batch]], logs.schema(), None)?;
+
+    let ctx = SessionContext::new();
+    let table = LokiLogTable::try_new(mock.endpoint())?;
+    for (policy, expected) in [
+        (DuplicateLabelPolicy::KeepFirst, Some("first")),
+        (DuplicateLabelPolicy::KeepLast, Some("last")),
+        (DuplicateLabelPolicy::Error, None),
+    ] {
+        let exec = table
+            .clone()
+            .with_duplicate_label_policy(policy)
+            .insert_into(&ctx.state(), input.clone(), InsertOp::Append)
+            .await?;
+        let result = collect(exec, ctx.task_ctx()).await;
+        match expected {
+            Some(app) => {
+                result?;
+                let body = String::from_utf8(mock.requests().pop().unwrap().body)?;
+                assert_eq!(
+                    body,
+                    format!(
+                        r#"{{"streams":[{{"stream":{{"app":"{app}"}},"values":[["0","duplicate labels"]]}}]}}"#
+                    )
+                );
+            }
+            None => {
+                let err = result.unwrap_err();
+                assert!(
+                    err.to_string()
+                        .contains("Duplicate label key app in labels map"),
+                    "{err}"
+                );
+            }
+        }
+    }
+    assert_eq!(mock.requests().len(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_clamps_future_timestamps() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::new(204, vec![])).await;
+
+    let minute = 60 * 1_000_000_000;
+    let before = current_timestamp_ns();
+    let logs = log_batch(&[
+        (
+            before + 24 * 60 * minute,
+            &[("app", "my-app")],
+            "future log",
+        ),
+        (
+            before + minute / 2,
+            &[("app", "my-app")],
+            "slightly ahead log",
+        ),
+        (1_000, &[("app", "my-app")], "past log"),
+    ]);
+    let input = MemorySourceConfig::try_new_exec(&[vec![logs]], LOG_TABLE_SCHEMA.clone(), None)?;
+
+    let ctx = SessionContext::new();
+    let table =
+        LokiLogTable::try_new(mock.endpoint())?.with_clock_skew_tolerance(Duration::from_secs(60));
+    let exec = table
+        .insert_into(&ctx.state(), input, InsertOp::Append)
+        .await?;
+    let exec = roundtrip_plan(exec, &ctx)?;
+    collect(exec, ctx.task_ctx()).await?;
+    let after = current_timestamp_ns();
+
+    let body: serde_json::Value =
+        serde_json::from_slice(&mock.requests_to("/loki/api/v1/push")[0].body)?;
+    let timestamps = body["streams"][0]["values"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|value| value[0].as_str().unwrap().parse::<i64>())
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(timestamps[..2], [1_000, before + minute / 2]);
+    assert!(before + minute <= timestamps[2] && timestamps[2] <= after + minute);
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_with_push_content_type() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::new(204, vec![])).await;
+
+    let logs = log_batch(&[(0, &[("app", "my-app")], "content type")]);
+    let input = MemorySourceConfig::try_new_exec(&[vec![logs]], LOG_TABLE_SCHEMA.clone(), None)?;
+
+    let ctx = SessionContext::new();
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_push_content_type("application/json; charset=utf-8");
+    let exec = table
+        .insert_into(&ctx.state(), input, InsertOp::Append)
+        .await?;
+    let exec = roundtrip_plan(exec, &ctx)?;
+    collect(exec, ctx.task_ctx()).await?;
+
+    let requests = mock.requests_to("/loki/api/v1/push");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(
+        requests[0].header("Content-Type"),
+        Some("application/json; charset=utf-8")
+    );
+    assert_eq!(
+        String::from_utf8(requests[0].body.clone())?,
+        r#"{"streams":[{"stream":{"app":"my-app"},"values":[["0","content type"]]}]}"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_with_push_compression() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::new(204, vec![])).await;
+
+    let logs = log_batch(&[(0, &[("app", "my-app")], "compressed")]);
+    let input = MemorySourceConfig::try_new_exec(&[vec![logs]], LOG_TABLE_SCHEMA.clone(), None)?;
+
+    let ctx = SessionContext::new();
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_push_compression(true);
+    let exec = table
+        .insert_into(&ctx.state(), input, InsertOp::Append)
+        .await?;
+    let exec = roundtrip_plan(exec, &ctx)?;
+    collect(exec.clone(), ctx.task_ctx()).await?;
+
+    let requests = mock.requests_to("/loki/api/v1/push");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].header("Content-Encoding"), Some("gzip"));
+    assert_eq!(requests[0].header("Content-Type"), Some("application/json"));
+    let mut body = String::new();
+    GzDecoder::new(requests[0].body.as_slice()).read_to_string(&mut body)?;
+    assert_eq!(
+        body,
+        r#"{"streams":[{"stream":{"app":"my-app"},"values":[["0","compressed"]]}]}"#
+    );
+    assert_eq!(
+        exec.metrics()
+            .unwrap()
+            .sum_by_name("push_bytes")
+            .map(|v| v.as_usize()),
+        Some(requests[0].body.len())
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_large_and_dictionary_labels() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::new(204, vec![])).await;
+
+    let mut large = MapBuilder::new(None, LargeStringBuilder::new(), LargeStringBuilder::new());
+    large.keys().append_value("app");
+    large.values().append_value("large-app");
+    large.append(true)?;
+    let mut dictionary = MapBuilder::new(
+        None,
+        StringDictionaryBuilder::<Int32Type>::new(),
+        StringDictionaryBuilder::<Int32Type>::new(),
+    );
+    dictionary.keys().append_value("app");
+    dictionary.values().append_value("dictionary-app");
+    dictionary.append(true)?;
+
+    let logs = log_batch(&[(0, &[], "encoded labels")]);
+    let ctx = SessionContext::new();
+    for labels in [
+        Arc::new(large.finish()) as ArrayRef,
+        Arc::new(dictionary.finish()),
+    ] {
+        let schema = Arc::new(Schema::new(vec![
+            logs.schema().field(0).clone(),
+            Field::new("labels", labels.data_type().clone(), false),
+            logs.schema().field(2).clone(),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![logs.column(0).clone(), labels, logs.column(2).clone()],
+        )?;
+        let input = MemorySourceConfig::try_new_exec(&[vec![batch]], schema, None)?;
+        let exec = LokiLogInsertExec::try_new(input, mock.endpoint())?;
+        collect(Arc::new(exec), ctx.task_ctx()).await?;
+    }
+
+    let bodies = mock
+        .requests_to("/loki/api/v1/push")
+        .into_iter()
+        .map(|r| String::from_utf8(r.body))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        bodies,
+        vec![
+            r#"{"streams":[{"stream":{"app":"large-app"},"values":[["0","encoded labels"]]}]}"#,
+            r#"{"streams":[{"stream":{"app":"dictionary-app"},"values":[["0","encoded labels"]]}]}"#,
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_matches_columns_by_position() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::new(204, vec![])).await;
+
+    let logs = log_batch(&[(0, &[("app", "my-app")], "renamed columns")]);
+    let schema = Schema::new(vec![
+        LOG_TABLE_SCHEMA.field(0).clone().with_name("ts"),
+        LOG_TABLE_SCHEMA.field(1).clone().with_name("l"),
+        LOG_TABLE_SCHEMA.field(2).clone().with_name("msg"),
+    ]);
+    let logs = RecordBatch::try_new(Arc::new(schema), logs.columns().to_vec())?;
+    let input = MemorySourceConfig::try_new_exec(&[vec![logs.clone()]], logs.schema(), None)?;
+    let exec = LokiLogInsertExec::try_new(input, mock.endpoint())?;
+    let ctx = SessionContext::new();
+    collect(Arc::new(exec), ctx.task_ctx()).await?;
+    assert_eq!(
+        String::from_utf8(mock.requests_to("/loki/api/v1/push")[0].body.clone())?,
+        r#"{"streams":[{"stream":{"app":"my-app"},"values":[["0","renamed columns"]]}]}"#
+    );
+
+    let schema = Schema::new(vec![
+        LOG_TABLE_SCHEMA.field(0).clone(),
+        Field::new("msg", DataType::Utf8, false),
+        LOG_TABLE_SCHEMA.field(1).clone(),
+    ]);
+    let input = MemorySourceConfig::try_new_exec(&[vec![]], Arc::new(schema), None)?;
+    let err = LokiLogInsertExec::try_new(input, mock.endpoint()).unwrap_err();
+    assert!(
+        err.to_string().contains(
+            "Insert input columns (timestamp Timestamp(ns, \"UTC\"), msg Utf8, labels Map("
+        ) && err.to_string().contains(
+            "don't match the expected columns (timestamp Timestamp(ns, \"UTC\"), labels Map("
+        ),
+        "{err}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_overwrite_deletes_time_range_first() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|req| match (req.method.as_str(), req.path.as_str()) {
+        ("GET", "/loki/api/v1/delete") => MockResponse::new(
+            200,
+            r#"[
+                {"request_id":"old","start_time":1735689600.123,"end_time":1735693200,"query":"{app=\"my-app\"}","status":"processed","created_at":1735600000},
+                {"request_id":"new","start_time":1735689600.123,"end_time":1735693200,"query":"{app=\"my-app\"}","status":"received","created_at":1735700000},
+                {"request_id":"other","start_time":0,"end_time":1735693200,"query":"{app=\"my-app\"}","status":"processed","created_at":1735800000}
+            ]"#,
+        ),
+        _ => MockResponse::new(204, vec![]),
+    })
+    .await;
+
+    let logs = log_batch(&[(0, &[("app", "my-app")], "overwritten")]);
+    let input = MemorySourceConfig::try_new_exec(&[vec![logs.clone()]], logs.schema(), None)?;
+    let ctx = SessionContext::new();
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_mandatory_selector(r#"app="my-app""#)
+        .with_time_bounds(
+            Some(1_735_689_600_123_000_000),
+            Some(1_735_693_200_000_000_000),
+        );
+    let err = table
+        .insert_into(&ctx.state(), input.clone(), InsertOp::Overwrite)
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("must be enabled with LokiLogTable::with_overwrite_via_delete"),
+        "{err}"
+    );
+    let err = table
+        .insert_into(&ctx.state(), input.clone(), InsertOp::Replace)
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("only append and overwrite inserts are supported"),
+        "{err}"
+    );
+
+    // The latest matching delete isn't processed yet
+    let table = table.with_overwrite_via_delete(Some(Duration::ZERO));
+    let exec = table
+        .insert_into(&ctx.state(), input, InsertOp::Overwrite)
+        .await?;
+    let exec = roundtrip_plan(exec, &ctx)?;
+    let err = collect(exec, ctx.task_ctx()).await.unwrap_err();
+    assert!(
+        err.to_string().contains(
+            r#"Loki didn't process the delete of {app="my-app"} within 0ns, no logs were pushed"#
+        ),
+        "{err}"
+    );
+    assert!(mock.requests_to("/loki/api/v1/push").is_empty());
+
+    let deletes = mock.requests_to("/loki/api/v1/delete");
+    assert_eq!(deletes[0].method, "POST");
+    assert_eq!(deletes[0].query_param("query"), Some(r#"{app="my-app"}"#));
+    assert_eq!(
+        deletes[0].query_param("start"),
+        Some("2025-01-01T00:00:00.123+00:00")
+    );
+    assert_eq!(
+        deletes[0].query_param("end"),
+        Some("2025-01-01T01:00:00+00:00")
+    );
+    assert_eq!(deletes[1].method, "GET");
+    Ok(())
+}
+
+#[tokio::test]
+async fn insert_overwrite_pushes_after_delete() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|req| match (req.method.as_str(), req.path.as_str()) {
+        ("GET", "/loki/api/v1/delete") => MockResponse::new(
+            200,
+            r#"[{"request_id":"a","start_time":1735689600,"end_time":1735693200,"query":"{app=~\".+\"}","status":"processed","created_at":1735700000}]"#,
+        ),
+        _ => MockResponse::new(204, vec![]),
+    })
+    .await;
+
+    let logs = log_batch(&[(0, &[("app", "my-app")], "overwritten")]);
+    let input = MemorySourceConfig::try_new_exec(&[vec![logs.clone()]], logs.schema(), None)?;
+    let ctx = SessionContext::new();
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_time_bounds(
+            Some(1_735_689_600_000_000_000),
+            Some(1_735_693_200_000_000_000),
+        )
+        .with_overwrite_via_delete(Some(Duration::from_secs(10)));
+    let exec = table
+        .insert_into(&ctx.state(), input, InsertOp::Overwrite)
+        .await?;
+    let batches = collect(exec, ctx.task_ctx()).await?;
+    assert_eq!(
+        batches[0].column(0).as_any().downcast_ref::<Int64Array>(),
+        Some(&Int64Array::from(vec![1]))
+    );
+
+    let requests = mock
+        .requests()
+        .into_iter()
+        .map(|req| format!("{} {}", req.method, req.path))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        requests,
+        vec![
+            "POST /loki/api/v1/delete",
+            "GET /loki/api/v1/delete",
+            "POST /loki/api/v1/push"
+        ]
+    );
+    Ok(())
+}
@@ -0,0 +1,1160 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use datafusion::{
+    arrow::{
+        array::{AsArray, BooleanArray},
+        compute::filter_record_batch,
+        datatypes::{Schema, TimestampNanosecondType},
+        util::pretty::pretty_format_batches,
+    },
+    catalog::TableProvider,
+    common::ScalarValue,
+    execution::SessionStateBuilder,
+    functions::core::expr_fn::get_field,
+    logical_expr::{ScalarUDF, TableProviderFilterPushDown},
+    physical_plan::{collect, displayable},
+    prelude::{SessionConfig, SessionContext, col, lit},
+};
+use datafusion_loki::{
+    DetectedField, LABELS_FIELD_REF, LINE_FIELD_REF, LOG_STREAMS_SCHEMA, LokiLogScanExec,
+    LokiLogTable, LokiMetricPushdown, MapContainsKey, MapGet, TIMESTAMP_FIELD_REF,
+    current_timestamp_ns,
+};
+use integration_tests::{
+    MockLoki, MockResponse, find_scan_exec, log_batch, plan_scan_exec, roundtrip_plan,
+};
+
+#[test]
+fn clone_with_endpoint_preserves_config() -> Result<(), Box<dyn std::error::Error>> {
+    let table = LokiLogTable::try_new("http://loki-dev:3100")?
+        .with_default_label(Some("app".to_string()))
+        .with_query_tags(HashMap::from([("user".to_string(), "alice".to_string())]))
+        .with_int64_timestamp_as_nanos(true);
+
+    let cloned = table.clone_with_endpoint("http://loki-prod:3100")?;
+    assert_eq!(cloned.endpoint, "http://loki-prod:3100");
+    assert_eq!(table.endpoint, "http://loki-dev:3100");
+    assert_eq!(cloned.default_label, table.default_label);
+    assert_eq!(cloned.query_tags, table.query_tags);
+    assert_eq!(cloned.schema, table.schema);
+    assert!(cloned.int64_timestamp_as_nanos);
+    Ok(())
+}
+
+#[tokio::test]
+async fn age_filter_sets_start() -> Result<(), Box<dyn std::error::Error>> {
+    let table = LokiLogTable::try_new("http://localhost:3100")?
+        .with_default_label(Some("app".to_string()))
+        .with_age_column(true);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let before = current_timestamp_ns();
+    let exec = plan_scan_exec(
+        &ctx,
+        "select line, age from loki where age < interval '15 minutes'",
+    )
+    .await?;
+    let after = current_timestamp_ns();
+
+    let reference = exec.age_reference.unwrap();
+    assert!(before - 1_000_000_000 <= reference && reference <= after);
+    assert_eq!(exec.start, Some(reference - 15 * 60 * 1_000_000_000 + 1));
+    assert_eq!(exec.end, Some(reference));
+
+    let exec = plan_scan_exec(&ctx, "select * from loki where age >= interval '1 day'").await?;
+    let reference = exec.age_reference.unwrap();
+    let end = reference - 24 * 60 * 60 * 1_000_000_000 + 1;
+    assert_eq!(exec.start, Some(end - 30 * 24 * 60 * 60 * 1_000_000_000));
+    assert_eq!(exec.end, Some(end));
+    Ok(())
+}
+
+#[tokio::test]
+async fn age_and_timestamp_filters_intersect() -> Result<(), Box<dyn std::error::Error>> {
+    let table = LokiLogTable::try_new("http://localhost:3100")?
+        .with_default_label(Some("app".to_string()))
+        .with_age_column(true);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table.clone()))?;
+
+    const HOUR: i64 = 60 * 60 * 1_000_000_000;
+    let now = current_timestamp_ns();
+    for (timestamp, age_wins) in [(now - 2 * HOUR, true), (now - HOUR / 2, false)] {
+        let timestamp_filter = format!("timestamp > to_timestamp_nanos({timestamp})");
+        let age_filter = "age < interval '1 hour'";
+        for sql in [
+            format!("select line from loki where {timestamp_filter} and {age_filter}"),
+            format!("select line from loki where {age_filter} and {timestamp_filter}"),
+        ] {
+            let exec = plan_scan_exec(&ctx, &sql).await?;
+            let reference = exec.age_reference.unwrap();
+            let expected = if age_wins {
+                reference - HOUR + 1
+            } else {
+                timestamp + 1
+            };
+            assert_eq!(exec.start, Some(expected), "{sql}");
+            assert_eq!(exec.end, Some(reference), "{sql}");
+        }
+    }
+
+    // Intervals too long for nanoseconds aren't pushed down
+    let too_long = col("age").gt(lit(ScalarValue::new_interval_mdn(0, 200_000, 0)));
+    assert_eq!(
+        table.supports_filters_pushdown(&[&too_long])?,
+        vec![TableProviderFilterPushDown::Unsupported]
+    );
+    plan_scan_exec(
+        &ctx,
+        "select line from loki where age > interval '200000 days'",
+    )
+    .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn pushdown_verification() -> Result<(), Box<dyn std::error::Error>> {
+    let batch = log_batch(&[
+        (1_000_000_000, &[("app", "my-app1")], "this is aaa log"),
+        (2_000_000_000, &[("app", "my-app1")], "this is bbb log"),
+    ]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+    let sql = "select line from loki where labels['app'] = 'my-app1' and line like '%aaa%'";
+
+    let mut outputs = vec![];
+    for verification in [false, true] {
+        let table =
+            LokiLogTable::try_new(mock.endpoint())?.with_pushdown_verification(verification);
+        let ctx = SessionContext::new();
+        ctx.register_table("loki", Arc::new(table))?;
+
+        let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+        let plan_str = displayable(plan.as_ref()).indent(true).to_string();
+        assert_eq!(plan_str.contains("FilterExec"), verification, "{plan_str}");
+        assert_eq!(
+            find_scan_exec(&plan).unwrap().log_query,
+            r#"{app="my-app1"} |= `aaa`"#
+        );
+
+        let batches = collect(plan, ctx.task_ctx()).await?;
+        outputs.push(pretty_format_batches(&batches)?.to_string());
+    }
+    // The mock ignores the pushed down line filter, which verification catches
+    assert_eq!(
+        outputs[1],
+        r#"+-----------------+
+| line            |
++-----------------+
+| this is aaa log |
++-----------------+"#
+    );
+    assert_ne!(outputs[0], outputs[1]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn detected_fields() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| {
+        MockResponse::new(
+            200,
+            r#"{"fields":[{"label":"level","type":"string","cardinality":3,"parsers":["logfmt"]},{"label":"duration","type":"duration","cardinality":120,"parsers":["json"]}],"limit":1000}"#,
+        )
+        .with_header("Content-Type", "application/json")
+    })
+    .await;
+
+    let table =
+        LokiLogTable::try_new(mock.endpoint())?.with_bearer_token(Some("token".to_string()));
+    let fields = table
+        .detected_fields(r#"{app="my-app1"}"#, Some(1), Some(2))
+        .await?;
+    assert_eq!(
+        fields,
+        vec![
+            DetectedField {
+                label: "level".to_string(),
+                r#type: "string".to_string(),
+                cardinality: 3,
+            },
+            DetectedField {
+                label: "duration".to_string(),
+                r#type: "duration".to_string(),
+                cardinality: 120,
+            },
+        ]
+    );
+
+    let requests = mock.requests_to("/loki/api/v1/detected_fields");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].query_param("query"), Some(r#"{app="my-app1"}"#));
+    assert_eq!(requests[0].query_param("start"), Some("1"));
+    assert_eq!(requests[0].query_param("end"), Some("2"));
+    assert_eq!(requests[0].header("Authorization"), Some("Bearer token"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn label_names() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| {
+        MockResponse::new(
+            200,
+            r#"{"status":"success","data":["app","env","namespace"]}"#,
+        )
+        .with_header("Content-Type", "application/json")
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_tenant("team-a");
+    assert_eq!(
+        table.label_names(Some(1), Some(2)).await?,
+        vec!["app", "env", "namespace"]
+    );
+    let requests = mock.requests_to("/loki/api/v1/labels");
+    assert_eq!(requests[0].query_param("start"), Some("1"));
+    assert_eq!(requests[0].query_param("end"), Some("2"));
+    assert_eq!(requests[0].query_param("query"), None);
+    assert_eq!(requests[0].header("X-Scope-OrgID"), Some("team-a"));
+
+    let table = table.with_mandatory_selector(r#"{namespace="prod"}"#);
+    table.label_names(None, None).await?;
+    let requests = mock.requests_to("/loki/api/v1/labels");
+    assert_eq!(
+        requests[1].query_param("query"),
+        Some(r#"{namespace="prod"}"#)
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn label_values() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| {
+        MockResponse::new(200, r#"{"status":"success","data":["my-app1","my-app2"]}"#)
+            .with_header("Content-Type", "application/json")
+    })
+    .await;
+
+    let table =
+        LokiLogTable::try_new(mock.endpoint())?.with_bearer_token(Some("token".to_string()));
+    assert_eq!(
+        table.label_values("app", Some(1), Some(2)).await?,
+        vec!["my-app1", "my-app2"]
+    );
+    let requests = mock.requests_to("/loki/api/v1/label/app/values");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].query_param("start"), Some("1"));
+    assert_eq!(requests[0].query_param("end"), Some("2"));
+    assert_eq!(requests[0].header("Authorization"), Some("Bearer token"));
+
+    assert!(table.label_values("../labels", None, None).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn positional_line_filters() -> Result<(), Box<dyn std::error::Error>> {
+    let batch = log_batch(&[
+        (1_000_000_000, &[("app", "my-app1")], "GET /index.html"),
+        (2_000_000_000, &[("app", "my-app1")], "POST /error"),
+        (3_000_000_000, &[("app", "my-app1")], "GET /error"),
+    ]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    for (sql, log_query, expected) in [
+        (
+            "select line from loki where position('error' in line) > 0",
+            r#"{app=~".+"} |= `error`"#,
+            vec!["POST /error", "GET /error"],
+        ),
+        (
+            "select line from loki where substr(line, 1, 4) = 'GET '",
+            r#"{app=~".+"} |~ `^GET `"#,
+            vec!["GET /index.html", "GET /error"],
+        ),
+        (
+            "select line from loki where substr(line, 1, 3) = 'P.S'",
+            r#"{app=~".+"} |~ `^P\.S`"#,
+            vec![],
+        ),
+    ] {
+        let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+        assert_eq!(find_scan_exec(&plan).unwrap().log_query, log_query);
+
+        // The mock ignores line filters, so rows are only correct if DataFusion re-verifies
+        let batches = collect(plan, ctx.task_ctx()).await?;
+        let lines = batches
+            .iter()
+            .flat_map(|b| {
+                b.column(0)
+                    .as_string::<i32>()
+                    .iter()
+                    .map(|v| v.unwrap().to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(lines, expected);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn timestamp_filter_boundaries() -> Result<(), Box<dyn std::error::Error>> {
+    // Emulates loki's end-exclusive `[start, end)` query range
+    let at = current_timestamp_ns() - 60 * 60 * 1_000_000_000;
+    let batch = log_batch(&[
+        (at - 1, &[("app", "my-app1")], "before"),
+        (at, &[("app", "my-app1")], "at"),
+        (at + 1, &[("app", "my-app1")], "after"),
+    ]);
+    let mock = MockLoki::start(move |req| {
+        let start = req.query_param("start").unwrap().parse::<i64>().unwrap();
+        let end = req.query_param("end").unwrap().parse::<i64>().unwrap();
+        let timestamps = batch.column(0).as_primitive::<TimestampNanosecondType>();
+        let mask = timestamps
+            .iter()
+            .map(|ts| ts.map(|ts| start <= ts && ts < end))
+            .collect::<BooleanArray>();
+        MockResponse::parquet(&[filter_record_batch(&batch, &mask).unwrap()])
+    })
+    .await;
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let at = format!("to_timestamp_nanos({at})");
+    for (predicate, expected) in [
+        (format!("timestamp < {at}"), vec!["before"]),
+        (format!("timestamp <= {at}"), vec!["before", "at"]),
+        (format!("timestamp > {at}"), vec!["after"]),
+        (format!("timestamp >= {at}"), vec!["at", "after"]),
+        (format!("{at} > timestamp"), vec!["before"]),
+        (format!("{at} >= timestamp"), vec!["before", "at"]),
+    ] {
+        let sql = format!("select line from loki where {predicate}");
+        let batches = ctx.sql(&sql).await?.collect().await?;
+        let lines = batches
+            .iter()
+            .flat_map(|b| {
+                b.column(0)
+                    .as_string::<i32>()
+                    .iter()
+                    .map(|v| v.unwrap().to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(lines, expected, "{predicate}");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn timestamp_between_filters() -> Result<(), Box<dyn std::error::Error>> {
+    let table =
+        LokiLogTable::try_new("http://localhost:3100")?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table.clone()))?;
+
+    let ts = |v: i64| {
+        lit(ScalarValue::TimestampNanosecond(
+            Some(v),
+            Some("UTC".into()),
+        ))
+    };
+    let between = col("timestamp").between(ts(1_000), ts(2_000));
+    let exec = table.scan(&ctx.state(), None, &[between], None).await?;
+    let exec = exec.as_any().downcast_ref::<LokiLogScanExec>().unwrap();
+    assert_eq!((exec.start, exec.end), (Some(1_000), Some(2_001)));
+
+    let not_between = col("timestamp").not_between(ts(1_000), ts(2_000));
+    assert_eq!(
+        table.supports_filters_pushdown(&[&not_between])?,
+        vec![TableProviderFilterPushDown::Unsupported]
+    );
+
+    let exec = plan_scan_exec(
+        &ctx,
+        "select * from loki where timestamp between to_timestamp_nanos(1000) and to_timestamp_nanos(2000)",
+    )
+    .await?;
+    assert_eq!((exec.start, exec.end), (Some(1_000), Some(2_001)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn timestamp_filters_with_time_zones() -> Result<(), Box<dyn std::error::Error>> {
+    let table =
+        LokiLogTable::try_new("http://localhost:3100")?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table.clone()))?;
+
+    // 2025-01-01T00:00:00Z
+    let midnight = 1_735_689_600_000_000_000;
+    for predicate in [
+        "timestamp >= '2025-01-01T00:00:00Z'",
+        "timestamp >= '2025-01-01T02:00:00+02:00'",
+        "timestamp >= '2024-12-31T19:00:00-05:00'",
+        "timestamp >= arrow_cast('2025-01-01T08:00:00+08:00', 'Timestamp(Nanosecond, Some(\"+08:00\"))')",
+        "timestamp >= arrow_cast('2025-01-01T01:00:00+01:00', 'Timestamp(Millisecond, Some(\"Europe/Paris\"))')",
+    ] {
+        let exec = plan_scan_exec(&ctx, &format!("select * from loki where {predicate}")).await?;
+        assert_eq!(exec.start, Some(midnight), "{predicate}");
+    }
+
+    // Timestamp values are UTC whatever their time zone, which only affects display
+    let ts = |v: i64, tz: &str| lit(ScalarValue::TimestampNanosecond(Some(v), Some(tz.into())));
+    let filters = [
+        col("timestamp").gt(ts(midnight, "+02:00")),
+        col("timestamp").lt(ts(midnight + 1_000, "America/New_York")),
+    ];
+    let exec = table.scan(&ctx.state(), None, &filters, None).await?;
+    let exec = exec.as_any().downcast_ref::<LokiLogScanExec>().unwrap();
+    assert_eq!(
+        (exec.start, exec.end),
+        (Some(midnight + 1), Some(midnight + 1_000))
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn timestamp_between_intersects_other_bounds() -> Result<(), Box<dyn std::error::Error>> {
+    let table =
+        LokiLogTable::try_new("http://localhost:3100")?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+
+    let ts = |v: Option<i64>| lit(ScalarValue::TimestampNanosecond(v, Some("UTC".into())));
+    let between = col("timestamp").between(ts(Some(1_000)), ts(Some(2_000)));
+    let null_between = col("timestamp").between(ts(None), ts(Some(2_000)));
+    for (filters, expected) in [
+        (
+            vec![between.clone(), col("timestamp").lt(ts(Some(1_500)))],
+            (Some(1_000), Some(1_500)),
+        ),
+        (
+            vec![col("timestamp").lt(ts(Some(1_500))), between.clone()],
+            (Some(1_000), Some(1_500)),
+        ),
+        (
+            vec![between.clone(), col("timestamp").gt(ts(Some(1_500)))],
+            (Some(1_501), Some(2_001)),
+        ),
+        (
+            vec![col("timestamp").gt(ts(Some(1_500))), between.clone()],
+            (Some(1_501), Some(2_001)),
+        ),
+        (
+            vec![col("timestamp").lt(ts(Some(3_000))), between.clone()],
+            (Some(1_000), Some(2_001)),
+        ),
+        (
+            vec![col("timestamp").gt_eq(ts(Some(500))), null_between],
+            (Some(500), Some(2_001)),
+        ),
+    ] {
+        let exec = table.scan(&ctx.state(), None, &filters, None).await?;
+        let exec = exec.as_any().downcast_ref::<LokiLogScanExec>().unwrap();
+        assert_eq!((exec.start, exec.end), expected, "{filters:?}");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn table_time_bounds() -> Result<(), Box<dyn std::error::Error>> {
+    let table = LokiLogTable::try_new("http://localhost:3100")?
+        .with_default_label(Some("app".to_string()))
+        .with_time_bounds(Some(1_000), Some(2_000));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let exec = plan_scan_exec(&ctx, "select * from loki").await?;
+    assert_eq!((exec.start, exec.end), (Some(1_000), Some(2_000)));
+
+    // Timestamp filters override each bound on their own
+    let exec = plan_scan_exec(
+        &ctx,
+        "select * from loki where timestamp >= to_timestamp_nanos(1500)",
+    )
+    .await?;
+    assert_eq!((exec.start, exec.end), (Some(1_500), Some(2_000)));
+    let exec = plan_scan_exec(
+        &ctx,
+        "select * from loki where timestamp < to_timestamp_nanos(500) and timestamp >= to_timestamp_nanos(100)",
+    )
+    .await?;
+    assert_eq!((exec.start, exec.end), (Some(100), Some(500)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn inverted_time_range() -> Result<(), Box<dyn std::error::Error>> {
+    let table =
+        LokiLogTable::try_new("http://localhost:3100")?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table.clone()))?;
+    ctx.register_table(
+        "loki_bounded",
+        Arc::new(table.with_time_bounds(None, Some(1_735_689_600_000_000_000))),
+    )?;
+
+    for (sql, error) in [
+        (
+            "select * from loki where timestamp > '2025-01-02T00:00:00Z' and timestamp < '2025-01-01T00:00:00Z'",
+            "Start bound 1735776000000000001 (2025-01-02T00:00:00.000000001+00:00) is after end bound 1735689600000000000 (2025-01-01T00:00:00+00:00)",
+        ),
+        (
+            "select * from loki_bounded where timestamp >= '2025-01-02T00:00:00Z'",
+            "Start bound 1735776000000000000 (2025-01-02T00:00:00+00:00) is after end bound 1735689600000000000 (2025-01-01T00:00:00+00:00)",
+        ),
+    ] {
+        let err = ctx
+            .sql(sql)
+            .await?
+            .create_physical_plan()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains(error), "{sql}: {err}");
+    }
+
+    // Empty but not inverted
+    let exec = plan_scan_exec(
+        &ctx,
+        "select * from loki where timestamp >= '2025-01-01T00:00:00Z' and timestamp < '2025-01-01T00:00:00Z'",
+    )
+    .await?;
+    assert_eq!(exec.start, exec.end);
+    Ok(())
+}
+
+#[tokio::test]
+async fn table_default_lookback() -> Result<(), Box<dyn std::error::Error>> {
+    let table =
+        LokiLogTable::try_new("http://localhost:3100")?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table.clone()))?;
+    ctx.register_table(
+        "loki_hour",
+        Arc::new(table.with_default_lookback(Duration::from_secs(60 * 60))),
+    )?;
+
+    let hour = 60 * 60 * 1_000_000_000;
+    for (sql, lookback) in [
+        ("select * from loki", 30 * 24 * hour),
+        ("select * from loki_hour", hour),
+    ] {
+        let before = current_timestamp_ns();
+        let exec = plan_scan_exec(&ctx, sql).await?;
+        let after = current_timestamp_ns();
+        let (start, end) = (exec.start.unwrap(), exec.end.unwrap());
+        assert!(before - 1_000_000_000 <= end && end <= after, "{sql}");
+        assert_eq!(end - start, lookback, "{sql}");
+    }
+
+    let exec = plan_scan_exec(
+        &ctx,
+        "select * from loki_hour where timestamp < to_timestamp_nanos(5000)",
+    )
+    .await?;
+    assert_eq!((exec.start, exec.end), (Some(5000 - hour), Some(5000)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_streams_groups_logs() -> Result<(), Box<dyn std::error::Error>> {
+    let now = current_timestamp_ns();
+    let batch = log_batch(&[
+        (
+            now - 1_000,
+            &[("app", "my-app1"), ("env", "prod")],
+            "this is ccc log",
+        ),
+        (now - 2_000, &[("app", "my-app2")], "this is bbb log"),
+        (
+            now - 3_000,
+            &[("env", "prod"), ("app", "my-app1")],
+            "this is aaa log",
+        ),
+    ]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_target_partitions(1);
+    let ctx = SessionContext::new();
+
+    let batch = table.scan_streams(&ctx.state(), &[]).await?;
+    assert_eq!(batch.schema(), LOG_STREAMS_SCHEMA.clone());
+    assert_eq!(
+        pretty_format_batches(&[batch])?.to_string(),
+        r#"+---------------------------+------------------------------------+-------+
+| labels                    | lines                              | count |
++---------------------------+------------------------------------+-------+
+| {app: my-app1, env: prod} | [this is ccc log, this is aaa log] | 2     |
+| {app: my-app2}            | [this is bbb log]                  | 1     |
++---------------------------+------------------------------------+-------+"#
+    );
+
+    let filters = [get_field(col("labels"), "app").eq(lit("my-app2"))];
+    let batch = table.scan_streams(&ctx.state(), &filters).await?;
+    let requests = mock.requests_to("/loki/api/v1/query_range");
+    assert_eq!(
+        requests.last().unwrap().query_param("query"),
+        Some(r#"{app="my-app2"} "#)
+    );
+    assert_eq!(batch.num_rows(), 2);
+
+    // Fields are found by name in a reordered schema
+    let schema = Arc::new(Schema::new(vec![
+        LINE_FIELD_REF.clone(),
+        TIMESTAMP_FIELD_REF.clone(),
+        LABELS_FIELD_REF.clone(),
+    ]));
+    let batch = table
+        .with_schema(schema)
+        .scan_streams(&ctx.state(), &[])
+        .await?;
+    assert_eq!(batch.num_rows(), 2);
+    assert_eq!(
+        batch
+            .column(1)
+            .as_list::<i32>()
+            .value(0)
+            .as_string::<i32>()
+            .value(0),
+        "this is ccc log"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn map_contains_key_label_filters() -> Result<(), Box<dyn std::error::Error>> {
+    let now = current_timestamp_ns();
+    let batch = log_batch(&[
+        (
+            now - 2_000,
+            &[("app", "my-app1"), ("env", "prod")],
+            "with env",
+        ),
+        (now - 1_000, &[("app", "my-app1")], "without env"),
+    ]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+    let table = LokiLogTable::try_new(mock.endpoint())?;
+    let ctx = SessionContext::new();
+    ctx.register_udf(ScalarUDF::from(MapContainsKey::new()));
+    ctx.register_table("loki", Arc::new(table))?;
+
+    for (sql, log_query) in [
+        (
+            "select line from loki where map_contains_key(labels, 'app')",
+            r#"{app=~".+"} "#,
+        ),
+        (
+            "select line from loki where labels['app'] = 'my-app1' and not map_contains_key(labels, 'env')",
+            r#"{app="my-app1", env=""} "#,
+        ),
+        (
+            "select line from loki where labels['app'] = 'my-app1' and map_contains_key(labels, 'env') = false",
+            r#"{app="my-app1", env=""} "#,
+        ),
+    ] {
+        let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+        assert_eq!(find_scan_exec(&plan).unwrap().log_query, log_query, "{sql}");
+        assert!(
+            !displayable(plan.as_ref())
+                .indent(true)
+                .to_string()
+                .contains("FilterExec"),
+            "{sql}"
+        );
+    }
+
+    // Evaluated on any map
+    let batches = ctx
+        .sql("select map_contains_key(labels, 'env') as has_env, line from loki where map_contains_key(labels, 'app') order by line")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+---------+-------------+
+| has_env | line        |
++---------+-------------+
+| true    | with env    |
+| false   | without env |
++---------+-------------+"#
+    );
+    let batches = ctx
+        .sql("select map_contains_key(map {'a': 1}, 'a') as a, map_contains_key(map {'a': 1}, 'b') as b")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+------+-------+
+| a    | b     |
++------+-------+
+| true | false |
++------+-------+"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn in_list_label_filters() -> Result<(), Box<dyn std::error::Error>> {
+    let table = LokiLogTable::try_new("http://localhost:3100")?;
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    for (sql, log_query) in [
+        (
+            "select line from loki where labels['app'] in ('api', 'web.v2', 'worker')",
+            r#"{app=~"api|web\\.v2|worker"} "#,
+        ),
+        (
+            "select line from loki where labels['app'] = 'api' and labels['env'] not in ('dev', 'test')",
+            r#"{app="api", env!~"dev|test"} "#,
+        ),
+    ] {
+        let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+        assert_eq!(find_scan_exec(&plan).unwrap().log_query, log_query, "{sql}");
+        assert!(
+            !displayable(plan.as_ref())
+                .indent(true)
+                .to_string()
+                .contains("FilterExec"),
+            "{sql}"
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn merged_negative_label_filters() -> Result<(), Box<dyn std::error::Error>> {
+    let table = LokiLogTable::try_new("http://localhost:3100")?;
+    let ctx = SessionContext::new();
+    ctx.register_udf(ScalarUDF::from(MapGet::new()));
+    ctx.register_table("loki", Arc::new(table))?;
+
+    for (sql, log_query) in [
+        (
+            "select line from loki where map_get(labels, 'env') != 'dev' and map_get(labels, 'env') != 'test.v2'",
+            r#"{env!~"dev|test\\.v2"} "#,
+        ),
+        (
+            "select line from loki where labels['env'] != 'dev' and labels['app'] = 'api' and labels['env'] != 'test' and labels['env'] != 'dev'",
+            r#"{env!~"dev|test", app="api"} "#,
+        ),
+        (
+            "select line from loki where labels['app'] = 'api' and map_get(labels, 'env') != 'dev'",
+            r#"{app="api", env!="dev"} "#,
+        ),
+    ] {
+        let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+        assert_eq!(find_scan_exec(&plan).unwrap().log_query, log_query, "{sql}");
+        assert!(
+            !displayable(plan.as_ref())
+                .indent(true)
+                .to_string()
+                .contains("FilterExec"),
+            "{sql}"
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn index_and_map_get_label_filters() -> Result<(), Box<dyn std::error::Error>> {
+    let table = LokiLogTable::try_new("http://localhost:3100")?;
+    let ctx = SessionContext::new();
+    ctx.register_udf(ScalarUDF::from(MapGet::new()));
+    ctx.register_table("loki", Arc::new(table))?;
+
+    for (predicate, log_query) in [
+        ("{app} = 'api'", r#"{app="api"} "#),
+        ("{app} != 'api'", r#"{app!="api"} "#),
+        ("{app} ~ 'a.+i'", r#"{app=~"a.+i"} "#),
+        ("{app} !~ 'a.+i'", r#"{app!~"a.+i"} "#),
+        ("{app} in ('api', 'web')", r#"{app=~"api|web"} "#),
+        ("{app} not in ('api', 'web')", r#"{app!~"api|web"} "#),
+    ] {
+        for entry in ["labels['app']", "map_get(labels, 'app')"] {
+            let sql = format!(
+                "select line from loki where {}",
+                predicate.replace("{app}", entry)
+            );
+            let plan = ctx.sql(&sql).await?.create_physical_plan().await?;
+            assert_eq!(find_scan_exec(&plan).unwrap().log_query, log_query, "{sql}");
+            assert!(
+                !displayable(plan.as_ref())
+                    .indent(true)
+                    .to_string()
+                    .contains("FilterExec"),
+                "{sql}"
+            );
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn mandatory_selector() -> Result<(), Box<dyn std::error::Error>> {
+    let table = LokiLogTable::try_new("http://localhost:3100")?
+        .with_mandatory_selector(r#"{cluster="prod"}"#);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table.clone()))?;
+
+    for (sql, log_query) in [
+        ("select line from loki", r#"{cluster="prod"} "#),
+        (
+            "select line from loki where labels['app'] = 'api' and line like '%error%'",
+            r#"{cluster="prod", app="api"} |= `error`"#,
+        ),
+        (
+            "select line from loki where labels['cluster'] != 'prod'",
+            r#"{cluster="prod", cluster!="prod"} "#,
+        ),
+        (
+            r#"select line from loki where labels['app'] = 'api"} or {cluster=~".*'"#,
+            r#"{cluster="prod", app="api\"} or {cluster=~\".*"} "#,
+        ),
+    ] {
+        let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+        assert_eq!(find_scan_exec(&plan).unwrap().log_query, log_query, "{sql}");
+    }
+
+    let exec = table.tail(r#"{app="api"} |= "error""#)?;
+    assert_eq!(exec.log_query, r#"{cluster="prod", app="api"} |= "error""#);
+    assert!(table.tail("rate({app=\"api\"}[1m])").is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn coalesced_label_filters() -> Result<(), Box<dyn std::error::Error>> {
+    let table =
+        LokiLogTable::try_new("http://localhost:3100")?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    for (sql, log_query, pushed_down) in [
+        (
+            "select line from loki where coalesce(labels['env'], 'none') = 'prod'",
+            r#"{env="prod"} "#,
+            true,
+        ),
+        (
+            "select line from loki where coalesce(labels['env'], 'none') != 'prod'",
+            r#"{env!="prod"} "#,
+            true,
+        ),
+        // Logs without the label match, which no loki matcher expresses
+        (
+            "select line from loki where coalesce(labels['env'], 'none') = 'none'",
+            r#"{app=~".+"} "#,
+            false,
+        ),
+        (
+            "select line from loki where coalesce(labels['env'], 'none') like 'pr%'",
+            r#"{app=~".+"} "#,
+            false,
+        ),
+    ] {
+        let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+        assert_eq!(find_scan_exec(&plan).unwrap().log_query, log_query, "{sql}");
+        assert_eq!(
+            !displayable(plan.as_ref())
+                .indent(true)
+                .to_string()
+                .contains("FilterExec"),
+            pushed_down,
+            "{sql}"
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn duplicate_label_filters() -> Result<(), Box<dyn std::error::Error>> {
+    let table = LokiLogTable::try_new("http://localhost:3100")?;
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table.clone()))?;
+
+    let app = get_field(col("labels"), "app");
+    let filters = [
+        app.clone().eq(lit("api")),
+        get_field(col("labels"), "env").eq(lit("prod")),
+        app.clone().eq(lit("api")),
+    ];
+    let exec = table.scan(&ctx.state(), None, &filters, None).await?;
+    let exec = exec.as_any().downcast_ref::<LokiLogScanExec>().unwrap();
+    assert_eq!(exec.log_query, r#"{app="api", env="prod"} "#);
+
+    for (sql, error) in [
+        (
+            "select line from loki where labels['app'] = 'api' and labels['app'] = 'web'",
+            r#"Conflicting label filters app="api" and app="web""#,
+        ),
+        (
+            "select line from loki where labels['app'] != 'api' and labels['app'] = 'api'",
+            r#"Conflicting label filters app="api" and app!="api""#,
+        ),
+    ] {
+        let err = ctx
+            .sql(sql)
+            .await?
+            .create_physical_plan()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains(error), "{sql}: {err}");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn backticks_in_line_filters() -> Result<(), Box<dyn std::error::Error>> {
+    let table =
+        LokiLogTable::try_new("http://localhost:3100")?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    for (sql, log_query) in [
+        (
+            "select line from loki where line like '%foo`bar%'",
+            r#"{app=~".+"} |= "foo`bar""#,
+        ),
+        (
+            r#"select line from loki where contains(line, 'say "`hi`"')"#,
+            r#"{app=~".+"} |= "say \"`hi`\"""#,
+        ),
+        (
+            r"select line from loki where line ~ 'a`b\d'",
+            r#"{app=~".+"} |~"a`b\\d""#,
+        ),
+        (
+            "select line from loki where line !~ 'a`b+'",
+            r#"{app=~".+"} !~"a`b+""#,
+        ),
+        (
+            "select line from loki where position('a`b' in line) > 0",
+            r#"{app=~".+"} |= "a`b""#,
+        ),
+        (
+            "select line from loki where substr(line, 1, 3) = 'a`.'",
+            r#"{app=~".+"} |~ "^a`\\.""#,
+        ),
+    ] {
+        let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+        assert_eq!(find_scan_exec(&plan).unwrap().log_query, log_query, "{sql}");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn case_insensitive_line_filters() -> Result<(), Box<dyn std::error::Error>> {
+    let table =
+        LokiLogTable::try_new("http://localhost:3100")?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    for (sql, log_query) in [
+        (
+            "select line from loki where line ilike '%Time.out%'",
+            r#"{app=~".+"} |~ `(?i)Time\.out`"#,
+        ),
+        (
+            "select line from loki where line not ilike '%debug%'",
+            r#"{app=~".+"} !~ `(?i)debug`"#,
+        ),
+        (
+            "select line from loki where line ~~* '%error%' and line !~~ '%retry%'",
+            r#"{app=~".+"} |~ `(?i)error` != `retry`"#,
+        ),
+        (
+            "select line from loki where line ilike '%a`b%'",
+            r#"{app=~".+"} |~ "(?i)a`b""#,
+        ),
+        (
+            "select line from loki where line like '%a`b.c%'",
+            r#"{app=~".+"} |= "a`b.c""#,
+        ),
+    ] {
+        let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+        assert_eq!(find_scan_exec(&plan).unwrap().log_query, log_query, "{sql}");
+        assert!(
+            !displayable(plan.as_ref())
+                .indent(true)
+                .to_string()
+                .contains("FilterExec"),
+            "{sql}"
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn string_function_line_filters() -> Result<(), Box<dyn std::error::Error>> {
+    let batch = log_batch(&[
+        (1_000_000_000, &[("app", "my-app1")], "GET /index.html"),
+        (2_000_000_000, &[("app", "my-app1")], "POST /error timeout"),
+        (3_000_000_000, &[("app", "my-app1")], "GET /error"),
+    ]);
+    // Applies the pushed down line filters like loki
+    let mock = MockLoki::start(move |req| {
+        let query = req.query_param("query").unwrap();
+        let lines = batch.column(2).as_string::<i32>();
+        let mask = lines
+            .iter()
+            .map(|line| {
+                let line = line.unwrap();
+                Some(if let Some((_, term)) = query.split_once("|= `") {
+                    line.contains(term.trim_end_matches('`'))
+                } else if let Some((_, term)) = query.split_once("!= `") {
+                    !line.contains(term.trim_end_matches('`'))
+                } else if query.ends_with("|~ `^GET `") {
+                    line.starts_with("GET ")
+                } else if query.ends_with("|~ `/error$`") {
+                    line.ends_with("/error")
+                } else {
+                    true
+                })
+            })
+            .collect::<BooleanArray>();
+        MockResponse::parquet(&[filter_record_batch(&batch, &mask).unwrap()])
+    })
+    .await;
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    for (sql, log_query, expected) in [
+        (
+            "select line from loki where contains(line, 'timeout')",
+            r#"{app=~".+"} |= `timeout`"#,
+            vec!["POST /error timeout"],
+        ),
+        (
+            "select line from loki where not contains(line, 'timeout')",
+            r#"{app=~".+"} != `timeout`"#,
+            vec!["GET /index.html", "GET /error"],
+        ),
+        (
+            "select line from loki where starts_with(line, 'GET ')",
+            r#"{app=~".+"} |~ `^GET `"#,
+            vec!["GET /index.html", "GET /error"],
+        ),
+        (
+            "select line from loki where ends_with(line, '/error')",
+            r#"{app=~".+"} |~ `/error$`"#,
+            vec!["GET /error"],
+        ),
+        (
+            "select line from loki where ends_with(line, '.html')",
+            r#"{app=~".+"} |~ `\.html$`"#,
+            vec!["GET /index.html", "POST /error timeout", "GET /error"],
+        ),
+    ] {
+        let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+        assert_eq!(find_scan_exec(&plan).unwrap().log_query, log_query, "{sql}");
+        let batches = collect(plan, ctx.task_ctx()).await?;
+        let lines = batches
+            .iter()
+            .flat_map(|b| {
+                b.column(0)
+                    .as_string::<i32>()
+                    .iter()
+                    .map(|v| v.unwrap().to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(lines, expected, "{sql}");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn count_pushed_down_as_metric_query() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|req| {
+        let body = if req.query_param("query").unwrap().starts_with("sum by") {
+            r#"{"status":"success","data":{"resultType":"matrix","result":[{"metric":{"app":"api"},"values":[[1735693199.999,"3"]]},{"metric":{},"values":[[1735693199.999,"1"]]},{"metric":{"app":"web"},"values":[[1735693199.999,"2"]]}]}}"#
+        } else {
+            r#"{"status":"success","data":{"resultType":"matrix","result":[]}}"#
+        };
+        MockResponse::new(200, body).with_header("Content-Type", "application/json")
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_default_label(Some("env".to_string()));
+    let state = SessionStateBuilder::new()
+        .with_default_features()
+        .with_config(SessionConfig::new().with_target_partitions(4))
+        .with_physical_optimizer_rule(Arc::new(LokiMetricPushdown::new()))
+        .build();
+    let ctx = SessionContext::new_with_state(state);
+    ctx.register_udf(ScalarUDF::from(MapGet::new()));
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let sql = "select map_get(labels, 'app') as app, count(*) as logs from loki where labels['env'] = 'prod' and line like '%error%' and timestamp >= '2025-01-01T00:00:00Z' and timestamp < '2025-01-01T01:00:00Z' group by app order by app";
+    let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+    let plan = roundtrip_plan(plan, &ctx)?;
+    let display = displayable(plan.as_ref()).indent(true).to_string();
+    assert!(display.contains("LokiMetricScanExec"), "{display}");
+    assert!(find_scan_exec(&plan).is_none(), "{display}");
+    let batches = collect(plan, ctx.task_ctx()).await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-----+------+
+| app | logs |
++-----+------+
+| api | 3    |
+| web | 2    |
+|     | 1    |
++-----+------+"#
+    );
+
+    let requests = mock.requests_to("/loki/api/v1/query_range");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(
+        requests[0].query_param("query"),
+        Some(r#"sum by (app) (count_over_time({env="prod"} |= `error` [3600000000000ns]))"#)
+    );
+    assert_eq!(
+        requests[0].query_param("start"),
+        Some("1735693199999999999")
+    );
+    assert_eq!(requests[0].query_param("end"), Some("1735693199999999999"));
+
+    // An ungrouped count of no logs is still a row
+    let batches = ctx
+        .sql("select count(*) from loki where labels['env'] = 'prod'")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+----------+
+| count(*) |
++----------+
+| 0        |
++----------+"#
+    );
+    let requests = mock.requests_to("/loki/api/v1/query_range");
+    assert!(
+        requests[1]
+            .query_param("query")
+            .unwrap()
+            .starts_with(r#"sum(count_over_time({env="prod"} ["#)
+    );
+
+    // Filters loki can't evaluate keep the logs scanned
+    let plan = ctx
+        .sql("select count(*) from loki where labels['env'] = 'prod' and length(line) > 3")
+        .await?
+        .create_physical_plan()
+        .await?;
+    assert!(find_scan_exec(&plan).is_some());
+    assert!(
+        !displayable(plan.as_ref())
+            .indent(true)
+            .to_string()
+            .contains("LokiMetricScanExec")
+    );
+    Ok(())
+}
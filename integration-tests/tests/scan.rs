@@ -0,0 +1,2276 @@
+use std::{collections::HashMap, io::Write, sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use datafusion::{
+    arrow::{
+        array::{
+            ArrayRef, AsArray, BooleanArray, DurationNanosecondArray, LargeStringBuilder,
+            MapBuilder, RecordBatch, StringDictionaryBuilder,
+        },
+        compute::{cast, concat_batches, filter_record_batch},
+        datatypes::{DataType, Field, Int32Type, Schema, TimeUnit, TimestampNanosecondType},
+        util::pretty::pretty_format_batches,
+    },
+    common::{ScalarValue, stats::Precision},
+    error::DataFusionError,
+    execution::SessionStateBuilder,
+    logical_expr::ScalarUDF,
+    parquet::{
+        arrow::ArrowWriter, file::metadata::ParquetMetaDataReader,
+        file::properties::WriterProperties,
+    },
+    physical_plan::{
+        ExecutionPlan, collect, displayable,
+        execution_plan::{Boundedness, EmissionType},
+    },
+    prelude::{SessionConfig, SessionContext},
+};
+use datafusion_loki::{
+    DICTIONARY_LABELS_FIELD_REF, JsonGet, LABELS_FIELD_REF, LINE_FIELD_REF, LOG_TABLE_SCHEMA,
+    LogfmtGet, LokiDirection, LokiExistsLimit, LokiLogScanExec, LokiLogTable, LokiQueryStats,
+    LokiResponseMetadata, MapGet, PartitionErrorPolicy, SORTED_KEYS_METADATA_KEY,
+    STRUCTURED_METADATA_FIELD_REF, TIMESTAMP_FIELD_REF, current_timestamp_ns, has_sorted_keys,
+};
+use flate2::{Compression, write::GzEncoder};
+use futures::StreamExt;
+use integration_tests::{
+    MockLoki, MockRequest, MockResponse, find_scan_exec, labels_array, log_batch, parquet_bytes,
+    plan_scan_exec, roundtrip_plan,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+#[tokio::test]
+async fn scan_into_custom_schema() -> Result<(), Box<dyn std::error::Error>> {
+    let batch = log_batch(&[
+        (1_000_000_000, &[("app", "my-app1")], "this is aaa log"),
+        (2_000_000_000, &[("app", "my-app2")], "this is bbb log"),
+    ]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new(
+            "ts",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        LABELS_FIELD_REF.as_ref().clone(),
+        Field::new("message", DataType::LargeUtf8, false),
+    ]));
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_schema(schema.clone());
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let batches = ctx.sql("select * from loki").await?.collect().await?;
+    for batch in &batches {
+        assert_eq!(batch.schema(), schema);
+    }
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+----------------------+----------------+-----------------+
+| ts                   | labels         | message         |
++----------------------+----------------+-----------------+
+| 1970-01-01T00:00:01Z | {app: my-app1} | this is aaa log |
+| 1970-01-01T00:00:02Z | {app: my-app2} | this is bbb log |
++----------------------+----------------+-----------------+"#
+    );
+
+    let batches = ctx.sql("select message from loki").await?.collect().await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-----------------+
+| message         |
++-----------------+
+| this is aaa log |
+| this is bbb log |
++-----------------+"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_into_incompatible_schema() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::ok()).await;
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("timestamp", DataType::Boolean, false),
+        LABELS_FIELD_REF.as_ref().clone(),
+        Field::new("line", DataType::Utf8, false),
+    ]));
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_schema(schema);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let err = ctx
+        .sql("select * from loki")
+        .await?
+        .collect()
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Cannot cast loki column timestamp of type"),
+        "{err}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_sends_query_tags() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::parquet(&[log_batch(&[])])).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_query_tags(HashMap::from([
+            ("user".to_string(), "alice".to_string()),
+            ("source".to_string(), "grafana".to_string()),
+        ]));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+    ctx.sql("select * from loki").await?.collect().await?;
+
+    let requests = mock.requests_to("/loki/api/v1/query_range");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(
+        requests[0].header("X-Query-Tags"),
+        Some("source=grafana,user=alice")
+    );
+
+    // Separators in tags can't smuggle in other tags
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_query_tags(HashMap::from([(
+            "user".to_string(),
+            "alice,source=admin".to_string(),
+        )]));
+    ctx.register_table("tagged", Arc::new(table))?;
+    ctx.sql("select * from tagged").await?.collect().await?;
+
+    let requests = mock.requests_to("/loki/api/v1/query_range");
+    assert_eq!(requests.len(), 2);
+    assert_eq!(
+        requests[1].header("X-Query-Tags"),
+        Some("user=alice_source_admin")
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn limited_scan_fetches_single_row() -> Result<(), Box<dyn std::error::Error>> {
+    // Server ignoring the limit still only yields a single row
+    let batch = log_batch(&[
+        (1_000_000_000, &[("app", "my-app1")], "this is aaa log"),
+        (2_000_000_000, &[("app", "my-app1")], "this is bbb log"),
+        (3_000_000_000, &[("app", "my-app1")], "this is ccc log"),
+    ]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?;
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let batches = ctx
+        .sql("select count(*) > 0 as found from (select 1 from loki where labels['app'] = 'my-app1' limit 1)")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-------+
+| found |
++-------+
+| true  |
++-------+"#
+    );
+
+    let exec = LokiLogScanExec::try_new(
+        mock.endpoint(),
+        r#"{app="my-app1"}"#.to_string(),
+        None,
+        None,
+        None,
+        Some(1),
+    )?;
+    let batches = collect(Arc::new(exec), ctx.task_ctx()).await?;
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
+
+    let requests = mock.requests_to("/loki/api/v1/query_range");
+    assert_eq!(requests.len(), 2);
+    for request in requests {
+        assert_eq!(request.query_param("limit"), Some("1"));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_age_column() -> Result<(), Box<dyn std::error::Error>> {
+    let one_minute_ago = current_timestamp_ns() - 60 * 1_000_000_000;
+    let batch = log_batch(&[(one_minute_ago, &[("app", "my-app1")], "this is aaa log")]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_age_column(true);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let batches = ctx
+        .sql("select age from loki where age < interval '15 minutes'")
+        .await?
+        .collect()
+        .await?;
+    let ages = batches[0]
+        .column(0)
+        .as_any()
+        .downcast_ref::<DurationNanosecondArray>()
+        .unwrap();
+    assert_eq!(ages.len(), 1);
+    let age = ages.value(0);
+    assert!((60 * 1_000_000_000..120 * 1_000_000_000).contains(&age));
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_source_column() -> Result<(), Box<dyn std::error::Error>> {
+    let now = current_timestamp_ns();
+    let batch = log_batch(&[(
+        now - 1_000_000_000,
+        &[("app", "my-app1")],
+        "this is aaa log",
+    )]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_source_column("source", "loki")
+        .with_target_partitions(1);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let exec = plan_scan_exec(&ctx, "select line, source from loki").await?;
+    let exec = roundtrip_plan(Arc::new(exec), &ctx)?;
+    let batches = collect(exec, ctx.task_ctx()).await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-----------------+--------+
+| line            | source |
++-----------------+--------+
+| this is aaa log | loki   |
++-----------------+--------+"#
+    );
+
+    for (sql, rows) in [
+        ("select line from loki where source = 'loki'", 1),
+        ("select line from loki where source = 'other'", 0),
+    ] {
+        let batches = ctx.sql(sql).await?.collect().await?;
+        assert_eq!(
+            batches.iter().map(|b| b.num_rows()).sum::<usize>(),
+            rows,
+            "{sql}"
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_captures_query_id() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| {
+        MockResponse::parquet(&[log_batch(&[])]).with_header("X-Query-Id", "query-1234")
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let exec = plan_scan_exec(&ctx, "select * from loki").await?;
+    assert!(exec.response_metadata().is_empty());
+    collect(Arc::new(exec.clone()), ctx.task_ctx()).await?;
+    assert_eq!(
+        exec.response_metadata(),
+        vec![LokiResponseMetadata {
+            query_id: Some("query-1234".to_string()),
+            stats: None,
+        }]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_reports_query_stats() -> Result<(), Box<dyn std::error::Error>> {
+    // Abridged stats block of a loki response
+    let stats = r#"{
+        "summary": {
+            "bytesProcessedPerSecond": 4096,
+            "linesProcessedPerSecond": 100,
+            "totalBytesProcessed": 2048,
+            "totalLinesProcessed": 50,
+            "execTime": 0.5,
+            "queueTime": 0.01,
+            "subqueries": 0,
+            "totalEntriesReturned": 1,
+            "splits": 1,
+            "shards": 1
+        },
+        "querier": {"store": {"totalChunksRef": 2}},
+        "ingester": {"totalReached": 1}
+    }"#;
+    let page = log_batch(&[(1_000_000_000, &[("app", "my-app1")], "this is aaa log")]);
+    let mock = MockLoki::start(move |req| {
+        let next = match req.query_param("cursor") {
+            None => "page-2",
+            Some(_) => "",
+        };
+        MockResponse::parquet_with_metadata(
+            std::slice::from_ref(&page),
+            &[("next", next), ("stats", stats)],
+        )
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let exec = plan_scan_exec(&ctx, "select * from loki").await?;
+    collect(Arc::new(exec.clone()), ctx.task_ctx()).await?;
+    let page_stats = LokiQueryStats {
+        total_entries_returned: 1,
+        total_bytes_processed: 2048,
+        exec_time: Duration::from_millis(500),
+    };
+    assert_eq!(
+        exec.response_metadata()
+            .iter()
+            .map(|m| m.stats)
+            .collect::<Vec<_>>(),
+        vec![Some(page_stats); 2]
+    );
+    assert_eq!(
+        exec.query_stats(),
+        LokiQueryStats {
+            total_entries_returned: 2,
+            total_bytes_processed: 4096,
+            exec_time: Duration::from_secs(1),
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_follows_pagination_cursor() -> Result<(), Box<dyn std::error::Error>> {
+    let page1 = log_batch(&[(1_000_000_000, &[("app", "my-app1")], "this is aaa log")]);
+    let page2 = log_batch(&[(2_000_000_000, &[("app", "my-app1")], "this is bbb log")]);
+    let mock = MockLoki::start(move |req| match req.query_param("cursor") {
+        None => {
+            MockResponse::parquet_with_metadata(std::slice::from_ref(&page1), &[("next", "page-2")])
+        }
+        Some("page-2") => MockResponse::parquet(std::slice::from_ref(&page2)),
+        Some(_) => MockResponse::new(400, "unknown cursor"),
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let batches = ctx.sql("select line from loki").await?.collect().await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-----------------+
+| line            |
++-----------------+
+| this is aaa log |
+| this is bbb log |
++-----------------+"#
+    );
+    assert_eq!(mock.requests_to("/loki/api/v1/query_range").len(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_dictionary_labels() -> Result<(), Box<dyn std::error::Error>> {
+    let batch = log_batch(&[
+        (
+            1_000_000_000,
+            &[("app", "my-app1"), ("env", "prod")],
+            "this is aaa log",
+        ),
+        (
+            2_000_000_000,
+            &[("app", "my-app1"), ("env", "dev")],
+            "this is bbb log",
+        ),
+    ]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_dictionary_labels(true);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let batches = ctx.sql("select labels from loki").await?.collect().await?;
+    assert_eq!(
+        batches[0].schema().field(0).data_type(),
+        DICTIONARY_LABELS_FIELD_REF.data_type()
+    );
+
+    let batches = ctx
+        .sql("select labels['env'] as env from loki where labels['app'] = 'my-app1'")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+------+
+| env  |
++------+
+| prod |
+| dev  |
++------+"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_partition_error_policy() -> Result<(), Box<dyn std::error::Error>> {
+    // The partition fails after its first page
+    let page1 = log_batch(&[(1_000_000_000, &[("app", "my-app1")], "this is aaa log")]);
+    let mock = MockLoki::start(move |req| match req.query_param("cursor") {
+        None => {
+            MockResponse::parquet_with_metadata(std::slice::from_ref(&page1), &[("next", "page-2")])
+        }
+        Some(_) => MockResponse::new(500, "too many outstanding requests"),
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_default_label(Some("app".to_string()));
+
+    let ctx = SessionContext::new();
+    ctx.register_table(
+        "loki",
+        Arc::new(
+            table
+                .clone()
+                .with_partition_error_policy(PartitionErrorPolicy::FailFast),
+        ),
+    )?;
+    let err = ctx
+        .sql("select line from loki")
+        .await?
+        .collect()
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("too many outstanding requests"),
+        "{err}"
+    );
+
+    let ctx = SessionContext::new();
+    ctx.register_table(
+        "loki",
+        Arc::new(table.with_partition_error_policy(PartitionErrorPolicy::SkipAndWarn)),
+    )?;
+    let batches = ctx.sql("select line from loki").await?.collect().await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-----------------+
+| line            |
++-----------------+
+| this is aaa log |
++-----------------+"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_structured_metadata() -> Result<(), Box<dyn std::error::Error>> {
+    let logs = log_batch(&[
+        (1_000_000_000, &[("app", "my-app1")], "this is aaa log"),
+        (2_000_000_000, &[("app", "my-app1")], "this is bbb log"),
+    ]);
+    let mut fields = logs.schema().fields().to_vec();
+    fields.push(STRUCTURED_METADATA_FIELD_REF.clone());
+    let mut columns = logs.columns().to_vec();
+    columns.push(Arc::new(labels_array(&[
+        &[("trace_id", "abc")],
+        &[("trace_id", "def")],
+    ])));
+    let batch = RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)?;
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_structured_metadata_column(true);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let sql =
+        "select line, structured_metadata from loki where structured_metadata['trace_id'] = 'abc'";
+    let exec = plan_scan_exec(&ctx, sql).await?;
+    assert_eq!(exec.log_query, r#"{app=~".+"} | trace_id="abc""#);
+
+    // The mock ignores the metadata filter, so DataFusion re-verifies it
+    let batches = ctx.sql(sql).await?.collect().await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-----------------+---------------------+
+| line            | structured_metadata |
++-----------------+---------------------+
+| this is aaa log | {trace_id: abc}     |
++-----------------+---------------------+"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_missing_structured_metadata() -> Result<(), Box<dyn std::error::Error>> {
+    let batch = log_batch(&[(1_000_000_000, &[("app", "my-app1")], "this is aaa log")]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_structured_metadata_column(true);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let batches = ctx
+        .sql("select line, structured_metadata from loki")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-----------------+---------------------+
+| line            | structured_metadata |
++-----------------+---------------------+
+| this is aaa log | {}                  |
++-----------------+---------------------+"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_response_without_timestamp() -> Result<(), Box<dyn std::error::Error>> {
+    let logs = log_batch(&[(1_000_000_000, &[("app", "my-app1")], "this is aaa log")]);
+    let batch = logs.project(&[1, 2])?;
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    // Not projected, so not required
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table.clone()))?;
+    let batches = ctx.sql("select line from loki").await?.collect().await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-----------------+
+| line            |
++-----------------+
+| this is aaa log |
++-----------------+"#
+    );
+
+    // Projected into a non-nullable field
+    let err = ctx
+        .sql("select timestamp, line from loki")
+        .await?
+        .collect()
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Loki response is missing column timestamp"),
+        "{err}"
+    );
+
+    // Projected into a nullable field
+    let schema = Arc::new(Schema::new(vec![
+        TIMESTAMP_FIELD_REF.as_ref().clone().with_nullable(true),
+        LABELS_FIELD_REF.as_ref().clone(),
+        LINE_FIELD_REF.as_ref().clone(),
+    ]));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table.with_schema(schema)))?;
+    let batches = ctx
+        .sql("select timestamp, line from loki")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-----------+-----------------+
+| timestamp | line            |
++-----------+-----------------+
+|           | this is aaa log |
++-----------+-----------------+"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_large_response() -> Result<(), Box<dyn std::error::Error>> {
+    // Large enough to be spilled to disk, with incompressible lines
+    let mut seed = 42u64;
+    let lines = (0..20_000)
+        .map(|_| {
+            (0..32)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    format!("{seed:016x}")
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>();
+    let rows = lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| (i as i64, &[("app", "my-app1")][..], line.as_str()))
+        .collect::<Vec<_>>();
+    let batch = log_batch(&rows);
+    let body = parquet_bytes(std::slice::from_ref(&batch));
+    assert!(body.len() > 8 * 1024 * 1024);
+    let mock = MockLoki::start(move |_| MockResponse::new(200, body.clone())).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let batches = ctx.sql("select line from loki").await?.collect().await?;
+    let scanned = batches
+        .iter()
+        .flat_map(|b| {
+            b.column(0)
+                .as_string::<i32>()
+                .iter()
+                .map(|v| v.unwrap().to_string())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(scanned, lines);
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_decodes_row_groups_on_demand() -> Result<(), Box<dyn std::error::Error>> {
+    let now = current_timestamp_ns();
+    let batch = log_batch(&[
+        (now - 3_000_000_000, &[("app", "my-app")], "log 0"),
+        (now - 2_000_000_000, &[("app", "my-app")], "log 1"),
+        (now - 1_000_000_000, &[("app", "my-app")], "log 2"),
+    ]);
+    let props = WriterProperties::builder()
+        .set_max_row_group_row_count(Some(1))
+        .build();
+    let mut body = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut body, batch.schema(), Some(props))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    // Corrupts the last row group, so decoding it fails
+    let metadata = ParquetMetaDataReader::new().parse_and_finish(&Bytes::from(body.clone()))?;
+    assert_eq!(metadata.num_row_groups(), 3);
+    for column in metadata.row_group(2).columns() {
+        let (start, len) = column.byte_range();
+        body[start as usize..(start + len) as usize].fill(0xff);
+    }
+    let mock = MockLoki::start(move |_| MockResponse::new(200, body.clone())).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_target_partitions(1);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let exec = plan_scan_exec(&ctx, "select line from loki").await?;
+    let mut stream = exec.execute(0, ctx.task_ctx())?;
+    // A slow consumer only gets a row group decoded once it took the previous batch
+    for line in ["log 0", "log 1"] {
+        let batch = stream.next().await.unwrap()?;
+        assert_eq!(batch.column(0).as_string::<i32>().value(0), line);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(stream.next().await.unwrap().is_err());
+    assert_eq!(mock.requests().len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn tail_logs() -> Result<(), Box<dyn std::error::Error>> {
+    let long_line = "x".repeat(200);
+    let messages = [
+        r#"{"streams":[{"stream":{"app":"api","env":"prod"},"values":[["1700000000000000000","log 0"],["1700000001000000000","log 1"]]}]}"#.to_string(),
+        r#"{"streams":[],"dropped_entries":null}"#.to_string(),
+        format!(
+            r#"{{"streams":[{{"stream":{{"app":"api"}},"values":[["1700000002000000000","{long_line}",{{"trace_id":"abc"}}]]}}]}}"#
+        ),
+    ];
+    let mock = MockLoki::start(move |_| {
+        MockResponse::websocket(&messages.iter().map(String::as_str).collect::<Vec<_>>())
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_tenant("team-a");
+    let exec = table.tail(r#"{app="api"}"#)?.with_start(Some(1));
+    assert_eq!(
+        exec.properties().boundedness,
+        Boundedness::Unbounded {
+            requires_infinite_memory: false
+        }
+    );
+    assert_eq!(exec.properties().emission_type, EmissionType::Incremental);
+
+    let ctx = SessionContext::new();
+    let exec = roundtrip_plan(Arc::new(exec), &ctx)?;
+    let batches = collect(exec, ctx.task_ctx()).await?;
+    assert_eq!(batches.len(), 2);
+    let batch = concat_batches(&LOG_TABLE_SCHEMA, &batches)?;
+    assert_eq!(
+        pretty_format_batches(&[batch.slice(0, 2)])?.to_string(),
+        r#"+----------------------+-----------------------+-------+
+| timestamp            | labels                | line  |
++----------------------+-----------------------+-------+
+| 2023-11-14T22:13:20Z | {app: api, env: prod} | log 0 |
+| 2023-11-14T22:13:21Z | {app: api, env: prod} | log 1 |
++----------------------+-----------------------+-------+"#
+    );
+    assert_eq!(batch.column(2).as_string::<i32>().value(2), long_line);
+
+    let requests = mock.requests_to("/loki/api/v1/tail");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].query_param("query"), Some(r#"{app="api"}"#));
+    assert_eq!(requests[0].query_param("start"), Some("1"));
+    assert_eq!(requests[0].header("Upgrade"), Some("websocket"));
+    assert_eq!(requests[0].header("Sec-WebSocket-Version"), Some("13"));
+    assert!(requests[0].header("Sec-WebSocket-Key").is_some());
+    assert_eq!(requests[0].header("X-Scope-OrgID"), Some("team-a"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn tail_ends_after_max_lines() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| {
+        MockResponse::websocket_open(&[
+            r#"{"streams":[{"stream":{"app":"api"},"values":[["1700000000000000000","log 0"],["1700000001000000000","log 1"]]}]}"#,
+            r#"{"streams":[{"stream":{"app":"api"},"values":[["1700000002000000000","log 2"]]}]}"#,
+        ])
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_tail_max_lines(2);
+    let exec = table.tail(r#"{app="api"}"#)?;
+    assert_eq!(exec.max_lines, Some(2));
+    assert_eq!(exec.properties().boundedness, Boundedness::Bounded);
+
+    let ctx = SessionContext::new();
+    let exec = roundtrip_plan(Arc::new(exec), &ctx)?;
+    assert!(
+        displayable(exec.as_ref())
+            .one_line()
+            .to_string()
+            .contains("max_lines=2")
+    );
+    let batches =
+        tokio::time::timeout(Duration::from_secs(10), collect(exec, ctx.task_ctx())).await??;
+    let batch = concat_batches(&LOG_TABLE_SCHEMA, &batches)?;
+    assert_eq!(batch.num_rows(), 2);
+    assert_eq!(batch.column(2).as_string::<i32>().value(1), "log 1");
+    Ok(())
+}
+
+#[tokio::test]
+async fn tail_ends_after_max_duration() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| {
+        MockResponse::websocket_open(&[
+            r#"{"streams":[{"stream":{"app":"api"},"values":[["1700000000000000000","log 0"]]}]}"#,
+        ])
+    })
+    .await;
+
+    let table =
+        LokiLogTable::try_new(mock.endpoint())?.with_tail_max_duration(Duration::from_millis(300));
+    let exec = table.tail(r#"{app="api"}"#)?;
+    assert_eq!(exec.max_duration, Some(Duration::from_millis(300)));
+    assert_eq!(exec.properties().boundedness, Boundedness::Bounded);
+
+    let ctx = SessionContext::new();
+    let exec = roundtrip_plan(Arc::new(exec), &ctx)?;
+    let batches =
+        tokio::time::timeout(Duration::from_secs(10), collect(exec, ctx.task_ctx())).await??;
+    let batch = concat_batches(&LOG_TABLE_SCHEMA, &batches)?;
+    assert_eq!(batch.num_rows(), 1);
+    assert_eq!(batch.column(2).as_string::<i32>().value(0), "log 0");
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_partition_statistics() -> Result<(), Box<dyn std::error::Error>> {
+    let table = LokiLogTable::try_new("http://localhost:3100")?
+        .with_default_label(Some("app".to_string()))
+        .with_target_partitions(4);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let sql = "select line from loki where timestamp >= '2025-01-01T00:00:00Z' and timestamp < '2025-01-02T00:00:00Z'";
+    let exec = plan_scan_exec(&ctx, sql).await?;
+    let statistics = exec.partition_statistics(None)?;
+    assert_eq!(statistics.num_rows, Precision::Absent);
+    assert_eq!(statistics.column_statistics.len(), 1);
+    assert_eq!(
+        statistics.column_statistics[0].null_count,
+        Precision::Absent
+    );
+
+    let exec = plan_scan_exec(&ctx, &format!("{sql} limit 10")).await?;
+    assert_eq!(
+        exec.partition_statistics(Some(0))?.num_rows,
+        Precision::Inexact(10)
+    );
+    assert_eq!(
+        exec.partition_statistics(None)?.num_rows,
+        Precision::Inexact(40)
+    );
+    assert!(exec.partition_statistics(Some(4)).is_err());
+
+    // Timestamps lie within the scanned window of the partition
+    let sql = "select timestamp, line from loki where timestamp >= '2025-01-01T00:00:00Z' and timestamp < '2025-01-02T00:00:00Z'";
+    let exec = plan_scan_exec(&ctx, sql).await?;
+    let statistics = exec.partition_statistics(None)?;
+    assert_eq!(statistics.column_statistics[1].min_value, Precision::Absent);
+    assert_eq!(
+        statistics.column_statistics[0].min_value,
+        timestamp_statistic(1_735_689_600_000_000_000)
+    );
+    assert_eq!(
+        statistics.column_statistics[0].max_value,
+        timestamp_statistic(1_735_775_999_999_999_999)
+    );
+    let statistics = exec.partition_statistics(Some(1))?;
+    assert_eq!(
+        statistics.column_statistics[0].min_value,
+        timestamp_statistic(1_735_711_200_000_000_000)
+    );
+    assert_eq!(
+        statistics.column_statistics[0].max_value,
+        timestamp_statistic(1_735_732_799_999_999_999)
+    );
+    Ok(())
+}
+
+fn timestamp_statistic(ns: i64) -> Precision<ScalarValue> {
+    Precision::Inexact(ScalarValue::TimestampNanosecond(
+        Some(ns),
+        Some("UTC".into()),
+    ))
+}
+
+#[tokio::test]
+async fn scan_probes_timestamp_statistics() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|req| {
+        if req.query_param("limit") != Some("1") {
+            return MockResponse::parquet(&[log_batch(&[])]);
+        }
+        let timestamp = match req.query_param("direction") {
+            Some("forward") => "1735700000000000000",
+            _ => "1735710000000000000",
+        };
+        MockResponse::new(
+            200,
+            format!(
+                r#"{{"status":"success","data":{{"resultType":"streams","result":[{{"stream":{{"app":"my-app"}},"values":[["{timestamp}","probed"]]}}]}}}}"#
+            ),
+        )
+    })
+    .await;
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_timestamp_statistics(true);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let sql = "select timestamp from loki where timestamp >= '2025-01-01T00:00:00Z' and timestamp < '2025-01-02T00:00:00Z'";
+    let exec = plan_scan_exec(&ctx, sql).await?;
+    let exec = roundtrip_plan(Arc::new(exec), &ctx)?;
+    let statistics = exec.partition_statistics(None)?;
+    assert_eq!(
+        statistics.column_statistics[0].min_value,
+        timestamp_statistic(1_735_700_000_000_000_000)
+    );
+    assert_eq!(
+        statistics.column_statistics[0].max_value,
+        timestamp_statistic(1_735_710_000_000_000_000)
+    );
+    let probes = mock.requests();
+    assert_eq!(probes.len(), 2);
+    for probe in probes {
+        assert_eq!(probe.query_param("query"), Some(r#"{app=~".+"} "#));
+        assert_eq!(probe.query_param("start"), Some("1735689600000000000"));
+        assert_eq!(probe.query_param("end"), Some("1735776000000000000"));
+    }
+
+    // Failed probes fall back to the time bounds
+    let table = LokiLogTable::try_new("http://127.0.0.1:1")?
+        .with_default_label(Some("app".to_string()))
+        .with_timestamp_statistics(true);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+    let exec = plan_scan_exec(&ctx, sql).await?;
+    assert_eq!(
+        exec.partition_statistics(None)?.column_statistics[0].min_value,
+        timestamp_statistic(1_735_689_600_000_000_000)
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_error_includes_response_text() -> Result<(), Box<dyn std::error::Error>> {
+    let mock =
+        MockLoki::start(|_| MockResponse::new(400, "parse error at line 1, col 8: syntax error"))
+            .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let err = ctx
+        .sql("select * from loki where line like '%aaa%'")
+        .await?
+        .collect()
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Request to loki failed with status 400 Bad Request, url: ")
+            && err
+                .to_string()
+                .ends_with("text: parse error at line 1, col 8: syntax error"),
+        "{err}"
+    );
+    // The generated LogQL, unlike the url, isn't percent-encoded
+    assert!(
+        err.to_string()
+            .contains(r#", query: {app=~".+"} |= `aaa`, text: "#),
+        "{err}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_error_parses_response_body() -> Result<(), Box<dyn std::error::Error>> {
+    let html = format!(
+        "<html><body><h1>502 Bad Gateway</h1>{}</body></html>",
+        "x".repeat(2000)
+    );
+    let mock = MockLoki::start(move |req| match req.query_param("query").unwrap() {
+        q if q.contains("json") => MockResponse::new(
+            400,
+            r#"{"status":"error","errorType":"bad_data","error":"parse error at line 1, col 8"}"#,
+        )
+        .with_header("Content-Type", "application/json"),
+        q if q.contains("limited") => MockResponse::new(429, "too many outstanding requests"),
+        _ => MockResponse::new(502, html.clone()).with_header("Content-Type", "text/html"),
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?;
+    let ctx = SessionContext::new_with_config(SessionConfig::new().with_target_partitions(1));
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let scan_error = |app: &'static str| {
+        let ctx = ctx.clone();
+        async move {
+            ctx.sql(&format!("select * from loki where labels['app'] = '{app}'"))
+                .await?
+                .collect()
+                .await
+                .map(|_| ())
+        }
+    };
+
+    let err = scan_error("json").await.unwrap_err();
+    assert!(
+        matches!(err.find_root(), DataFusionError::Plan(_)),
+        "{err:?}"
+    );
+    assert!(
+        err.to_string()
+            .ends_with("text: parse error at line 1, col 8"),
+        "{err}"
+    );
+
+    let err = scan_error("limited").await.unwrap_err();
+    assert!(
+        matches!(err.find_root(), DataFusionError::ResourcesExhausted(_)),
+        "{err:?}"
+    );
+    assert!(
+        err.to_string()
+            .ends_with("text: too many outstanding requests"),
+        "{err}"
+    );
+
+    let err = scan_error("html").await.unwrap_err();
+    assert!(
+        matches!(err.find_root(), DataFusionError::Execution(_)),
+        "{err:?}"
+    );
+    let text = err.to_string();
+    let text = text.split_once("text: ").unwrap().1;
+    assert!(
+        text.starts_with("<html><body><h1>502 Bad Gateway</h1>xxx"),
+        "{text}"
+    );
+    assert_eq!(text.chars().count(), 1024 + "...".len());
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_multi_key_regex_selector_keeps_all_labels() -> Result<(), Box<dyn std::error::Error>>
+{
+    let now = current_timestamp_ns();
+    // The streams loki matches for the selector, with labels beyond the filtered ones
+    let batch = log_batch(&[
+        (
+            now - 3_000_000_000,
+            &[("app", "api"), ("env", "prod"), ("pod", "api-0")],
+            "log 0",
+        ),
+        (
+            now - 2_000_000_000,
+            &[
+                ("app", "auth"),
+                ("env", "stg"),
+                ("region", "eu"),
+                ("pod", "auth-1"),
+            ],
+            "log 1",
+        ),
+        (
+            now - 1_000_000_000,
+            &[("env", "prod"), ("app", "admin")],
+            "log 2",
+        ),
+    ]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_target_partitions(1);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let sql = "select labels, line from loki where labels['app'] ~ 'a.*' and labels['env'] ~ '^(prod|stg)$' order by line";
+    let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+    assert_eq!(
+        find_scan_exec(&plan).unwrap().log_query,
+        r#"{app=~"a.*", env=~"prod|stg"} "#
+    );
+    let batches = collect(plan, ctx.task_ctx()).await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+------------------------------------------------+-------+
+| labels                                         | line  |
++------------------------------------------------+-------+
+| {app: api, env: prod, pod: api-0}              | log 0 |
+| {app: auth, env: stg, region: eu, pod: auth-1} | log 1 |
+| {env: prod, app: admin}                        | log 2 |
++------------------------------------------------+-------+"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_map_keys_and_values_of_labels() -> Result<(), Box<dyn std::error::Error>> {
+    let now = current_timestamp_ns();
+    let batch = log_batch(&[
+        (
+            now - 2_000_000_000,
+            &[("app", "api"), ("env", "prod")],
+            "log 0",
+        ),
+        (now - 1_000_000_000, &[], "log 1"),
+    ]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_target_partitions(1);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let batches = ctx
+        .sql("select map_keys(labels) as keys, map_values(labels) as values, line from loki order by line")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+------------+-------------+-------+
+| keys       | values      | line  |
++------------+-------------+-------+
+| [app, env] | [api, prod] | log 0 |
+| []         | []          | log 1 |
++------------+-------------+-------+"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_map_get_of_labels() -> Result<(), Box<dyn std::error::Error>> {
+    let now = current_timestamp_ns();
+    let batch = log_batch(&[
+        (
+            now - 2_000_000_000,
+            &[("app", "api"), ("env", "prod")],
+            "log 0",
+        ),
+        (now - 1_000_000_000, &[("app", "api")], "log 1"),
+    ]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_target_partitions(1);
+    let ctx = SessionContext::new();
+    ctx.register_udf(ScalarUDF::from(MapGet::new()));
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let batches = ctx
+        .sql("select map_get(labels, 'env') as env, map_get(labels, 'env', 'none') as env_or_none, map_get(labels, 'app', 'none') as app, line from loki order by line")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+------+-------------+-----+-------+
+| env  | env_or_none | app | line  |
++------+-------------+-----+-------+
+| prod | prod        | api | log 0 |
+|      | none        | api | log 1 |
++------+-------------+-----+-------+"#
+    );
+
+    let batches = ctx
+        .sql("select map_get(Map {'a': 1}, 'b') as missing, map_get(Map {'a': 1}, 'b', 2) as defaulted")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+---------+-----------+
+| missing | defaulted |
++---------+-----------+
+|         | 2         |
++---------+-----------+"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_map_get_of_duplicate_labels() -> Result<(), Box<dyn std::error::Error>> {
+    let now = current_timestamp_ns();
+    let logs = log_batch(&[(now - 1_000_000_000, &[], "duplicate labels")]);
+    let batch = RecordBatch::try_new(
+        logs.schema(),
+        vec![
+            logs.column(0).clone(),
+            Arc::new(labels_array(&[&[
+                ("app", "first"),
+                ("env", "prod"),
+                ("app", "last"),
+            ]])),
+            logs.column(2).clone(),
+        ],
+    )?;
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_target_partitions(1);
+    let ctx = SessionContext::new();
+    ctx.register_udf(ScalarUDF::from(MapGet::new()));
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let batches = ctx
+        .sql("select map_get(labels, 'app') as app, map_get(labels, 'env') as env, map_get(labels, 'team', 'none') as team from loki")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-------+------+------+
+| app   | env  | team |
++-------+------+------+
+| first | prod | none |
++-------+------+------+"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_json_get_of_lines() -> Result<(), Box<dyn std::error::Error>> {
+    let now = current_timestamp_ns();
+    let batch = log_batch(&[
+        (
+            now - 4_000_000_000,
+            &[("app", "api")],
+            r#"{"user_id":"u-1","status":200}"#,
+        ),
+        (now - 3_000_000_000, &[("app", "api")], r#"{"status":500}"#),
+        (now - 2_000_000_000, &[("app", "api")], "GET /health 200"),
+        (now - 1_000_000_000, &[("app", "api")], r#"["user_id"]"#),
+    ]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_target_partitions(1);
+    let ctx = SessionContext::new();
+    ctx.register_udf(ScalarUDF::from(JsonGet::new()));
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let batches = ctx
+        .sql("select json_get(line, 'user_id') as user_id, json_get(line, 'status') as status, line from loki order by timestamp")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+---------+--------+--------------------------------+
+| user_id | status | line                           |
++---------+--------+--------------------------------+
+| u-1     | 200    | {"user_id":"u-1","status":200} |
+|         | 500    | {"status":500}                 |
+|         |        | GET /health 200                |
+|         |        | ["user_id"]                    |
++---------+--------+--------------------------------+"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_logfmt_get_of_lines() -> Result<(), Box<dyn std::error::Error>> {
+    let now = current_timestamp_ns();
+    let batch = log_batch(&[
+        (
+            now - 3_000_000_000,
+            &[("app", "api")],
+            r#"level=error msg="boom" dur=12ms"#,
+        ),
+        (
+            now - 2_000_000_000,
+            &[("app", "api")],
+            r#"level=info msg="said \"hi\"\tthen left" cached dur=3ms dur=4ms"#,
+        ),
+        (now - 1_000_000_000, &[("app", "api")], "plain text line"),
+    ]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_target_partitions(1);
+    let ctx = SessionContext::new();
+    ctx.register_udf(ScalarUDF::from(LogfmtGet::new()));
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let batches = ctx
+        .sql("select logfmt_get(line, 'level') as level, logfmt_get(line, 'msg') as msg, logfmt_get(line, 'dur') as dur, logfmt_get(line, 'cached') as cached from loki order by timestamp")
+        .await?
+        .collect()
+        .await?;
+    let column = |i: usize| {
+        batches[0]
+            .column(i)
+            .as_string::<i32>()
+            .iter()
+            .map(|v| v.map(str::to_string))
+            .collect::<Vec<_>>()
+    };
+    let some = |v: &str| Some(v.to_string());
+    assert_eq!(column(0), vec![some("error"), some("info"), None]);
+    assert_eq!(
+        column(1),
+        vec![some("boom"), some("said \"hi\"\tthen left"), None]
+    );
+    // The first of repeated keys wins
+    assert_eq!(column(2), vec![some("12ms"), some("3ms"), None]);
+    // Keys without a value are empty
+    assert_eq!(column(3), vec![None, some(""), None]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn map_get_of_large_and_dictionary_labels() -> Result<(), Box<dyn std::error::Error>> {
+    let mut large = MapBuilder::new(None, LargeStringBuilder::new(), LargeStringBuilder::new());
+    large.keys().append_value("app");
+    large.values().append_value("large-app");
+    large.append(true)?;
+    let mut dictionary = MapBuilder::new(
+        None,
+        StringDictionaryBuilder::<Int32Type>::new(),
+        StringDictionaryBuilder::<Int32Type>::new(),
+    );
+    dictionary.keys().append_value("app");
+    dictionary.values().append_value("dictionary-app");
+    dictionary.append(true)?;
+
+    for (labels, expected) in [
+        (Arc::new(large.finish()) as ArrayRef, "large-app"),
+        (Arc::new(dictionary.finish()), "dictionary-app"),
+    ] {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "labels",
+            labels.data_type().clone(),
+            false,
+        )]));
+        let ctx = SessionContext::new();
+        ctx.register_udf(ScalarUDF::from(MapGet::new()));
+        ctx.register_batch("t", RecordBatch::try_new(schema, vec![labels])?)?;
+        let batches = ctx
+            .sql("select map_get(labels, 'app') as app, map_get(labels, 'env', 'none') as env from t")
+            .await?
+            .collect()
+            .await?;
+        let column = |i: usize| {
+            let array = cast(batches[0].column(i), &DataType::Utf8).unwrap();
+            array.as_string::<i32>().value(0).to_string()
+        };
+        assert_eq!(
+            (column(0), column(1)),
+            (expected.to_string(), "none".to_string())
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_sorted_labels() -> Result<(), Box<dyn std::error::Error>> {
+    let now = current_timestamp_ns();
+    let logs = log_batch(&[(now - 1_000_000_000, &[], "unsorted labels")]);
+    let labels = labels_array(&[&[("env", "prod"), ("app", "first"), ("app", "last")]]);
+    let batch = RecordBatch::try_new(
+        logs.schema(),
+        vec![
+            logs.column(0).clone(),
+            Arc::new(labels.clone()),
+            logs.column(2).clone(),
+        ],
+    )?;
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_sorted_labels(true)
+        .with_target_partitions(1);
+    let ctx = SessionContext::new();
+    ctx.register_udf(ScalarUDF::from(MapGet::new()));
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let batches = ctx
+        .sql("select labels, map_get(labels, 'app') as app, map_get(labels, 'env') as env, map_get(labels, 'team', 'none') as team from loki")
+        .await?
+        .collect()
+        .await?;
+    assert!(has_sorted_keys(batches[0].schema().field(0)));
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+------------------------------------+-------+------+------+
+| labels                             | app   | env  | team |
++------------------------------------+-------+------+------+
+| {app: first, app: last, env: prod} | first | prod | none |
++------------------------------------+-------+------+------+"#
+    );
+
+    // Binary searching the unsorted map misses `app`, which a linear scan finds
+    let field = LABELS_FIELD_REF.as_ref().clone();
+    let sorted_field = field.clone().with_metadata(HashMap::from([(
+        SORTED_KEYS_METADATA_KEY.to_string(),
+        "true".to_string(),
+    )]));
+    for (field, expected) in [(field, Some("first")), (sorted_field, None)] {
+        let schema = Arc::new(Schema::new(vec![field]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(labels.clone())])?;
+        let ctx = SessionContext::new();
+        ctx.register_udf(ScalarUDF::from(MapGet::new()));
+        ctx.register_batch("t", batch)?;
+        let batches = ctx
+            .sql("select map_get(labels, 'app') from t")
+            .await?
+            .collect()
+            .await?;
+        let apps = batches[0].column(0).as_string::<i32>();
+        assert_eq!(apps.iter().next().flatten(), expected);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_max_labels_per_stream() -> Result<(), Box<dyn std::error::Error>> {
+    let now = current_timestamp_ns();
+    let batch = log_batch(&[
+        (
+            now - 2_000_000_000,
+            &[
+                ("pod", "api-0"),
+                ("env", "prod"),
+                ("app", "api"),
+                ("zone", "a"),
+                ("cluster", "eu"),
+            ],
+            "wide labels",
+        ),
+        (now - 1_000_000_000, &[("app", "api")], "narrow labels"),
+    ]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_max_labels_per_stream(2)
+        .with_target_partitions(1);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let exec = plan_scan_exec(&ctx, "select labels, line from loki").await?;
+    let exec = roundtrip_plan(Arc::new(exec), &ctx)?;
+    let batches = collect(exec, ctx.task_ctx()).await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-------------------------+---------------+
+| labels                  | line          |
++-------------------------+---------------+
+| {app: api, cluster: eu} | wide labels   |
+| {app: api}              | narrow labels |
++-------------------------+---------------+"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_truncation_flag() -> Result<(), Box<dyn std::error::Error>> {
+    let now = current_timestamp_ns();
+    let batch = log_batch(&[
+        (now - 2_000_000_000, &[("app", "my-app")], "log 0"),
+        (now - 1_000_000_000, &[("app", "my-app")], "log 1"),
+    ]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_truncation_flag(true)
+        .with_target_partitions(1);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    for (sql, truncated) in [
+        ("select line, __truncated from loki limit 2", true),
+        ("select line, __truncated from loki limit 5", false),
+        ("select line, __truncated from loki", false),
+    ] {
+        let exec = plan_scan_exec(&ctx, sql).await?;
+        let exec = roundtrip_plan(Arc::new(exec), &ctx)?;
+        let batches = collect(exec, ctx.task_ctx()).await?;
+        let flags = batches
+            .iter()
+            .flat_map(|b| b.column(1).as_boolean().iter().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(flags, vec![Some(truncated); 2], "{sql}");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_timestamp_unit() -> Result<(), Box<dyn std::error::Error>> {
+    let batch = log_batch(&[
+        (1_700_000_000_123_455_789, &[("app", "my-app")], "log 0"),
+        (1_700_000_000_123_456_789, &[("app", "my-app")], "log 1"),
+    ]);
+    let mock = MockLoki::start(move |req| {
+        let start = req.query_param("start").unwrap().parse::<i64>().unwrap();
+        let end = req.query_param("end").unwrap().parse::<i64>().unwrap();
+        let timestamps = batch.column(0).as_primitive::<TimestampNanosecondType>();
+        let mask = timestamps
+            .iter()
+            .map(|ts| ts.map(|ts| start <= ts && ts < end))
+            .collect::<BooleanArray>();
+        MockResponse::parquet(&[filter_record_batch(&batch, &mask).unwrap()])
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_timestamp_unit(TimeUnit::Microsecond)
+        .with_target_partitions(1);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let sql = "select timestamp, line from loki where timestamp > to_timestamp_micros(1700000000123455) and timestamp <= to_timestamp_micros(1700000000123456)";
+    let exec = plan_scan_exec(&ctx, sql).await?;
+    assert_eq!(
+        (exec.start, exec.end),
+        (
+            Some(1_700_000_000_123_456_000),
+            Some(1_700_000_000_123_457_000)
+        )
+    );
+
+    let batches = ctx.sql(sql).await?.collect().await?;
+    assert_eq!(
+        batches[0].schema().field(0).data_type(),
+        &DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()))
+    );
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-----------------------------+-------+
+| timestamp                   | line  |
++-----------------------------+-------+
+| 2023-11-14T22:13:20.123456Z | log 1 |
++-----------------------------+-------+"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_time_range_partitions() -> Result<(), Box<dyn std::error::Error>> {
+    let second = 1_000_000_000;
+    let batch = log_batch(&[
+        (10 * second, &[("app", "my-app1")], "log 0"),
+        (11 * second, &[("app", "my-app1")], "log 1"),
+        (12 * second, &[("app", "my-app1")], "log 2"),
+        (13 * second, &[("app", "my-app1")], "log 3"),
+        (14 * second, &[("app", "my-app1")], "log 4"),
+    ]);
+    // Returns logs at the range end too, which partitions must not duplicate
+    let mock = MockLoki::start(move |req| {
+        let start = req.query_param("start").unwrap().parse::<i64>().unwrap();
+        let end = req.query_param("end").unwrap().parse::<i64>().unwrap();
+        let timestamps = batch.column(0).as_primitive::<TimestampNanosecondType>();
+        let mask = timestamps
+            .iter()
+            .map(|ts| ts.map(|ts| start <= ts && ts <= end))
+            .collect::<BooleanArray>();
+        MockResponse::parquet(&[filter_record_batch(&batch, &mask).unwrap()])
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_target_partitions(4);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let sql = "select line from loki where timestamp >= to_timestamp(10) and timestamp < to_timestamp(14) order by line";
+    let exec = plan_scan_exec(&ctx, sql).await?;
+    assert_eq!(exec.properties().partitioning.partition_count(), 4);
+    assert_eq!(
+        exec.partition_windows(),
+        (10..14)
+            .map(|s| (Some(s * second), Some((s + 1) * second)))
+            .collect::<Vec<_>>()
+    );
+
+    let plan = ctx.sql(sql).await?.create_physical_plan().await?;
+    let plan = roundtrip_plan(plan, &ctx)?;
+    let batches = collect(plan, ctx.task_ctx()).await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-------+
+| line  |
++-------+
+| log 0 |
+| log 1 |
+| log 2 |
+| log 3 |
++-------+"#
+    );
+    let mut windows = mock
+        .requests_to("/loki/api/v1/query_range")
+        .iter()
+        .map(|r| {
+            let start = r.query_param("start").unwrap().parse::<i64>().unwrap();
+            let end = r.query_param("end").unwrap().parse::<i64>().unwrap();
+            (start / second, end / second)
+        })
+        .collect::<Vec<_>>();
+    windows.sort();
+    assert_eq!(windows, vec![(10, 11), (11, 12), (12, 13), (13, 14)]);
+
+    // Scans bounded on one end are bounded on the other by the table defaults
+    let exec = plan_scan_exec(
+        &ctx,
+        "select line from loki where timestamp >= to_timestamp(10)",
+    )
+    .await?;
+    assert_eq!(exec.properties().partitioning.partition_count(), 4);
+
+    let batches = ctx.sql(&format!("{sql} limit 2")).await?.collect().await?;
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+    Ok(())
+}
+
+/// Serves `rows` like loki, returning up to `limit` logs in `[start, end)` from the end
+/// the `direction` asks for.
+///
+/// Timestamps are relative to `base`.
+fn capped_loki_handler(
+    base: i64,
+    rows: Vec<(i64, &'static str)>,
+) -> impl Fn(&MockRequest) -> MockResponse {
+    move |req| {
+        let start = req.query_param("start").unwrap().parse::<i64>().unwrap();
+        let end = req.query_param("end").unwrap().parse::<i64>().unwrap();
+        let limit = req.query_param("limit").unwrap().parse::<usize>().unwrap();
+        let mut rows = rows
+            .iter()
+            .filter(|(ts, _)| start <= base + *ts && base + *ts < end)
+            .map(|(ts, line)| (base + *ts, &[("app", "my-app1")][..], *line))
+            .collect::<Vec<_>>();
+        rows.sort_by_key(|(ts, _, _)| *ts);
+        if req.query_param("direction") != Some("forward") {
+            rows.reverse();
+        }
+        rows.truncate(limit);
+        MockResponse::parquet(&[log_batch(&rows)])
+    }
+}
+
+#[tokio::test]
+async fn scan_follows_session_config() -> Result<(), Box<dyn std::error::Error>> {
+    let now = current_timestamp_ns();
+    let batch = log_batch(&[
+        (now - 5_000_000_000, &[("app", "my-app1")], "log 0"),
+        (now - 4_000_000_000, &[("app", "my-app1")], "log 1"),
+        (now - 3_000_000_000, &[("app", "my-app1")], "log 2"),
+    ]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let config = SessionConfig::new()
+        .with_target_partitions(3)
+        .with_batch_size(2);
+    let ctx = SessionContext::new_with_config(config);
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_default_label(Some("app".to_string()));
+    ctx.register_table("loki", Arc::new(table.clone()))?;
+    ctx.register_table(
+        "loki_single",
+        Arc::new(table.clone().with_target_partitions(1)),
+    )?;
+    ctx.register_table("loki_small", Arc::new(table.with_scan_batch_size(1)))?;
+
+    let bounded = "timestamp >= to_timestamp(10) and timestamp < to_timestamp(14)";
+    let exec = plan_scan_exec(&ctx, &format!("select line from loki where {bounded}")).await?;
+    assert_eq!(exec.properties().partitioning.partition_count(), 3);
+    assert_eq!(exec.batch_size, 2);
+    let exec = plan_scan_exec(
+        &ctx,
+        &format!("select line from loki_single where {bounded}"),
+    )
+    .await?;
+    assert_eq!(exec.properties().partitioning.partition_count(), 1);
+
+    let plan = ctx
+        .sql("select line from loki")
+        .await?
+        .create_physical_plan()
+        .await?;
+    let plan = roundtrip_plan(plan, &ctx)?;
+    assert_eq!(find_scan_exec(&plan).unwrap().batch_size, 2);
+    let batches = collect(plan, ctx.task_ctx()).await?;
+    assert_eq!(
+        batches
+            .iter()
+            .map(|b| b.num_rows())
+            .filter(|rows| *rows > 0)
+            .collect::<Vec<_>>(),
+        vec![2, 1]
+    );
+
+    // The table's batch size takes precedence over the session's
+    let plan = ctx
+        .sql("select line from loki_small")
+        .await?
+        .create_physical_plan()
+        .await?;
+    let plan = roundtrip_plan(plan, &ctx)?;
+    assert_eq!(find_scan_exec(&plan).unwrap().batch_size, 1);
+    let batches = collect(plan, ctx.task_ctx()).await?;
+    assert_eq!(
+        batches
+            .iter()
+            .map(|b| b.num_rows())
+            .filter(|rows| *rows > 0)
+            .collect::<Vec<_>>(),
+        vec![1, 1, 1]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_fail_on_empty() -> Result<(), Box<dyn std::error::Error>> {
+    let batch = log_batch(&[(
+        current_timestamp_ns() - 1_000_000_000,
+        &[("app", "my-app1")],
+        "log 0",
+    )]);
+    let mock = MockLoki::start(move |req| {
+        if req.query_param("query").unwrap().contains("my-app1") {
+            MockResponse::parquet(std::slice::from_ref(&batch))
+        } else {
+            MockResponse::parquet(&[log_batch(&[])])
+        }
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_fail_on_empty(true);
+    let ctx = SessionContext::new_with_config(SessionConfig::new().with_target_partitions(4));
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let plan = ctx
+        .sql("select line from loki where labels['app'] = 'my-app2'")
+        .await?
+        .create_physical_plan()
+        .await?;
+    let plan = roundtrip_plan(plan, &ctx)?;
+    // Fails every execution of the plan
+    for _ in 0..2 {
+        let err = collect(plan.clone(), ctx.task_ctx()).await.unwrap_err();
+        assert!(
+            err.to_string().contains("Loki query returned no results"),
+            "{err}"
+        );
+    }
+
+    let plan = ctx
+        .sql("select line from loki where labels['app'] = 'my-app1'")
+        .await?
+        .create_physical_plan()
+        .await?;
+    let plan = roundtrip_plan(plan, &ctx)?;
+    assert_eq!(find_scan_exec(&plan).unwrap().partition_windows().len(), 4);
+    let batches = collect(plan, ctx.task_ctx()).await?;
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_continues_capped_queries() -> Result<(), Box<dyn std::error::Error>> {
+    // Logs at 12 straddle the first two queries
+    let base = current_timestamp_ns() - 60 * 60 * 1_000_000_000;
+    let mock = MockLoki::start(capped_loki_handler(
+        base,
+        vec![
+            (10, "log 0"),
+            (11, "log 1"),
+            (12, "log 2"),
+            (12, "log 3"),
+            (13, "log 4"),
+            (14, "log 5"),
+            (15, "log 6"),
+        ],
+    ))
+    .await;
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_max_entries_limit(Some(3));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table.clone()))?;
+
+    let batches = ctx
+        .sql("select line from loki order by line")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-------+
+| line  |
++-------+
+| log 0 |
+| log 1 |
+| log 2 |
+| log 3 |
+| log 4 |
+| log 5 |
+| log 6 |
++-------+"#
+    );
+    let requests = mock.requests_to("/loki/api/v1/query_range");
+    let ends = requests[1..]
+        .iter()
+        .map(|r| r.query_param("end").unwrap().parse::<i64>().unwrap() - base)
+        .collect::<Vec<_>>();
+    assert_eq!(ends, vec![14, 13, 12]);
+    assert!(requests.iter().all(|r| r.query_param("limit") == Some("3")));
+
+    // Follow-ups only fetch the rows still wanted
+    let batches = ctx
+        .sql("select line from loki limit 4")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 4);
+    let requests = mock.requests_to("/loki/api/v1/query_range");
+    assert_eq!(requests.len(), 4 + 2);
+    assert_eq!(requests[5].query_param("limit"), Some("2"));
+
+    // More logs at a single timestamp than the limit can't be continued
+    let mock = MockLoki::start(capped_loki_handler(
+        base,
+        vec![(12, "log 0"), (12, "log 1"), (12, "log 2"), (12, "log 3")],
+    ))
+    .await;
+    let ctx = SessionContext::new();
+    ctx.register_table(
+        "loki",
+        Arc::new(table.clone_with_endpoint(mock.endpoint())?),
+    )?;
+    let batches = ctx.sql("select line from loki").await?.collect().await?;
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 3);
+    assert_eq!(mock.requests_to("/loki/api/v1/query_range").len(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_direction_follows_order_by() -> Result<(), Box<dyn std::error::Error>> {
+    let base = current_timestamp_ns() - 60 * 60 * 1_000_000_000;
+    let mock = MockLoki::start(capped_loki_handler(
+        base,
+        vec![
+            (10, "log 0"),
+            (11, "log 1"),
+            (12, "log 2"),
+            (13, "log 3"),
+            (14, "log 4"),
+        ],
+    ))
+    .await;
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_age_column(true)
+        .with_max_entries_limit(Some(2));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    for (order_by, direction) in [
+        ("timestamp", LokiDirection::Forward),
+        ("timestamp desc", LokiDirection::Backward),
+        ("age", LokiDirection::Backward),
+        ("age desc", LokiDirection::Forward),
+    ] {
+        let sql = format!("select line from loki order by {order_by}");
+        let exec = plan_scan_exec(&ctx, &sql).await?;
+        assert_eq!(exec.direction, direction, "{order_by}");
+    }
+    let exec = plan_scan_exec(&ctx, "select line from loki").await?;
+    assert_eq!(exec.direction, LokiDirection::Backward);
+
+    // Forward scans continue capped queries from the newest timestamp
+    let plan = ctx
+        .sql("select line from loki order by timestamp limit 3")
+        .await?
+        .create_physical_plan()
+        .await?;
+    let plan = roundtrip_plan(plan, &ctx)?;
+    assert_eq!(
+        find_scan_exec(&plan).unwrap().direction,
+        LokiDirection::Forward
+    );
+    let batches = collect(plan, ctx.task_ctx()).await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-------+
+| line  |
++-------+
+| log 0 |
+| log 1 |
+| log 2 |
++-------+"#
+    );
+    let requests = mock.requests_to("/loki/api/v1/query_range");
+    assert!(
+        requests
+            .iter()
+            .all(|r| r.query_param("direction") == Some("forward"))
+    );
+    let starts = requests[1..]
+        .iter()
+        .map(|r| r.query_param("start").unwrap().parse::<i64>().unwrap() - base)
+        .collect::<Vec<_>>();
+    assert_eq!(starts, vec![11, 12, 13, 14]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_gzip_response() -> Result<(), Box<dyn std::error::Error>> {
+    let batch = log_batch(&[(1_000_000_000, &[("app", "my-app1")], "this is aaa log")]);
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&parquet_bytes(&[batch]))?;
+    let gzipped = encoder.finish()?;
+    let mock = MockLoki::start(move |req| {
+        let resp = MockResponse::new(200, gzipped.clone());
+        // A gateway compressing without announcing it
+        if req.query_param("query").unwrap().contains("unannounced") {
+            resp
+        } else {
+            resp.with_header("Content-Encoding", "gzip")
+        }
+    })
+    .await;
+    let table = LokiLogTable::try_new(mock.endpoint())?;
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    for app in ["announced", "unannounced"] {
+        let batches = ctx
+            .sql(&format!(
+                "select line from loki where labels['app'] = '{app}'"
+            ))
+            .await?
+            .collect()
+            .await?;
+        assert_eq!(
+            pretty_format_batches(&batches)?.to_string(),
+            r#"+-----------------+
+| line            |
++-----------------+
+| this is aaa log |
++-----------------+"#
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_metrics() -> Result<(), Box<dyn std::error::Error>> {
+    let now = current_timestamp_ns();
+    let batch = log_batch(&[
+        (now - 2_000_000_000, &[("app", "my-app")], "this is aaa log"),
+        (now - 1_000_000_000, &[("app", "my-app")], "this is bbb log"),
+    ]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let exec = Arc::new(LokiLogScanExec::try_new(
+        mock.endpoint(),
+        r#"{app="my-app"}"#.to_string(),
+        Some(now - 60_000_000_000),
+        Some(now),
+        None,
+        None,
+    )?);
+    let ctx = SessionContext::new();
+    collect(exec.clone(), ctx.task_ctx()).await?;
+
+    let metrics = exec.metrics().unwrap();
+    assert_eq!(metrics.output_rows(), Some(2));
+    assert!(metrics.sum_by_name("bytes_scanned").unwrap().as_usize() > 0);
+    assert!(metrics.sum_by_name("http_request_time").unwrap().as_usize() > 0);
+    assert!(
+        metrics
+            .sum_by_name("parquet_decode_time")
+            .unwrap()
+            .as_usize()
+            > 0
+    );
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_default_label(Some("app".to_string()));
+    ctx.register_table("loki", Arc::new(table))?;
+    let batches = ctx
+        .sql("explain analyze select line from loki")
+        .await?
+        .collect()
+        .await?;
+    let plan = pretty_format_batches(&batches)?.to_string();
+    let scan = plan
+        .lines()
+        .find(|line| line.contains("LokiLogScanExec"))
+        .unwrap();
+    for name in [
+        "output_rows=2",
+        "bytes_scanned=",
+        "http_request_time=",
+        "parquet_decode_time=",
+    ] {
+        assert!(scan.contains(name), "{name} missing from {scan}");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_with_step() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| MockResponse::parquet(&[log_batch(&[])])).await;
+    let ctx = SessionContext::new();
+
+    let exec = LokiLogScanExec::try_new(
+        mock.endpoint(),
+        r#"{app="my-app"}"#.to_string(),
+        Some(1),
+        Some(2),
+        None,
+        None,
+    )?;
+    let plan = roundtrip_plan(
+        Arc::new(exec.clone().with_step(Some("30s".to_string()))),
+        &ctx,
+    )?;
+    let plan_str = displayable(plan.as_ref()).one_line().to_string();
+    assert!(plan_str.contains("step=30s"), "{plan_str}");
+    collect(plan, ctx.task_ctx()).await?;
+    collect(Arc::new(exec), ctx.task_ctx()).await?;
+
+    let requests = mock.requests_to("/loki/api/v1/query_range");
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].query_param("step"), Some("30s"));
+    assert_eq!(requests[1].query_param("step"), None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_json_response() -> Result<(), Box<dyn std::error::Error>> {
+    let body = r#"{
+  "status": "success",
+  "data": {
+    "resultType": "streams",
+    "result": [
+      {
+        "stream": {"app": "my-app1"},
+        "values": [["3000000000", "this is ccc log", {"trace_id": "abc"}], ["1000000000", "this is aaa log"]]
+      },
+      {
+        "stream": {"app": "my-app2", "env": "prod"},
+        "values": [["2000000000", "this is bbb log"]]
+      }
+    ],
+    "stats": {"summary": {"totalEntriesReturned": 3, "totalBytesProcessed": 45, "execTime": 0.5}}
+  }
+}"#;
+    let mock = MockLoki::start(move |_| {
+        MockResponse::new(200, body).with_header("Content-Type", "application/json")
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_structured_metadata_column(true)
+        .with_target_partitions(1);
+    let ctx = SessionContext::new_with_config(SessionConfig::new().with_batch_size(2));
+    ctx.register_table("loki", Arc::new(table))?;
+    let batches = ctx
+        .sql("select * from loki order by timestamp")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+----------------------+---------------------------+-----------------+---------------------+
+| timestamp            | labels                    | line            | structured_metadata |
++----------------------+---------------------------+-----------------+---------------------+
+| 1970-01-01T00:00:01Z | {app: my-app1}            | this is aaa log | {}                  |
+| 1970-01-01T00:00:02Z | {app: my-app2, env: prod} | this is bbb log | {}                  |
+| 1970-01-01T00:00:03Z | {app: my-app1}            | this is ccc log | {trace_id: abc}     |
++----------------------+---------------------------+-----------------+---------------------+"#
+    );
+
+    let exec = plan_scan_exec(&ctx, "select line from loki").await?;
+    collect(Arc::new(exec.clone()), ctx.task_ctx()).await?;
+    let metadata = exec.response_metadata();
+    assert_eq!(metadata.len(), 1);
+    assert_eq!(metadata[0].stats.unwrap().total_entries_returned, 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_json_response_of_metric_query() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockLoki::start(|_| {
+        MockResponse::new(
+            200,
+            r#"{"status": "success", "data": {"resultType": "matrix", "result": []}}"#,
+        )
+        .with_header("Content-Type", "application/json; charset=utf-8")
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+    let err = ctx
+        .sql("select * from loki")
+        .await?
+        .collect()
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Loki JSON response has result type matrix"),
+        "{err}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn scan_accepts_gzip_responses() -> Result<(), Box<dyn std::error::Error>> {
+    let batch = log_batch(&[(1_000_000_000, &[("app", "my-app1")], "this is aaa log")]);
+    let body = parquet_bytes(&[batch]);
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&body)?;
+    let gzipped = encoder.finish()?;
+    let mock = MockLoki::start(move |req| match req.header("Accept-Encoding") {
+        Some("gzip") => {
+            MockResponse::new(200, gzipped.clone()).with_header("Content-Encoding", "gzip")
+        }
+        _ => MockResponse::new(200, body.clone()),
+    })
+    .await;
+    let table = LokiLogTable::try_new(mock.endpoint())?.with_default_label(Some("app".to_string()));
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table.clone()))?;
+    ctx.register_table(
+        "loki_identity",
+        Arc::new(table.with_response_compression(false)),
+    )?;
+
+    for (name, accept_encoding) in [("loki", Some("gzip")), ("loki_identity", None)] {
+        let sent = mock.requests_to("/loki/api/v1/query_range").len();
+        let plan = ctx
+            .sql(&format!("select line from {name}"))
+            .await?
+            .create_physical_plan()
+            .await?;
+        let batches = collect(roundtrip_plan(plan, &ctx)?, ctx.task_ctx()).await?;
+        assert_eq!(
+            pretty_format_batches(&batches)?.to_string(),
+            r#"+-----------------+
+| line            |
++-----------------+
+| this is aaa log |
++-----------------+"#
+        );
+        let requests = mock.requests_to("/loki/api/v1/query_range");
+        assert!(requests.len() > sent);
+        for request in &requests[sent..] {
+            assert_eq!(request.header("Accept-Encoding"), accept_encoding);
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn limited_scan_stops_paging() -> Result<(), Box<dyn std::error::Error>> {
+    let page1 = log_batch(&[
+        (2_000_000_000, &[("app", "my-app1")], "this is bbb log"),
+        (1_000_000_000, &[("app", "my-app1")], "this is aaa log"),
+    ]);
+    let mock = MockLoki::start(move |_| {
+        MockResponse::parquet_with_metadata(std::slice::from_ref(&page1), &[("next", "page-2")])
+    })
+    .await;
+
+    let table = LokiLogTable::try_new(mock.endpoint())?
+        .with_default_label(Some("app".to_string()))
+        .with_target_partitions(1)
+        .with_max_entries_limit(None);
+    let ctx = SessionContext::new();
+    ctx.register_table("loki", Arc::new(table))?;
+
+    let batches = ctx
+        .sql("select line from loki limit 1")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
+    assert_eq!(mock.requests_to("/loki/api/v1/query_range").len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn dropping_scan_stream_cancels_download() -> Result<(), Box<dyn std::error::Error>> {
+    // A response body trickling in, far from received when the stream is dropped
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let endpoint = format!("http://{}", listener.local_addr()?);
+    let (closed_tx, closed_rx) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = [0; 4096];
+        let _ = socket.read(&mut request).await;
+        let head = "HTTP/1.1 200 OK\r\nContent-Length: 1073741824\r\n\r\n";
+        socket.write_all(head.as_bytes()).await.unwrap();
+        let chunk = vec![0u8; 64 * 1024];
+        let mut sent = 0;
+        while socket.write_all(&chunk).await.is_ok() {
+            sent += chunk.len();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let _ = closed_tx.send(sent);
+    });
+
+    let exec = LokiLogScanExec::try_new(
+        endpoint,
+        r#"{app="my-app"}"#.to_string(),
+        Some(1),
+        Some(2),
+        None,
+        Some(1),
+    )?;
+    let ctx = SessionContext::new();
+    let mut stream = exec.execute(0, ctx.task_ctx())?;
+    let next = tokio::time::timeout(Duration::from_millis(200), stream.next()).await;
+    assert!(
+        next.is_err(),
+        "the response body should still be downloading"
+    );
+    drop(stream);
+
+    let sent = tokio::time::timeout(Duration::from_secs(5), closed_rx).await??;
+    assert!(sent < 1 << 30);
+    Ok(())
+}
+
+#[tokio::test]
+async fn exists_subquery_fetches_single_row() -> Result<(), Box<dyn std::error::Error>> {
+    let batch = log_batch(&[
+        (1_000_000_000, &[("app", "my-app1")], "this is aaa log"),
+        (2_000_000_000, &[("app", "my-app1")], "this is bbb log"),
+    ]);
+    let mock = MockLoki::start(move |_| MockResponse::parquet(std::slice::from_ref(&batch))).await;
+
+    let state = SessionStateBuilder::new()
+        .with_default_features()
+        .with_physical_optimizer_rule(Arc::new(LokiExistsLimit::new()))
+        .build();
+    let ctx = SessionContext::new_with_state(state);
+    ctx.register_table("loki", Arc::new(LokiLogTable::try_new(mock.endpoint())?))?;
+
+    let batches = ctx
+        .sql("select 'yes' as found where exists (select 1 from loki where labels['app'] = 'my-app1')")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(
+        pretty_format_batches(&batches)?.to_string(),
+        r#"+-------+
+| found |
++-------+
+| yes   |
++-------+"#
+    );
+
+    let requests = mock.requests_to("/loki/api/v1/query_range");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(
+        requests[0].query_param("query"),
+        Some(r#"{app="my-app1"} "#)
+    );
+    assert_eq!(requests[0].query_param("limit"), Some("1"));
+
+    let batches = ctx
+        .sql("select 'no' as missing where not exists (select 1 from loki where labels['app'] = 'my-app1')")
+        .await?
+        .collect()
+        .await?;
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 0);
+    let requests = mock.requests_to("/loki/api/v1/query_range");
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].query_param("limit"), Some("1"));
+    Ok(())
+}
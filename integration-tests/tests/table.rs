@@ -95,6 +95,16 @@ async fn line_filter() -> Result<(), Box<dyn std::error::Error>> {
 ++"#,
     )
     .await?;
+
+    assert_loki_output(
+        "select * from loki where line ilike '%BBB%'",
+        r#"+----------------------------------------------------------------+-----------------+
+| labels                                                         | line            |
++----------------------------------------------------------------+-----------------+
+| {app: my-app2, detected_level: unknown, service_name: my-app2} | this is bbb log |
++----------------------------------------------------------------+-----------------+"#,
+    )
+    .await?;
     Ok(())
 }
 